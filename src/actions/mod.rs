@@ -0,0 +1,482 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use bevy::{input::InputSystems, platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// where the player's bindings are kept, relative to the working directory
+static BINDINGS_PATH: &str = "config/bindings.ron";
+// how far an action's value has to go before it counts as pressed
+static PRESS_THRESHOLD: f32 = 0.5;
+// how far a stick has to be pushed before it does anything
+static AXIS_DEADZONE: f32 = 0.2;
+
+pub struct ActionsPlugin;
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .insert_resource(InputBindings::load_or_default(BINDINGS_PATH))
+        .insert_resource(BindingsFile(BINDINGS_PATH.into()))
+        .init_resource::<Actions>()
+        .add_systems(PreUpdate, (
+            update_actions,
+            capture_rebinding.run_if(resource_exists::<Rebinding>),
+        ).chain().after(InputSystems));
+    }
+}
+
+// what the player can do, whichever key, button or stick it is bound to
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Reflect, Serialize, Deserialize)]
+pub enum Action {
+    // ride the cable onwards, or take the selected cable at a junction
+    Forward,
+    // ride the cable back, or turn around at a junction
+    Back,
+    // select the cable to the left (negative) or to the right (positive) at a junction
+    ChooseBranch,
+    Arc,
+    // switch the closest breaker
+    Switch,
+    Boost,
+    // finish writing the current text, or show the next one
+    Advance,
+    // drop every text still to be shown
+    Skip,
+    // while held, every text is written at once and advanced right away
+    FastForward,
+    // zoom the camera in (positive) or out (negative)
+    Zoom,
+    // turn the camera around the spark while orbiting
+    Orbit,
+    // switch the camera between following and orbiting the spark
+    Camera,
+    // open or close the log of texts already shown
+    Backlog,
+    // scroll the backlog down (positive) or up (negative)
+    Scroll,
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::Forward, Action::Back, Action::ChooseBranch, Action::Arc,
+        Action::Switch, Action::Boost, Action::Advance, Action::Skip, Action::FastForward,
+        Action::Zoom, Action::Orbit, Action::Camera, Action::Backlog, Action::Scroll,
+    ];
+
+    // the value of most actions goes from 0 to 1, these go both ways
+    pub fn is_bidirectional(&self) -> bool {
+        matches!(self, Action::ChooseBranch | Action::Zoom | Action::Orbit | Action::Scroll)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum InputSource {
+    Key(KeyCode),
+    Button(GamepadButton),
+    Axis(GamepadAxis),
+}
+
+/*
+one input driving an action. the input's value, 0 or 1 for keys and buttons and -1 to 1 for axes,
+is multiplied by the scale, so e.g. the stick pushed down drives Back with a scale of -1.
+*/
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Binding {
+    pub source: InputSource,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 { 1.0 }
+
+impl Binding {
+    pub fn key(key: KeyCode) -> Self {
+        Binding { source: InputSource::Key(key), scale: 1.0 }
+    }
+
+    pub fn button(button: GamepadButton) -> Self {
+        Binding { source: InputSource::Button(button), scale: 1.0 }
+    }
+
+    pub fn axis(axis: GamepadAxis, scale: f32) -> Self {
+        Binding { source: InputSource::Axis(axis), scale }
+    }
+
+    pub fn with_scale(self, scale: f32) -> Self {
+        Binding { scale, ..self }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BindingsError {
+    #[error("could not access bindings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse bindings file at {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write bindings: {0}")]
+    Write(#[from] ron::Error),
+}
+
+/*
+the keys, buttons and sticks bound to each action. one input may drive several actions.
+loaded from the bindings file at startup, falling back to the default bindings for actions it does not mention,
+and saved back to it whenever the player rebinds an action.
+*/
+#[derive(Resource, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct InputBindings {
+    actions: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use Action::*;
+        let actions = BTreeMap::from([
+            (Forward, vec![Binding::key(KeyCode::KeyW), Binding::button(GamepadButton::DPadUp), Binding::axis(GamepadAxis::LeftStickY, 1.0)]),
+            (Back, vec![Binding::key(KeyCode::KeyS), Binding::button(GamepadButton::DPadDown), Binding::axis(GamepadAxis::LeftStickY, -1.0)]),
+            (ChooseBranch, vec![
+                Binding::key(KeyCode::KeyA).with_scale(-1.0), Binding::key(KeyCode::KeyD),
+                Binding::button(GamepadButton::DPadLeft).with_scale(-1.0), Binding::button(GamepadButton::DPadRight),
+                Binding::axis(GamepadAxis::LeftStickX, 1.0),
+            ]),
+            (Arc, vec![Binding::key(KeyCode::KeyE), Binding::button(GamepadButton::West)]),
+            (Switch, vec![Binding::key(KeyCode::KeyF), Binding::button(GamepadButton::North)]),
+            (Boost, vec![Binding::key(KeyCode::ShiftLeft), Binding::button(GamepadButton::RightTrigger2)]),
+            (Advance, vec![Binding::key(KeyCode::KeyZ), Binding::button(GamepadButton::South)]),
+            (Skip, vec![Binding::key(KeyCode::KeyX), Binding::button(GamepadButton::East)]),
+            (FastForward, vec![Binding::key(KeyCode::ControlLeft), Binding::button(GamepadButton::LeftTrigger2)]),
+            (Zoom, vec![Binding::key(KeyCode::Equal), Binding::key(KeyCode::Minus).with_scale(-1.0), Binding::axis(GamepadAxis::RightStickY, 1.0)]),
+            (Orbit, vec![Binding::key(KeyCode::ArrowLeft).with_scale(-1.0), Binding::key(KeyCode::ArrowRight), Binding::axis(GamepadAxis::RightStickX, 1.0)]),
+            (Camera, vec![Binding::key(KeyCode::KeyC), Binding::button(GamepadButton::RightThumb)]),
+            (Backlog, vec![Binding::key(KeyCode::Tab), Binding::button(GamepadButton::Select)]),
+            (Scroll, vec![
+                Binding::key(KeyCode::PageDown), Binding::key(KeyCode::PageUp).with_scale(-1.0),
+                Binding::button(GamepadButton::RightTrigger), Binding::button(GamepadButton::LeftTrigger).with_scale(-1.0),
+            ]),
+        ]);
+        InputBindings { actions }
+    }
+}
+
+impl InputBindings {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.actions.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, source: InputSource) {
+        if let Some(bindings) = self.actions.get_mut(&action) {
+            bindings.retain(|binding| binding.source != source);
+        }
+    }
+
+    // replace an input of the action with another one, keeping its scale. binds the new input if the old one was not bound.
+    pub fn rebind(&mut self, action: Action, old: InputSource, new: InputSource) {
+        let bindings = self.actions.entry(action).or_default();
+        match bindings.iter_mut().find(|binding| binding.source == old) {
+            Some(binding) => binding.source = new,
+            None => bindings.push(Binding { source: new, scale: 1.0 }),
+        }
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, BindingsError> {
+        let mut bindings = InputBindings::default();
+        let loaded: InputBindings = ron::de::from_str(text)?;
+        bindings.actions.extend(loaded.actions);
+        Ok(bindings)
+    }
+
+    pub fn to_ron(&self) -> Result<String, BindingsError> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindingsError> {
+        InputBindings::from_ron(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BindingsError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+
+    // the default bindings if there is no bindings file yet, or it cannot be read
+    fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            return InputBindings::default();
+        }
+        InputBindings::load(path).unwrap_or_else(|error| {
+            warn!("using default bindings, {}", error);
+            InputBindings::default()
+        })
+    }
+
+    // the value of the action from the values of the inputs bound to it
+    fn value(&self, action: Action, source_value: impl Fn(InputSource) -> f32) -> f32 {
+        let value: f32 = self.bindings(action).iter()
+            .map(|binding| source_value(binding.source) * binding.scale)
+            .sum();
+        match action.is_bidirectional() {
+            true => value.clamp(-1.0, 1.0),
+            false => value.clamp(0.0, 1.0),
+        }
+    }
+}
+
+// the actions of one device, this frame and the frame before
+#[derive(Clone, Default, Debug)]
+pub struct ActionState {
+    values: HashMap<Action, f32>,
+    previous: HashMap<Action, f32>,
+}
+
+impl ActionState {
+    pub fn value(&self, action: Action) -> f32 {
+        self.values.get(&action).copied().unwrap_or(0.0)
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.value(action).abs() >= PRESS_THRESHOLD
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.pressed(action) && self.previous.get(&action).is_none_or(|previous| previous.abs() < PRESS_THRESHOLD)
+    }
+
+    // whether the action was just pressed, making it look held down to everyone asking after this
+    pub fn consume(&mut self, action: Action) -> bool {
+        let just_pressed = self.just_pressed(action);
+        self.previous.insert(action, self.value(action));
+        just_pressed
+    }
+
+    fn update(&mut self, value: impl Fn(Action) -> f32) {
+        self.previous = std::mem::take(&mut self.values);
+        self.values = Action::ALL.iter().map(|action| (*action, value(*action))).collect();
+    }
+}
+
+// the actions of the keyboard and of every connected gamepad
+#[derive(Resource, Default, Debug)]
+pub struct Actions {
+    pub keyboard: ActionState,
+    pub gamepads: HashMap<Entity, ActionState>,
+}
+
+impl Actions {
+    pub fn gamepad(&self, gamepad: Entity) -> Option<&ActionState> {
+        self.gamepads.get(&gamepad)
+    }
+
+    fn devices(&self) -> impl Iterator<Item = &ActionState> {
+        std::iter::once(&self.keyboard).chain(self.gamepads.values())
+    }
+
+    // for actions that do not belong to a spark, like advancing text: any device will do
+    pub fn just_pressed_any(&self, action: Action) -> bool {
+        self.devices().any(|state| state.just_pressed(action))
+    }
+
+    pub fn pressed_any(&self, action: Action) -> bool {
+        self.devices().any(|state| state.pressed(action))
+    }
+
+    // the value of whichever device pushes the action furthest
+    pub fn value_any(&self, action: Action) -> f32 {
+        self.devices().map(|state| state.value(action)).fold(0.0, |furthest, value| match value.abs() > furthest.abs() {
+                true => value,
+                false => furthest,
+            })
+    }
+
+    pub fn consume_any(&mut self, action: Action) -> bool {
+        let mut consumed = false;
+        for state in std::iter::once(&mut self.keyboard).chain(self.gamepads.values_mut()) {
+            consumed |= state.consume(action);
+        }
+        consumed
+    }
+}
+
+fn gamepad_value(gamepad: &Gamepad, source: InputSource) -> f32 {
+    match source {
+        InputSource::Key(_) => 0.0,
+        InputSource::Button(button) => match gamepad.pressed(button) {
+            true => 1.0,
+            false => 0.0,
+        },
+        InputSource::Axis(axis) => {
+            let value = gamepad.get(axis).unwrap_or(0.0);
+            match value.abs() > AXIS_DEADZONE {
+                true => value.clamp(-1.0, 1.0),
+                false => 0.0,
+            }
+        },
+    }
+}
+
+fn update_actions(
+    mut actions: ResMut<Actions>,
+    bindings: Res<InputBindings>,
+    keyboard: Option<Res<ButtonInput<KeyCode>>>,
+    gamepads: Query<(Entity, &Gamepad)>,
+) {
+    let key_value = |source| match (source, keyboard.as_ref()) {
+        (InputSource::Key(key), Some(keyboard)) if keyboard.pressed(key) => 1.0,
+        _ => 0.0,
+    };
+    actions.keyboard.update(|action| bindings.value(action, key_value));
+
+    actions.gamepads.retain(|entity, _| gamepads.contains(*entity));
+    for (entity, gamepad) in gamepads {
+        actions.gamepads.entry(entity).or_default()
+            .update(|action| bindings.value(action, |source| gamepad_value(gamepad, source)));
+    }
+}
+
+// where rebound bindings are saved, nowhere if missing
+#[derive(Resource, Clone, Debug)]
+pub struct BindingsFile(pub PathBuf);
+
+/*
+while present, the next key or gamepad button pressed is bound to the action instead of doing anything,
+replacing the given input if there is one. escape cancels. the new bindings are saved to the bindings file right away.
+*/
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Rebinding {
+    pub action: Action,
+    pub replace: Option<InputSource>,
+}
+
+// triggered once an action got a new input
+#[derive(Event, Debug)]
+pub struct ActionRebound {
+    pub action: Action,
+    pub source: InputSource,
+}
+
+fn capture_rebinding(
+    mut commands: Commands,
+    rebinding: Res<Rebinding>,
+    mut bindings: ResMut<InputBindings>,
+    mut actions: ResMut<Actions>,
+    file: Option<Res<BindingsFile>>,
+    keyboard: Option<Res<ButtonInput<KeyCode>>>,
+    gamepads: Query<&Gamepad>,
+) {
+    let key = keyboard.as_ref().and_then(|keyboard| keyboard.get_just_pressed().next().copied());
+    if key == Some(KeyCode::Escape) {
+        commands.remove_resource::<Rebinding>();
+        return;
+    }
+    let source = key.map(InputSource::Key)
+        .or_else(|| gamepads.iter().find_map(|gamepad| gamepad.get_just_pressed().next().copied()).map(InputSource::Button));
+    let Some(source) = source else { return };
+
+    match rebinding.replace {
+        Some(old) => bindings.rebind(rebinding.action, old, source),
+        None => bindings.bind(rebinding.action, Binding { source, scale: 1.0 }),
+    }
+    info!("bound {:?} to {:?}", source, rebinding.action);
+    if let Some(file) = file
+        && let Err(error) = bindings.save(&file.0) {
+        warn!("could not save bindings, {}", error);
+    }
+    // the press that was captured does not count as an action
+    for action in Action::ALL {
+        actions.consume_any(action);
+    }
+    commands.remove_resource::<Rebinding>();
+    commands.trigger(ActionRebound { action: rebinding.action, source });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
+    fn actions_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<InputBindings>();
+        world.init_resource::<Actions>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world
+    }
+
+    fn press(world: &mut World, key: KeyCode) {
+        let mut keyboard = world.resource_mut::<ButtonInput<KeyCode>>();
+        keyboard.clear();
+        keyboard.press(key);
+        world.run_system_once(update_actions).unwrap();
+    }
+
+    // -- basic --
+    // keys drive their actions, and a held key is only just pressed on the first frame
+    #[test]
+    fn test_keyboard_actions() {
+        let mut world = actions_world();
+        press(&mut world, KeyCode::KeyA);
+        let keyboard = &world.resource::<Actions>().keyboard;
+        assert_eq!(keyboard.value(Action::ChooseBranch), -1.0);
+        assert!(keyboard.just_pressed(Action::ChooseBranch) && !keyboard.pressed(Action::Forward));
+
+        world.run_system_once(update_actions).unwrap();
+        let keyboard = &world.resource::<Actions>().keyboard;
+        assert!(keyboard.pressed(Action::ChooseBranch) && !keyboard.just_pressed(Action::ChooseBranch));
+    }
+
+    // the stick drives forward and back by how far it is pushed, and nothing inside the deadzone
+    #[test]
+    fn test_gamepad_axis() {
+        let mut world = actions_world();
+        let mut gamepad = Gamepad::default();
+        gamepad.analog_mut().set(GamepadAxis::LeftStickY, -0.6);
+        gamepad.analog_mut().set(GamepadAxis::LeftStickX, 0.1);
+        let gamepad = world.spawn(gamepad).id();
+        world.run_system_once(update_actions).unwrap();
+
+        let state = world.resource::<Actions>().gamepad(gamepad).unwrap().clone();
+        assert_eq!((state.value(Action::Forward), state.value(Action::Back)), (0.0, 0.6));
+        assert_eq!(state.value(Action::ChooseBranch), 0.0);
+        assert!(world.resource::<Actions>().keyboard.value(Action::Back) == 0.0);
+    }
+
+    // bindings survive a trip through ron, and actions missing from the file keep their default bindings
+    #[test]
+    fn test_bindings_ron() {
+        let mut bindings = InputBindings::default();
+        bindings.rebind(Action::Arc, InputSource::Key(KeyCode::KeyE), InputSource::Key(KeyCode::Space));
+        assert_eq!(InputBindings::from_ron(&bindings.to_ron().unwrap()).unwrap(), bindings);
+
+        let partial = InputBindings::from_ron("(actions: { Skip: [(source: Key(Backspace))] })").unwrap();
+        assert_eq!(partial.bindings(Action::Skip), &[Binding::key(KeyCode::Backspace)]);
+        assert_eq!(partial.bindings(Action::Forward), InputBindings::default().bindings(Action::Forward));
+        assert!(InputBindings::from_ron("(actions: { Fly: [] })").is_err());
+    }
+
+    // -- edge cases --
+    // rebinding keeps the scale of the replaced input, and the press it captured does nothing else
+    #[test]
+    fn test_rebind() {
+        let mut world = actions_world();
+        world.resource_mut::<InputBindings>().bind(Action::Arc, Binding::key(KeyCode::KeyQ));
+        world.insert_resource(Rebinding { action: Action::ChooseBranch, replace: Some(InputSource::Key(KeyCode::KeyA)) });
+        press(&mut world, KeyCode::KeyQ);
+        world.run_system_once(capture_rebinding).unwrap();
+
+        let bindings = world.resource::<InputBindings>().bindings(Action::ChooseBranch);
+        assert!(bindings.contains(&Binding::key(KeyCode::KeyQ).with_scale(-1.0)));
+        assert!(!bindings.iter().any(|binding| binding.source == InputSource::Key(KeyCode::KeyA)));
+        assert!(!world.resource::<Actions>().keyboard.just_pressed(Action::Arc));
+        assert!(!world.contains_resource::<Rebinding>());
+    }
+}
@@ -0,0 +1,424 @@
+use bevy::{camera::ScalingMode, platform::collections::HashMap, prelude::*};
+
+use crate::{
+    actions::*,
+    electric_grid::{cables::Cable, spark_movement::{move_spark, Spark}},
+    terrain::Heightfield,
+};
+
+// how far the camera stays above the ground, in metres
+static GROUND_CLEARANCE: f32 = 5.0;
+// how fast zooming changes the scale, by a factor of e per second
+static ZOOM_SPEED: f32 = 1.5;
+// radians per second
+static ORBIT_SPEED: f32 = 1.5;
+
+pub struct CameraRigPlugin;
+impl Plugin for CameraRigPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<CameraShots>()
+        .add_observer(cue_camera_shot)
+        .add_systems(Update, (control_camera_rig, enter_camera_regions, update_camera_rig).chain().after(move_spark));
+    }
+}
+
+// a fixed camera position, looking at a fixed point
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CameraShot {
+    pub position: Vec3,
+    pub look_at: Vec3,
+    // orthographic scale during the shot, the player's zoom if None
+    pub scale: Option<f32>,
+}
+
+impl CameraShot {
+    fn transform(&self) -> Transform {
+        Transform::from_translation(self.position).looking_at(self.look_at, Vec3::Y)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CameraMode {
+    // trail the spark at the rig's offset
+    Follow,
+    // circle the spark, turned by the player
+    Orbit { yaw: f32, pitch: f32, distance: f32 },
+    Shot(CameraShot),
+}
+
+// blending from where the camera was when its mode changed to where the new mode wants it
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    from: Transform,
+    from_scale: f32,
+    elapsed: f32,
+    duration: f32,
+}
+
+/*
+a camera following a spark. it looks a little ahead of the spark along its cable, in the direction it moves,
+and stays above the terrain. the player can zoom, and switch to orbiting the spark.
+cinematic shots take over the camera while the spark is in a CameraRegion, or when cued with CueCameraShot,
+and every change of mode blends over the transition time.
+*/
+#[derive(Component, Clone, Debug)]
+#[require(Camera3d, Projection = default_projection())]
+pub struct CameraRig {
+    pub target: Entity,
+    pub mode: CameraMode,
+    // where the camera sits relative to the point it looks at while following
+    pub offset: Vec3,
+    // how far above the spark the camera looks
+    pub focus_height: f32,
+    // how far ahead of the spark along its cable the camera looks, in metres
+    pub look_ahead: f32,
+    // how quickly the camera catches up with the spark, per second
+    pub smoothing: f32,
+    // orthographic scale the player zoomed to, and how far they may zoom
+    pub zoom: f32,
+    pub zoom_range: (f32, f32),
+    // seconds
+    pub transition_time: f32,
+    transition: Option<Transition>,
+    // where the camera goes back to when a shot ends
+    resume: CameraMode,
+    // the region whose shot is playing
+    region: Option<Entity>,
+    // which way the spark is moving along its cable, and where it was last frame
+    heading: Vec3,
+    last_target: Option<Vec3>,
+}
+
+fn default_projection() -> Projection {
+    Projection::Orthographic(OrthographicProjection {
+        scaling_mode: ScalingMode::WindowSize,
+        scale: 0.15,
+        ..OrthographicProjection::default_3d()
+    })
+}
+
+impl CameraRig {
+    pub fn following(target: Entity) -> Self {
+        CameraRig {
+            target,
+            mode: CameraMode::Follow,
+            offset: Vec3::new(100.0, 10.0, -100.0),
+            focus_height: 20.0,
+            look_ahead: 15.0,
+            smoothing: 4.0,
+            zoom: 0.15,
+            zoom_range: (0.05, 0.5),
+            transition_time: 1.5,
+            transition: None,
+            resume: CameraMode::Follow,
+            region: None,
+            heading: Vec3::ZERO,
+            last_target: None,
+        }
+    }
+
+    // switch to another mode, blending over from the camera's current view. shots remember the mode to resume after them.
+    pub fn set_mode(&mut self, mode: CameraMode, current: Transform, current_scale: f32) {
+        if !matches!(self.mode, CameraMode::Shot(_)) {
+            self.resume = self.mode;
+        }
+        self.mode = mode;
+        self.transition = Some(Transition { from: current, from_scale: current_scale, elapsed: 0.0, duration: self.transition_time });
+    }
+
+    // go back to following or orbiting after a shot
+    pub fn end_shot(&mut self, current: Transform, current_scale: f32) {
+        if matches!(self.mode, CameraMode::Shot(_)) {
+            self.set_mode(self.resume, current, current_scale);
+        }
+    }
+
+    fn focus(&self, target: Vec3) -> Vec3 {
+        target + self.heading * self.look_ahead + Vec3::Y * self.focus_height
+    }
+
+    // where the camera wants to be, given where the spark is and the height of the ground under the camera
+    pub fn desired_transform(&self, target: Vec3, ground: impl Fn(Vec3) -> Option<f32>) -> Transform {
+        let focus = self.focus(target);
+        let mut position = match self.mode {
+            CameraMode::Follow => focus + self.offset,
+            CameraMode::Orbit { yaw, pitch, distance } => focus + Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0) * Vec3::Z * distance,
+            CameraMode::Shot(shot) => return shot.transform(),
+        };
+        if let Some(ground) = ground(position) {
+            position.y = position.y.max(ground + GROUND_CLEARANCE);
+        }
+        Transform::from_translation(position).looking_at(focus, Vec3::Y)
+    }
+
+    pub fn desired_scale(&self) -> f32 {
+        match self.mode {
+            CameraMode::Shot(CameraShot { scale: Some(scale), .. }) => scale,
+            _ => self.zoom,
+        }
+    }
+}
+
+// orbiting from the follow offset, so switching does not move the camera
+fn orbit_from_offset(offset: Vec3) -> CameraMode {
+    let distance = offset.length();
+    CameraMode::Orbit {
+        yaw: offset.x.atan2(offset.z),
+        pitch: -(offset.y / distance).asin(),
+        distance,
+    }
+}
+
+// zoom in for positive input by shrinking the scale, within the range
+pub fn zoomed(scale: f32, input: f32, dt: f32, range: (f32, f32)) -> f32 {
+    (scale * (-input * ZOOM_SPEED * dt).exp()).clamp(range.0, range.1)
+}
+
+// shots cued by name, e.g. from dialogue
+#[derive(Resource, Default, Debug)]
+pub struct CameraShots(pub HashMap<String, CameraShot>);
+
+// cut to the named shot, or end the current shot if None
+#[derive(Event, Debug)]
+pub struct CueCameraShot(pub Option<String>);
+
+// the rig's shot plays while the spark it follows is inside this box
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CameraRegion {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub shot: CameraShot,
+}
+
+impl CameraRegion {
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
+
+fn projection_scale(projection: &Projection) -> f32 {
+    match projection {
+        Projection::Orthographic(orthographic) => orthographic.scale,
+        _ => 1.0,
+    }
+}
+
+fn cue_camera_shot(
+    trigger: On<CueCameraShot>,
+    mut rigs: Query<(&mut CameraRig, &Transform, &Projection)>,
+    shots: Res<CameraShots>,
+) {
+    for (mut rig, transform, projection) in &mut rigs {
+        match &trigger.0 {
+            Some(name) => {
+                let Some(shot) = shots.0.get(name) else {
+                    warn!("no camera shot called {:?}", name);
+                    return;
+                };
+                rig.set_mode(CameraMode::Shot(*shot), *transform, projection_scale(projection));
+            },
+            None => rig.end_shot(*transform, projection_scale(projection)),
+        }
+    }
+}
+
+fn control_camera_rig(
+    mut rigs: Query<(&mut CameraRig, &Transform, &Projection)>,
+    actions: Option<Res<Actions>>,
+    time: Res<Time>,
+) {
+    let Some(actions) = actions else { return };
+    let dt = time.delta_secs();
+    for (mut rig, transform, projection) in &mut rigs {
+        rig.zoom = zoomed(rig.zoom, actions.value_any(Action::Zoom), dt, rig.zoom_range);
+
+        if actions.just_pressed_any(Action::Camera) {
+            let mode = match rig.mode {
+                CameraMode::Follow => orbit_from_offset(rig.offset),
+                CameraMode::Orbit { .. } => CameraMode::Follow,
+                // shots are not the player's to leave
+                CameraMode::Shot(_) => continue,
+            };
+            rig.set_mode(mode, *transform, projection_scale(projection));
+        }
+        if let CameraMode::Orbit { yaw, .. } = &mut rig.mode {
+            *yaw += actions.value_any(Action::Orbit) * ORBIT_SPEED * dt;
+        }
+    }
+}
+
+fn enter_camera_regions(
+    mut rigs: Query<(&mut CameraRig, &Transform, &Projection)>,
+    targets: Query<&Transform, (With<Spark>, Without<CameraRig>)>,
+    regions: Query<(Entity, &CameraRegion)>,
+) {
+    for (mut rig, transform, projection) in &mut rigs {
+        let Ok(target) = targets.get(rig.target) else { continue };
+        let inside = regions.iter().find(|(_, region)| region.contains(target.translation));
+        if inside.map(|(entity, _)| entity) == rig.region { continue; }
+        let scale = projection_scale(projection);
+        match inside {
+            Some((entity, region)) => {
+                debug!("camera entered region {}", entity);
+                rig.set_mode(CameraMode::Shot(region.shot), *transform, scale);
+            },
+            None => rig.end_shot(*transform, scale),
+        }
+        rig.region = inside.map(|(entity, _)| entity);
+    }
+}
+
+fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn update_camera_rig(
+    mut rigs: Query<(&mut CameraRig, &mut Transform, &mut Projection)>,
+    targets: Query<(&Spark, &Transform), Without<CameraRig>>,
+    cables: Query<&Cable>,
+    heightfield: Option<Res<Heightfield>>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (mut rig, mut transform, mut projection) in &mut rigs {
+        let Ok((spark, target)) = targets.get(rig.target) else { continue };
+        let target = target.translation;
+
+        // look ahead along the cable the way the spark is moving, keeping the last heading while it stands still
+        let motion = target - rig.last_target.unwrap_or(target);
+        if motion.length_squared() > f32::EPSILON
+            && let Ok(cable) = cables.get(spark.connected_to_cable_entity)
+            && let Some(tangent) = cable.tangent_at_distance(spark.dist_along) {
+            rig.heading = *tangent * motion.dot(*tangent).signum();
+        }
+        rig.last_target = Some(target);
+
+        let desired = rig.desired_transform(target, |position| {
+            heightfield.as_ref().and_then(|heightfield| heightfield.height_at(position.xz()))
+        });
+        let desired_scale = rig.desired_scale();
+
+        let (new_transform, scale) = match rig.transition.as_mut() {
+            Some(transition) => {
+                transition.elapsed += dt;
+                let t = smoothstep(transition.elapsed / transition.duration.max(f32::EPSILON));
+                let blended = Transform {
+                    translation: transition.from.translation.lerp(desired.translation, t),
+                    rotation: transition.from.rotation.slerp(desired.rotation, t),
+                    ..*transform
+                };
+                let scale = transition.from_scale.lerp(desired_scale, t);
+                if transition.elapsed >= transition.duration {
+                    rig.transition = None;
+                }
+                (blended, scale)
+            },
+            None => match rig.mode {
+                CameraMode::Shot(_) => (desired, desired_scale),
+                _ => {
+                    let t = 1.0 - (-rig.smoothing * dt).exp();
+                    let smoothed = Transform {
+                        translation: transform.translation.lerp(desired.translation, t),
+                        rotation: transform.rotation.slerp(desired.rotation, t),
+                        ..*transform
+                    };
+                    (smoothed, desired_scale)
+                },
+            },
+        };
+        *transform = new_transform;
+        if let Projection::Orthographic(orthographic) = projection.as_mut() {
+            orthographic.scale = scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
+    fn spawn_rig(world: &mut World) -> (Entity, Entity) {
+        world.init_resource::<CameraShots>();
+        let mut time = Time::<()>::default();
+        time.advance_by(std::time::Duration::from_secs_f32(0.5));
+        world.insert_resource(time);
+        let mut cable = Cable::default();
+        cable.set_segments(vec![Vec3::ZERO, Vec3::X * 100.0]);
+        let cable = world.spawn(cable).id();
+        let spark = world.spawn((Spark::new(cable, 1.0), Transform::default())).id();
+        let camera = world.spawn((CameraRig::following(spark), Transform::default())).id();
+        (spark, camera)
+    }
+
+    fn run_rig(world: &mut World) {
+        world.run_system_once(enter_camera_regions).unwrap();
+        world.run_system_once(update_camera_rig).unwrap();
+    }
+
+    // -- basic --
+    // the camera looks ahead of the spark the way it moves along its cable, and never dips under the terrain
+    #[test]
+    fn test_follow_looks_ahead() {
+        let mut rig = CameraRig::following(Entity::PLACEHOLDER);
+        rig.heading = Vec3::NEG_X;
+        let desired = rig.desired_transform(Vec3::ZERO, |_| Some(100.0));
+        assert_eq!(desired.translation, Vec3::new(85.0, 105.0, -100.0));
+        assert!(desired.forward().dot((Vec3::new(-15.0, 20.0, 0.0) - desired.translation).normalize()) > 0.999);
+
+        let CameraMode::Orbit { yaw, pitch, distance } = orbit_from_offset(rig.offset) else { panic!() };
+        let orbit = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0) * Vec3::Z * distance;
+        assert!(orbit.distance(rig.offset) < 0.001);
+    }
+
+    // a region's shot takes over the camera, blending in, and ends once the spark leaves the region
+    #[test]
+    fn test_region_shot() {
+        let mut world = World::new();
+        let (spark, camera) = spawn_rig(&mut world);
+        let shot = CameraShot { position: Vec3::new(0.0, 50.0, 50.0), look_at: Vec3::ZERO, scale: Some(0.3) };
+        world.spawn(CameraRegion { min: Vec3::splat(-10.0), max: Vec3::splat(10.0), shot });
+
+        run_rig(&mut world);
+        let halfway = world.get::<Transform>(camera).unwrap().translation;
+        assert!(halfway != Vec3::ZERO && halfway != shot.position);
+        for _ in 0..3 {
+            run_rig(&mut world);
+        }
+        assert_eq!(world.get::<Transform>(camera).unwrap().translation, shot.position);
+        let Projection::Orthographic(orthographic) = world.get::<Projection>(camera).unwrap() else { panic!() };
+        assert_eq!(orthographic.scale, 0.3);
+
+        world.get_mut::<Transform>(spark).unwrap().translation = Vec3::X * 50.0;
+        run_rig(&mut world);
+        assert_eq!(world.get::<CameraRig>(camera).unwrap().mode, CameraMode::Follow);
+    }
+
+    // shots cued by name play until released, after which the camera resumes what it was doing
+    #[test]
+    fn test_cued_shot() {
+        let mut app = App::new();
+        app.add_observer(cue_camera_shot);
+        let world = app.world_mut();
+        let (_, camera) = spawn_rig(world);
+        let shot = CameraShot { position: Vec3::Y * 80.0, look_at: Vec3::ZERO, scale: None };
+        world.resource_mut::<CameraShots>().0.insert("overview".into(), shot);
+        world.get_mut::<CameraRig>(camera).unwrap().mode = orbit_from_offset(Vec3::ONE);
+
+        world.trigger(CueCameraShot(Some("overview".into())));
+        assert_eq!(world.get::<CameraRig>(camera).unwrap().mode, CameraMode::Shot(shot));
+        world.trigger(CueCameraShot(None));
+        assert!(matches!(world.get::<CameraRig>(camera).unwrap().mode, CameraMode::Orbit { .. }));
+    }
+
+    // -- edge cases --
+    // zooming stays within the rig's range
+    #[test]
+    fn test_zoom_range() {
+        assert_eq!(zoomed(0.15, 1.0, 100.0, (0.05, 0.5)), 0.05);
+        assert_eq!(zoomed(0.15, -1.0, 100.0, (0.05, 0.5)), 0.5);
+        assert!(zoomed(0.15, 1.0, 0.1, (0.05, 0.5)) < 0.15);
+    }
+}
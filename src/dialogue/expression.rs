@@ -0,0 +1,264 @@
+use std::fmt;
+
+use bevy::platform::collections::HashMap;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Number(f32),
+    Bool(bool),
+    Text(String),
+}
+
+impl Value {
+    // what conditions see: false, zero and empty text are false, everything else is true
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0.0,
+            Value::Bool(boolean) => *boolean,
+            Value::Text(text) => !text.is_empty(),
+        }
+    }
+
+    fn number(&self) -> Result<f32, String> {
+        match self {
+            Value::Number(number) => Ok(*number),
+            other => Err(format!("{other} is not a number")),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Bool(boolean) => write!(f, "{boolean}"),
+            Value::Text(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+// variables dialogue can read and set, by name without the $
+pub type Variables = HashMap<String, Value>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Or, And,
+    Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual,
+    Add, Subtract, Multiply, Divide,
+}
+
+impl BinaryOp {
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal | BinaryOp::NotEqual => 3,
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 4,
+            BinaryOp::Add | BinaryOp::Subtract => 5,
+            BinaryOp::Multiply | BinaryOp::Divide => 6,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Literal(Value),
+    Variable(String),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // variables that were never set read as 0
+    pub fn eval(&self, variables: &Variables) -> Result<Value, String> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(name) => Ok(variables.get(name).cloned().unwrap_or(Value::Number(0.0))),
+            Expr::Not(expr) => Ok(Value::Bool(!expr.eval(variables)?.truthy())),
+            Expr::Negate(expr) => Ok(Value::Number(-expr.eval(variables)?.number()?)),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(variables)?;
+                // and/or only look at the right side when they have to
+                match (op, left.truthy()) {
+                    (BinaryOp::And, false) => return Ok(Value::Bool(false)),
+                    (BinaryOp::Or, true) => return Ok(Value::Bool(true)),
+                    _ => {},
+                }
+                let right = right.eval(variables)?;
+                Ok(match op {
+                    BinaryOp::And | BinaryOp::Or => Value::Bool(right.truthy()),
+                    BinaryOp::Equal => Value::Bool(left == right),
+                    BinaryOp::NotEqual => Value::Bool(left != right),
+                    BinaryOp::Less => Value::Bool(left.number()? < right.number()?),
+                    BinaryOp::LessEqual => Value::Bool(left.number()? <= right.number()?),
+                    BinaryOp::Greater => Value::Bool(left.number()? > right.number()?),
+                    BinaryOp::GreaterEqual => Value::Bool(left.number()? >= right.number()?),
+                    // adding text to anything joins them
+                    BinaryOp::Add => match (&left, &right) {
+                        (Value::Text(_), _) | (_, Value::Text(_)) => Value::Text(format!("{left}{right}")),
+                        _ => Value::Number(left.number()? + right.number()?),
+                    },
+                    BinaryOp::Subtract => Value::Number(left.number()? - right.number()?),
+                    BinaryOp::Multiply => Value::Number(left.number()? * right.number()?),
+                    BinaryOp::Divide => match right.number()? {
+                        0.0 => return Err("division by zero".into()),
+                        divisor => Value::Number(left.number()? / divisor),
+                    },
+                })
+            },
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = ExprParser { tokens, pos: 0 };
+        let expr = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(format!("unexpected {token:?} in expression")),
+            None => Ok(expr),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(f32),
+    Text(String),
+    Variable(String),
+    Word(String),
+    Symbol(&'static str),
+}
+
+static SYMBOLS: [&str; 16] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "(", ")", "!", "="];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+            let number = rest[..len].parse().map_err(|_| format!("bad number {:?}", &rest[..len]))?;
+            (Token::Number(number), len)
+        } else if c == '"' {
+            let end = rest[1..].find('"').ok_or("unterminated text")?;
+            (Token::Text(rest[1..end + 1].to_string()), end + 2)
+        } else if c == '$' || c.is_alphabetic() || c == '_' {
+            let start = (c == '$') as usize;
+            let len = rest[start..].find(|c: char| !c.is_alphanumeric() && c != '_').map_or(rest.len(), |len| len + start);
+            match c == '$' {
+                true => (Token::Variable(rest[1..len].to_string()), len),
+                false => (Token::Word(rest[..len].to_string()), len),
+            }
+        } else {
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)).ok_or_else(|| format!("unexpected {c:?} in expression"))?;
+            (Token::Symbol(symbol), symbol.len())
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        Some(match self.tokens.get(self.pos)? {
+            Token::Symbol("||") => BinaryOp::Or,
+            Token::Symbol("&&") => BinaryOp::And,
+            Token::Word(word) if word == "or" => BinaryOp::Or,
+            Token::Word(word) if word == "and" => BinaryOp::And,
+            Token::Word(word) if word == "is" => BinaryOp::Equal,
+            Token::Symbol("==") => BinaryOp::Equal,
+            Token::Symbol("!=") => BinaryOp::NotEqual,
+            Token::Symbol("<") => BinaryOp::Less,
+            Token::Symbol("<=") => BinaryOp::LessEqual,
+            Token::Symbol(">") => BinaryOp::Greater,
+            Token::Symbol(">=") => BinaryOp::GreaterEqual,
+            Token::Symbol("+") => BinaryOp::Add,
+            Token::Symbol("-") => BinaryOp::Subtract,
+            Token::Symbol("*") => BinaryOp::Multiply,
+            Token::Symbol("/") => BinaryOp::Divide,
+            _ => return None,
+        })
+    }
+
+    // precedence climbing, every operator is left associative
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.binary_op()
+            && op.precedence() > min_precedence {
+            self.pos += 1;
+            let right = self.binary(op.precedence())?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Literal(Value::Number(number))),
+            Some(Token::Text(text)) => Ok(Expr::Literal(Value::Text(text))),
+            Some(Token::Variable(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Word(word)) => match word.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "not" => Ok(Expr::Not(Box::new(self.unary()?))),
+                _ => Err(format!("unknown word {word:?} in expression, variables start with $")),
+            },
+            Some(Token::Symbol("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Symbol("-")) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Symbol("(")) => {
+                let expr = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Symbol(")")) => Ok(expr),
+                    _ => Err("missing )".into()),
+                }
+            },
+            Some(token) => Err(format!("unexpected {token:?} in expression")),
+            None => Err("expression ends too early".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, variables: &Variables) -> Value {
+        Expr::parse(source).unwrap().eval(variables).unwrap()
+    }
+
+    // -- basic --
+    // operators bind as usual, and comparisons read variables
+    #[test]
+    fn test_precedence() {
+        let variables = Variables::from_iter([("visits".to_string(), Value::Number(2.0))]);
+        assert_eq!(eval("1 + 2 * 3", &variables), Value::Number(7.0));
+        assert_eq!(eval("(1 + 2) * 3", &variables), Value::Number(9.0));
+        assert_eq!(eval("$visits > 1 and not $met", &variables), Value::Bool(true));
+        assert_eq!(eval("\"volts: \" + $visits", &variables), Value::Text("volts: 2".into()));
+        assert_eq!(eval("10 - 4 - 3", &variables), Value::Number(3.0));
+    }
+
+    // -- edge cases --
+    #[test]
+    fn test_errors() {
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("visits > 1").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("\"a\" - 1").unwrap().eval(&Variables::default()).is_err());
+        // the right side of a decided and is never evaluated
+        assert_eq!(eval("false and 1 / 0", &Variables::default()), Value::Bool(false));
+    }
+}
@@ -0,0 +1,376 @@
+use bevy::{asset::{io::Reader, AssetLoader, LoadContext}, prelude::*};
+use thiserror::Error;
+
+use crate::{
+    actions::*,
+    camera::CueCameraShot,
+    ui::{text_display, SkipAllText, TextHistory, TextQueue},
+};
+
+pub use expression::Variables;
+pub use runner::{DialogueRun, DialogueStep};
+pub use script::{DialogueError, DialogueScript};
+pub use triggers::{DialogueTrigger, DialogueTriggersPlugin, GridChange, TriggerAction, TriggerPolicy, TriggerShape};
+
+mod expression;
+mod runner;
+mod script;
+mod triggers;
+
+pub struct DialoguePlugin;
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_plugins(DialogueTriggersPlugin)
+        .init_asset::<DialogueAsset>()
+        .init_asset_loader::<DialogueLoader>()
+        .init_resource::<DialogueVariables>()
+        .init_resource::<DialogueRunner>()
+        .add_observer(start_dialogue)
+        .add_observer(camera_command)
+        .add_observer(skip_dialogue)
+        .add_systems(Update, (choose_dialogue_option, run_dialogue, show_dialogue_options).chain().before(text_display));
+    }
+}
+
+#[derive(Asset, TypePath, Debug)]
+pub struct DialogueAsset(pub DialogueScript);
+
+#[derive(Debug, Error)]
+pub enum DialogueLoadError {
+    #[error("could not read dialogue file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("dialogue file is not utf-8: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error("could not parse dialogue file at {0}")]
+    Parse(#[from] DialogueError),
+}
+
+#[derive(Default, TypePath)]
+pub struct DialogueLoader;
+
+impl AssetLoader for DialogueLoader {
+    type Asset = DialogueAsset;
+    type Settings = ();
+    type Error = DialogueLoadError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(DialogueAsset(DialogueScript::parse(&String::from_utf8(bytes)?)?))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dialogue"]
+    }
+}
+
+// variables dialogue reads and sets. they outlive the dialogue, so the game can set them too and later dialogue can check them.
+#[derive(Resource, Default, Debug)]
+pub struct DialogueVariables(pub Variables);
+
+// run a dialogue from the given node, replacing the one running
+#[derive(Event, Debug)]
+pub struct StartDialogue {
+    pub script: Handle<DialogueAsset>,
+    pub node: String,
+}
+
+/*
+triggered for every command in a dialogue that the runner does not handle itself, e.g. <<camera overview>>,
+with the words after the command's name as its arguments. the runner handles <<wait seconds>>.
+*/
+#[derive(Event, Debug)]
+pub struct DialogueCommand {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Event, Debug)]
+pub struct DialogueFinished;
+
+// options being offered, and the one the player has selected
+#[derive(Clone, PartialEq, Debug)]
+struct Choosing {
+    options: Vec<String>,
+    selected: usize,
+}
+
+#[derive(Debug)]
+struct ActiveDialogue {
+    script: Handle<DialogueAsset>,
+    node: String,
+    // started once the script is loaded
+    run: Option<DialogueRun>,
+    // seconds left of a <<wait>>
+    wait: f32,
+    choosing: Option<Choosing>,
+}
+
+/*
+runs one dialogue at a time, handing its lines to the text queue one by one.
+the next line is only queued once the text queue is done writing the last one, so commands happen in between lines,
+and options are offered once the line before them is written.
+*/
+#[derive(Resource, Default, Debug)]
+pub struct DialogueRunner {
+    active: Option<ActiveDialogue>,
+}
+
+impl DialogueRunner {
+    pub fn is_running(&self) -> bool {
+        self.active.is_some()
+    }
+
+    pub fn stop(&mut self) {
+        self.active = None;
+    }
+}
+
+fn start_dialogue(
+    trigger: On<StartDialogue>,
+    mut runner: ResMut<DialogueRunner>,
+) {
+    debug!("starting dialogue at node {:?}", trigger.node);
+    runner.active = Some(ActiveDialogue { script: trigger.script.clone(), node: trigger.node.clone(), run: None, wait: 0.0, choosing: None });
+}
+
+fn run_dialogue(
+    mut commands: Commands,
+    mut runner: ResMut<DialogueRunner>,
+    scripts: Res<Assets<DialogueAsset>>,
+    mut variables: ResMut<DialogueVariables>,
+    mut text_queue: ResMut<TextQueue>,
+    time: Res<Time>,
+) {
+    let Some(active) = runner.active.as_mut() else { return };
+    let Some(DialogueAsset(script)) = scripts.get(&active.script) else { return };
+    if active.run.is_none() {
+        active.run = DialogueRun::start(script, &active.node);
+        if active.run.is_none() {
+            warn!("dialogue has no node {:?}", active.node);
+            runner.active = None;
+            return;
+        }
+    }
+    active.wait -= time.delta_secs();
+    if active.wait > 0.0 || active.choosing.is_some() || !text_queue.is_idle() { return; }
+    let Some(run) = active.run.as_mut() else { return };
+
+    loop {
+        match run.step(script, &mut variables.0) {
+            DialogueStep::Line(text) => {
+                text_queue.push_text(&text);
+                return;
+            },
+            DialogueStep::Options(options) => {
+                active.choosing = Some(Choosing { options, selected: 0 });
+                return;
+            },
+            DialogueStep::Command(name, args) if name == "wait" => {
+                active.wait = args.first().and_then(|seconds| seconds.parse().ok()).unwrap_or(1.0);
+                return;
+            },
+            DialogueStep::Command(name, args) => commands.trigger(DialogueCommand { name, args }),
+            DialogueStep::End => break,
+        }
+    }
+    debug!("dialogue finished");
+    runner.active = None;
+    commands.trigger(DialogueFinished);
+}
+
+// skipping all text fast-forwards the dialogue to its next options or its end, running its commands on the way
+fn skip_dialogue(
+    _trigger: On<SkipAllText>,
+    mut commands: Commands,
+    mut runner: ResMut<DialogueRunner>,
+    scripts: Res<Assets<DialogueAsset>>,
+    mut variables: ResMut<DialogueVariables>,
+    mut history: ResMut<TextHistory>,
+    time: Res<Time>,
+) {
+    let Some(active) = runner.active.as_mut() else { return };
+    if active.choosing.is_some() { return; }
+    let Some(DialogueAsset(script)) = scripts.get(&active.script) else { return };
+    if active.run.is_none() {
+        active.run = DialogueRun::start(script, &active.node);
+    }
+    let Some(run) = active.run.as_mut() else { return };
+    active.wait = 0.0;
+
+    loop {
+        match run.step(script, &mut variables.0) {
+            // skipped lines are still kept in the history
+            DialogueStep::Line(text) => history.record(&text, time.elapsed_secs()),
+            DialogueStep::Options(options) => {
+                active.choosing = Some(Choosing { options, selected: 0 });
+                return;
+            },
+            DialogueStep::Command(name, _) if name == "wait" => {},
+            DialogueStep::Command(name, args) => commands.trigger(DialogueCommand { name, args }),
+            DialogueStep::End => break,
+        }
+    }
+    debug!("dialogue skipped to its end");
+    runner.active = None;
+    commands.trigger(DialogueFinished);
+}
+
+// the branch action moves the selection, advancing picks the selected option
+fn choose_dialogue_option(
+    mut runner: ResMut<DialogueRunner>,
+    actions: Option<Res<Actions>>,
+) {
+    let Some(actions) = actions else { return };
+    let Some(active) = runner.active.as_mut() else { return };
+    let Some(choosing) = active.choosing.as_mut() else { return };
+    if actions.just_pressed_any(Action::ChooseBranch) {
+        choosing.selected = match actions.value_any(Action::ChooseBranch) < 0.0 {
+            true => choosing.selected.saturating_sub(1),
+            false => (choosing.selected + 1).min(choosing.options.len().saturating_sub(1)),
+        };
+    }
+    // advancing also clears the line the options were asked with
+    if actions.just_pressed_any(Action::Advance) {
+        let selected = choosing.selected;
+        active.choosing = None;
+        if let Some(run) = active.run.as_mut() {
+            run.choose(selected);
+        }
+    }
+}
+
+#[derive(Component)]
+struct DialogueOptions;
+
+// the options on screen, rebuilt whenever they or the selection change
+fn show_dialogue_options(
+    mut commands: Commands,
+    runner: Res<DialogueRunner>,
+    shown: Query<Entity, With<DialogueOptions>>,
+    mut last_shown: Local<Option<Choosing>>,
+) {
+    let choosing = runner.active.as_ref().and_then(|active| active.choosing.clone());
+    if *last_shown == choosing { return; }
+    for entity in &shown {
+        commands.entity(entity).despawn();
+    }
+    if let Some(choosing) = &choosing {
+        commands.spawn((
+            DialogueOptions,
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: percent(0.),
+                left: percent(0.),
+                margin: px(50).all(),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+        )).with_children(|parent| {
+            for (index, option) in choosing.options.iter().enumerate() {
+                let (marker, color) = match index == choosing.selected {
+                    true => ("> ", Color::WHITE),
+                    false => ("  ", Color::srgb(0.6, 0.6, 0.6)),
+                };
+                parent.spawn((Text::new(format!("{marker}{option}")), TextColor(color)));
+            }
+        });
+    }
+    *last_shown = choosing;
+}
+
+// <<camera shot>> cuts to a shot of the level, <<camera>> ends it
+fn camera_command(
+    trigger: On<DialogueCommand>,
+    mut commands: Commands,
+) {
+    if trigger.name != "camera" { return; }
+    commands.trigger(CueCameraShot(trigger.args.first().cloned()));
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    // the dialogue shipped with the game always parses
+    #[test]
+    fn test_first_dialogue_valid() {
+        let script = DialogueScript::parse(include_str!("../../assets/dialogue/first.dialogue"));
+        assert!(script.is_ok_and(|script| script.nodes.contains_key("start")));
+    }
+
+    // lines reach the text queue one at a time, and commands only once the line before them is shown
+    #[test]
+    fn test_runner_waits_for_text() {
+        #[derive(Resource, Default)]
+        struct Triggered(Vec<String>);
+
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .init_resource::<Assets<DialogueAsset>>()
+            .init_resource::<DialogueVariables>()
+            .init_resource::<DialogueRunner>()
+            .init_resource::<TextQueue>()
+            .init_resource::<Triggered>()
+            .add_observer(start_dialogue)
+            .add_observer(|trigger: On<DialogueCommand>, mut triggered: ResMut<Triggered>| triggered.0.push(trigger.name.clone()))
+            .add_systems(Update, run_dialogue);
+        let script = DialogueScript::parse("title: start\n---\nfirst\n<<shake>>\nsecond\n===").unwrap();
+        let script = app.world_mut().resource_mut::<Assets<DialogueAsset>>().add(DialogueAsset(script));
+        app.world_mut().trigger(StartDialogue { script, node: "start".into() });
+
+        app.update();
+        app.update();
+        assert!(!app.world().resource::<TextQueue>().is_idle());
+        assert!(app.world().resource::<Triggered>().0.is_empty());
+
+        app.world_mut().resource_mut::<TextQueue>().clear();
+        app.update();
+        assert_eq!(app.world().resource::<Triggered>().0, vec!["shake".to_string()]);
+        app.world_mut().resource_mut::<TextQueue>().clear();
+        app.update();
+        assert!(!app.world().resource::<DialogueRunner>().is_running());
+    }
+
+    // skipping all text runs the dialogue up to its options, and then to its end once one is chosen
+    #[test]
+    fn test_skip_dialogue() {
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .init_resource::<Assets<DialogueAsset>>()
+            .init_resource::<DialogueVariables>()
+            .init_resource::<DialogueRunner>()
+            .init_resource::<TextQueue>()
+            .init_resource::<TextHistory>()
+            .add_observer(start_dialogue)
+            .add_observer(skip_dialogue)
+            .add_systems(Update, run_dialogue);
+        let script = DialogueScript::parse("title: start\n---\nfirst\n<<wait 5>>\nsecond\n-> a\n    chose a\n-> b\nlast\n===").unwrap();
+        let script = app.world_mut().resource_mut::<Assets<DialogueAsset>>().add(DialogueAsset(script));
+        app.world_mut().trigger(StartDialogue { script, node: "start".into() });
+        // the first line is on the text queue by now, which the ui skips on its own
+        app.update();
+
+        app.world_mut().trigger(SkipAllText);
+        let choosing = app.world().resource::<DialogueRunner>().active.as_ref().and_then(|active| active.choosing.clone());
+        assert_eq!(choosing.map(|choosing| choosing.options), Some(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(app.world().resource::<TextHistory>().last().unwrap().text, "second");
+
+        let mut runner = app.world_mut().resource_mut::<DialogueRunner>();
+        let active = runner.active.as_mut().unwrap();
+        active.choosing = None;
+        active.run.as_mut().unwrap().choose(0);
+        app.world_mut().trigger(SkipAllText);
+        assert!(!app.world().resource::<DialogueRunner>().is_running());
+        assert_eq!(app.world().resource::<TextHistory>().len(), 3);
+    }
+}
@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+
+use super::expression::*;
+use super::script::*;
+
+// statements a dialogue may run without giving the game anything to do, before it is taken to be stuck in a loop of jumps
+static STEP_BUDGET: usize = 10_000;
+
+// what a dialogue wants done next
+#[derive(Clone, PartialEq, Debug)]
+pub enum DialogueStep {
+    Line(String),
+    // the texts of the options the player can pick from, in order
+    Options(Vec<String>),
+    Command(String, Vec<String>),
+    End,
+}
+
+/*
+where a dialogue is in its script. every block being run is a frame on the stack,
+so the dialogue goes on after an if or an option once their statements are done.
+*/
+#[derive(Clone, Debug)]
+pub struct DialogueRun {
+    frames: Vec<(Vec<Statement>, usize)>,
+    // the options offered to the player, waiting for one to be chosen
+    offered: Vec<DialogueOption>,
+}
+
+impl DialogueRun {
+    pub fn start(script: &DialogueScript, node: &str) -> Option<Self> {
+        let statements = script.nodes.get(node)?;
+        Some(DialogueRun { frames: vec![(statements.clone(), 0)], offered: Vec::new() })
+    }
+
+    // run statements until the next one the game has to deal with
+    pub fn step(&mut self, script: &DialogueScript, variables: &mut Variables) -> DialogueStep {
+        if !self.offered.is_empty() {
+            return DialogueStep::Options(self.offered.iter().map(|option| option.text.clone()).collect());
+        }
+        for _ in 0..STEP_BUDGET {
+            let Some((statements, next)) = self.frames.last_mut() else { return DialogueStep::End };
+            let Some(statement) = statements.get(*next).cloned() else {
+                self.frames.pop();
+                continue;
+            };
+            *next += 1;
+            match statement {
+                Statement::Line(text) => return DialogueStep::Line(interpolate(&text, variables)),
+                Statement::Set(name, expr) => match expr.eval(variables) {
+                    Ok(value) => { variables.insert(name, value); },
+                    Err(problem) => warn!("could not set ${}: {}", name, problem),
+                },
+                Statement::If(branches, otherwise) => {
+                    let taken = branches.into_iter()
+                        .find(|(condition, _)| holds(condition, variables))
+                        .map_or(otherwise, |(_, body)| body);
+                    self.frames.push((taken, 0));
+                },
+                Statement::Options(options) => {
+                    self.offered = options.into_iter()
+                        .filter(|option| option.condition.as_ref().is_none_or(|condition| holds(condition, variables)))
+                        .collect();
+                    // with nothing to choose, the dialogue just goes on
+                    if !self.offered.is_empty() {
+                        return DialogueStep::Options(self.offered.iter().map(|option| option.text.clone()).collect());
+                    }
+                },
+                Statement::Jump(node) => match script.nodes.get(&node) {
+                    Some(statements) => self.frames = vec![(statements.clone(), 0)],
+                    None => {
+                        warn!("dialogue jumped to unknown node {:?}", node);
+                        self.frames.clear();
+                    },
+                },
+                Statement::Stop => self.frames.clear(),
+                Statement::Command(name, args) => return DialogueStep::Command(name, args),
+            }
+        }
+        warn!("dialogue ran {} statements without a line, option or command, ending it", STEP_BUDGET);
+        self.frames.clear();
+        DialogueStep::End
+    }
+
+    // pick one of the offered options, by its index among them. false if there is no such option.
+    pub fn choose(&mut self, index: usize) -> bool {
+        if index >= self.offered.len() { return false; }
+        let option = self.offered.swap_remove(index);
+        self.offered.clear();
+        self.frames.push((option.body, 0));
+        true
+    }
+}
+
+fn holds(condition: &Expr, variables: &Variables) -> bool {
+    condition.eval(variables).unwrap_or_else(|problem| {
+        warn!("dialogue condition failed, taken as false: {}", problem);
+        Value::Bool(false)
+    }).truthy()
+}
+
+// replace every {$name} with the variable's value
+pub(super) fn interpolate(text: &str, variables: &Variables) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{$") {
+        let Some(len) = rest[start..].find('}') else { break };
+        let name = &rest[start + 2..start + len];
+        result.push_str(&rest[..start]);
+        result.push_str(&variables.get(name).map_or_else(|| "0".to_string(), Value::to_string));
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_to_end(run: &mut DialogueRun, script: &DialogueScript, variables: &mut Variables, choices: &[usize]) -> Vec<DialogueStep> {
+        let mut choices = choices.iter();
+        let mut steps = Vec::new();
+        loop {
+            let step = run.step(script, variables);
+            if let DialogueStep::Options(_) = step {
+                run.choose(*choices.next().unwrap());
+            }
+            if step == DialogueStep::End { return steps; }
+            steps.push(step);
+        }
+    }
+
+    // -- basic --
+    // conditions see variables set earlier, options lead into their bodies and jumps go to other nodes
+    #[test]
+    fn test_run() {
+        let script = DialogueScript::parse("
+title: start
+---
+<<set $visits = $visits + 1>>
+<<if $visits > 1>>
+again, {$name}
+<<endif>>
+-> hidden <<if false>>
+    nope
+-> onwards
+    <<jump end>>
+-> stay
+    stayed
+after options
+===
+title: end
+---
+<<camera overview>>
+the end
+===").unwrap();
+        let mut variables = Variables::from_iter([("name".to_string(), Value::Text("spark".into()))]);
+
+        let mut run = DialogueRun::start(&script, "start").unwrap();
+        assert_eq!(run_to_end(&mut run, &script, &mut variables, &[1]), vec![
+            DialogueStep::Options(vec!["onwards".into(), "stay".into()]),
+            DialogueStep::Line("stayed".into()),
+            DialogueStep::Line("after options".into()),
+        ]);
+
+        let mut run = DialogueRun::start(&script, "start").unwrap();
+        assert_eq!(run_to_end(&mut run, &script, &mut variables, &[0]), vec![
+            DialogueStep::Line("again, spark".into()),
+            DialogueStep::Options(vec!["onwards".into(), "stay".into()]),
+            DialogueStep::Command("camera".into(), vec!["overview".into()]),
+            DialogueStep::Line("the end".into()),
+        ]);
+        assert_eq!(variables["visits"], Value::Number(2.0));
+    }
+
+    // -- edge cases --
+    // options whose conditions all fail are skipped, and choosing an option that was not offered does nothing
+    #[test]
+    fn test_no_options() {
+        let script = DialogueScript::parse("title: start\n---\n-> a <<if $never>>\n    x\nlast\n===").unwrap();
+        let mut run = DialogueRun::start(&script, "start").unwrap();
+        assert!(!run.choose(0));
+        assert_eq!(run.step(&script, &mut Variables::default()), DialogueStep::Line("last".into()));
+        assert!(DialogueRun::start(&script, "missing").is_none());
+    }
+
+    // a node that only jumps to itself ends instead of running forever
+    #[test]
+    fn test_jump_loop() {
+        let script = DialogueScript::parse("title: a\n---\n<<set $x = $x + 1>>\n<<jump a>>\n===").unwrap();
+        let mut run = DialogueRun::start(&script, "a").unwrap();
+        assert_eq!(run.step(&script, &mut Variables::default()), DialogueStep::End);
+        assert_eq!(run.step(&script, &mut Variables::default()), DialogueStep::End);
+    }
+}
@@ -0,0 +1,295 @@
+use bevy::platform::collections::HashMap;
+use bevy_pretty_text::prelude::*;
+use thiserror::Error;
+
+use super::expression::*;
+use super::runner::interpolate;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Statement {
+    // text for the text queue, with {$variable} replaced by the variable's value
+    Line(String),
+    Set(String, Expr),
+    // the statements of the first branch whose condition holds, or the else statements
+    If(Vec<(Expr, Vec<Statement>)>, Vec<Statement>),
+    Options(Vec<DialogueOption>),
+    Jump(String),
+    Stop,
+    // anything else between << >>, for the game to handle
+    Command(String, Vec<String>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DialogueOption {
+    pub text: String,
+    // the option is only offered while this holds
+    pub condition: Option<Expr>,
+    pub body: Vec<Statement>,
+}
+
+/*
+a parsed dialogue file, made of named nodes:
+
+    title: start
+    ---
+    // comments are ignored
+    little spark....
+    <<set $visits = $visits + 1>>
+    <<if $visits > 1>>
+    back again, {$name}?
+    <<else>>
+    who are you?
+    <<endif>>
+    -> follow the line
+        <<jump line>>
+    -> stay <<if $brave>>
+        you stay.
+    <<camera overview>>
+    ===
+
+options follow each other at the same indentation, each with its body indented below it.
+*/
+#[derive(Clone, Default, Debug)]
+pub struct DialogueScript {
+    pub nodes: HashMap<String, Vec<Statement>>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("line {line}: {problem}")]
+pub struct DialogueError {
+    pub line: usize,
+    pub problem: String,
+}
+
+struct SourceLine<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+impl SourceLine<'_> {
+    fn error(&self, problem: impl Into<String>) -> DialogueError {
+        DialogueError { line: self.number, problem: problem.into() }
+    }
+
+    // the inside of a << >> command, if the line is one
+    fn command(&self) -> Option<&str> {
+        self.text.strip_prefix("<<")?.strip_suffix(">>").map(str::trim)
+    }
+
+    // the node a <<jump>> goes to, if the line is one
+    fn jump_target(&self) -> Option<&str> {
+        let (name, rest) = self.command()?.split_once(char::is_whitespace)?;
+        (name == "jump").then(|| rest.trim())
+    }
+}
+
+impl DialogueScript {
+    pub fn parse(source: &str) -> Result<Self, DialogueError> {
+        let mut script = DialogueScript::default();
+        // every jump and where it is, to check once all nodes are known
+        let mut jumps = Vec::new();
+        let mut lines = source.lines().enumerate().map(|(index, text)| SourceLine {
+            number: index + 1,
+            indent: text.len() - text.trim_start().len(),
+            text: text.trim(),
+        });
+
+        while let Some(line) = lines.by_ref().find(|line| !line.text.is_empty() && !line.text.starts_with("//")) {
+            // headers, up to ---
+            let mut title = None;
+            let header_line = line.number;
+            let mut header = Some(line);
+            while let Some(line) = header.take().or_else(|| lines.next()) {
+                if line.text == "---" { break; }
+                if let Some(value) = line.text.strip_prefix("title:") {
+                    title = Some(value.trim().to_string());
+                }
+            }
+            let title = title.ok_or(DialogueError { line: header_line, problem: "node without a title".into() })?;
+
+            let body: Vec<SourceLine> = lines.by_ref()
+                .take_while(|line| line.text != "===")
+                .filter(|line| !line.text.is_empty() && !line.text.starts_with("//"))
+                .collect();
+            let mut pos = 0;
+            let statements = parse_block(&body, &mut pos, 0)?;
+            if let Some(line) = body.get(pos) {
+                return Err(line.error(format!("unexpected {:?}", line.text)));
+            }
+            jumps.extend(body.iter().filter_map(|line| line.jump_target().map(|node| (node.to_string(), line.number))));
+            if script.nodes.insert(title.clone(), statements).is_some() {
+                return Err(DialogueError { line: header_line, problem: format!("node {title:?} is defined twice") });
+            }
+        }
+
+        // every jump has to go to a node that exists
+        if let Some((node, line)) = jumps.into_iter().find(|(node, _)| !script.nodes.contains_key(node)) {
+            return Err(DialogueError { line, problem: format!("jump to unknown node {node:?}") });
+        }
+        Ok(script)
+    }
+}
+
+// statements up to the end of the block: a line indented less than min_indent, or an elseif, else or endif left for the caller
+fn parse_block(lines: &[SourceLine], pos: &mut usize, min_indent: usize) -> Result<Vec<Statement>, DialogueError> {
+    let mut statements = Vec::new();
+    while let Some(line) = lines.get(*pos) {
+        if line.indent < min_indent { break; }
+        if line.text.starts_with("->") {
+            statements.push(Statement::Options(parse_options(lines, pos)?));
+            continue;
+        }
+        let Some(command) = line.command() else {
+            let text = unescape(line.text);
+            check_markup(&text).map_err(|problem| line.error(problem))?;
+            statements.push(Statement::Line(text));
+            *pos += 1;
+            continue;
+        };
+        let (name, rest) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let rest = rest.trim();
+        match name {
+            "elseif" | "else" | "endif" => break,
+            "if" => statements.push(parse_if(lines, pos, rest)?),
+            "set" => {
+                let (variable, value) = rest.split_once(" to ").or_else(|| rest.split_once('='))
+                    .ok_or_else(|| line.error("set needs a variable and a value, like <<set $name = 1>>"))?;
+                let variable = variable.trim().strip_prefix('$').ok_or_else(|| line.error("variables start with $"))?;
+                statements.push(Statement::Set(variable.to_string(), Expr::parse(value).map_err(|problem| line.error(problem))?));
+                *pos += 1;
+            },
+            "jump" if !rest.is_empty() => {
+                statements.push(Statement::Jump(rest.to_string()));
+                *pos += 1;
+            },
+            "jump" => return Err(line.error("jump needs a node")),
+            "stop" => {
+                statements.push(Statement::Stop);
+                *pos += 1;
+            },
+            _ => {
+                statements.push(Statement::Command(name.to_string(), rest.split_whitespace().map(String::from).collect()));
+                *pos += 1;
+            },
+        }
+    }
+    Ok(statements)
+}
+
+fn parse_if(lines: &[SourceLine], pos: &mut usize, condition: &str) -> Result<Statement, DialogueError> {
+    let start = &lines[*pos];
+    let mut branches = Vec::new();
+    let mut condition = Some(Expr::parse(condition).map_err(|problem| start.error(problem))?);
+    *pos += 1;
+    loop {
+        let body = parse_block(lines, pos, 0)?;
+        let Some(line) = lines.get(*pos) else { return Err(start.error("if without endif")) };
+        let command = line.command().unwrap_or_default();
+        match condition.take() {
+            Some(condition) => branches.push((condition, body)),
+            // the else branch has to be the last
+            None if command == "endif" => {
+                *pos += 1;
+                return Ok(Statement::If(branches, body));
+            },
+            None => return Err(line.error("nothing may follow else but endif")),
+        }
+        *pos += 1;
+        match command.split_once(char::is_whitespace).unwrap_or((command, "")) {
+            ("elseif", expr) => condition = Some(Expr::parse(expr).map_err(|problem| line.error(problem))?),
+            ("else", _) => {},
+            _ => return Ok(Statement::If(branches, Vec::new())),
+        }
+    }
+}
+
+// options at the same indentation, one after the other
+fn parse_options(lines: &[SourceLine], pos: &mut usize) -> Result<Vec<DialogueOption>, DialogueError> {
+    let indent = lines[*pos].indent;
+    let mut options = Vec::new();
+    while let Some(line) = lines.get(*pos)
+        && line.indent == indent
+        && let Some(text) = line.text.strip_prefix("->") {
+        let (text, condition) = match text.split_once("<<if") {
+            Some((text, condition)) => {
+                let condition = condition.trim().strip_suffix(">>").ok_or_else(|| line.error("option condition without >>"))?;
+                (text, Some(Expr::parse(condition).map_err(|problem| line.error(problem))?))
+            },
+            None => (text, None),
+        };
+        *pos += 1;
+        let body = parse_block(lines, pos, indent + 1)?;
+        options.push(DialogueOption { text: unescape(text.trim()), condition, body });
+    }
+    Ok(options)
+}
+
+// lines are shown as pretty text, so their markup has to parse, with any variable in them
+fn check_markup(text: &str) -> Result<(), String> {
+    match PrettyParser::spans(&interpolate(text, &Variables::default())) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(format!("bad text markup, {error}")),
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("\\n", "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // -- basic --
+    // nodes hold their lines, commands, conditions and options with their bodies
+    #[test]
+    fn test_parse() {
+        let source = "
+title: start
+---
+hello\\nspark
+<<set $visits = $visits + 1>>
+<<if $visits > 1>>
+    again
+<<elseif $visits == 1>>
+    first
+<<else>>
+    never
+<<endif>>
+-> go on <<if $brave>>
+    <<camera overview>>
+    <<jump end>>
+-> stay
+<<stop>>
+===
+title: end
+---
+bye
+===";
+        let script = DialogueScript::parse(source).unwrap();
+        let start = &script.nodes["start"];
+        assert_eq!(start[0], Statement::Line("hello\nspark".into()));
+        assert!(matches!(&start[1], Statement::Set(name, _) if name == "visits"));
+        assert!(matches!(&start[2], Statement::If(branches, otherwise) if branches.len() == 2 && otherwise == &vec![Statement::Line("never".into())]));
+        let Statement::Options(options) = &start[3] else { panic!("{:?}", start[3]) };
+        assert_eq!(options.len(), 2);
+        assert!(options[0].condition.is_some() && options[1].condition.is_none());
+        assert_eq!(options[0].body, vec![Statement::Command("camera".into(), vec!["overview".into()]), Statement::Jump("end".into())]);
+        assert_eq!(start[4], Statement::Stop);
+        assert_eq!(script.nodes["end"], vec![Statement::Line("bye".into())]);
+    }
+
+    // -- edge cases --
+    // mistakes are reported with the line they are on
+    #[test]
+    fn test_errors() {
+        assert_eq!(DialogueScript::parse("title: a\n---\n<<if $x>>\nhi\n===").unwrap_err().line, 3);
+        assert_eq!(DialogueScript::parse("title: a\n---\nhi\n<<endif>>\n===").unwrap_err().line, 4);
+        assert_eq!(DialogueScript::parse("title: a\n---\n<<set x = 1>>\n===").unwrap_err().line, 3);
+        assert_eq!(DialogueScript::parse("title: a\n---\nhi {$name}\n[little spark|0.5 hi](spark)\n===").unwrap_err().line, 4);
+        let error = DialogueScript::parse("title: a\n---\nhi\n-> go\n    <<jump b>>\n===").unwrap_err();
+        assert!(error.problem.contains("\"b\"") && error.line == 5, "{error}");
+        assert_eq!(DialogueScript::parse("title: a\n---\nhi\n===\n\nfrom: b\n---\nhi\n===").unwrap_err().line, 6);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{collections::BTreeSet, path::{Path, PathBuf}};
+
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{DialogueAsset, StartDialogue};
+use crate::{
+    electric_grid::{cables::{Cable, CableSpatialIndex, TowerSpan}, controllers::SparkController, faults::GridEvent, spark_movement::{move_spark, Spark}},
+    ui::TextQueue,
+};
+
+// where the triggers that already fired are remembered, relative to the working directory
+static FIRED_TRIGGERS_PATH: &str = "save/fired_triggers.ron";
+
+pub struct DialogueTriggersPlugin;
+impl Plugin for DialogueTriggersPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .insert_resource(FiredTriggers::load_or_default(FIRED_TRIGGERS_PATH))
+        .insert_resource(FiredTriggersFile(FIRED_TRIGGERS_PATH.into()))
+        .add_observer(reset_fired_triggers)
+        .add_observer(check_grid_triggers)
+        .add_systems(Update, check_dialogue_triggers.after(move_spark));
+    }
+}
+
+// whether the grid lost or got back power somewhere
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GridChange {
+    Blackout,
+    Restored,
+}
+
+// where a trigger fires, around the trigger entity's position, or when something happens to the grid anywhere
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TriggerShape {
+    // when a spark enters the box
+    Box { half_extents: Vec3 },
+    // when a spark comes within the radius, e.g. of the tower the trigger is on
+    Sphere { radius: f32 },
+    // when a spark passes the point the given fraction along any cable of the span closest to the trigger, either way
+    CablePoint { t: f32 },
+    // when cables lose or get back power
+    Grid { on: GridChange },
+}
+
+impl TriggerShape {
+    // whether a point is inside the shape, for the shapes that have an inside
+    pub fn contains(&self, center: Vec3, point: Vec3) -> bool {
+        match self {
+            TriggerShape::Box { half_extents } => (point - center).abs().cmple(*half_extents).all(),
+            TriggerShape::Sphere { radius } => point.distance(center) <= *radius,
+            TriggerShape::CablePoint { .. } | TriggerShape::Grid { .. } => false,
+        }
+    }
+}
+
+// whether going from one distance along a cable to another passes the mark
+pub fn passes(from: f32, to: f32, mark: f32) -> bool {
+    (from < mark && to >= mark) || (from > mark && to <= mark)
+}
+
+#[derive(Clone, Debug)]
+pub enum TriggerAction {
+    // texts pushed onto the text queue
+    Text(Vec<String>),
+    // texts shown in speech bubbles at the trigger
+    Speech(Vec<String>),
+    Dialogue { script: Handle<DialogueAsset>, node: String },
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum TriggerPolicy {
+    // never again once fired, even after the level is reloaded or the game restarted
+    #[default]
+    Once,
+    EveryTime,
+    // again once this many seconds have passed
+    Cooldown(f32),
+}
+
+/*
+narrative placed in the world: fires its action when a player spark enters its shape or passes its point on a cable,
+or when the grid goes dark or gets power back.
+triggers that fire once are remembered by id in FiredTriggers, so ids have to be unique.
+*/
+#[derive(Component, Clone, Debug)]
+#[require(Transform, TriggerState)]
+pub struct DialogueTrigger {
+    pub id: String,
+    pub shape: TriggerShape,
+    pub action: TriggerAction,
+    pub policy: TriggerPolicy,
+}
+
+// triggered on a trigger that fired
+#[derive(EntityEvent, Debug)]
+pub struct DialogueTriggered {
+    pub entity: Entity,
+    // the player spark that set it off, none for triggers on the grid
+    pub spark: Option<Entity>,
+}
+
+#[derive(Component, Clone, Default, Debug)]
+pub struct TriggerState {
+    // sparks inside the shape last frame
+    inside: HashSet<Entity>,
+    // the cables of the span a cable point trigger watches
+    cables: HashSet<Entity>,
+    // where each spark was along its cable last frame
+    last_along: HashMap<Entity, f32>,
+    cooldown_left: f32,
+}
+
+#[derive(Debug, Error)]
+pub enum FiredTriggersError {
+    #[error("could not access fired triggers file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse fired triggers file at {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write fired triggers: {0}")]
+    Write(#[from] ron::Error),
+}
+
+// ids of the triggers that fire once and already did
+#[derive(Resource, Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FiredTriggers {
+    ids: BTreeSet<String>,
+}
+
+impl FiredTriggers {
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    pub fn insert(&mut self, id: &str) {
+        self.ids.insert(id.to_string());
+    }
+
+    // forget everything, for a new game
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FiredTriggersError> {
+        Ok(ron::de::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FiredTriggersError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)?;
+        Ok(())
+    }
+
+    fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            return FiredTriggers::default();
+        }
+        FiredTriggers::load(path).unwrap_or_else(|error| {
+            warn!("forgetting fired triggers, {}", error);
+            FiredTriggers::default()
+        })
+    }
+}
+
+// where fired triggers are saved, nowhere if missing
+#[derive(Resource, Clone, Debug)]
+pub struct FiredTriggersFile(pub PathBuf);
+
+// triggered to forget every trigger that fired, e.g. when starting a new game
+#[derive(Event, Debug)]
+pub struct ResetFiredTriggers;
+
+fn reset_fired_triggers(
+    _: On<ResetFiredTriggers>,
+    mut fired: ResMut<FiredTriggers>,
+    fired_file: Option<Res<FiredTriggersFile>>,
+    mut states: Query<&mut TriggerState>,
+) {
+    fired.clear();
+    if let Some(file) = &fired_file
+        && let Err(error) = fired.save(&file.0) {
+        warn!("could not save fired triggers, {}", error);
+    }
+    for mut state in &mut states {
+        state.cooldown_left = 0.0;
+    }
+}
+
+// every cable of the span the cable is in, or just the cable if it is not strung between towers
+fn span_cables(cable: Entity, spans: &Query<(Entity, &TowerSpan)>) -> HashSet<Entity> {
+    match spans.get(cable) {
+        Ok((_, span)) => spans.iter()
+            .filter(|(_, other)| *other == span)
+            .map(|(other_cable, _)| other_cable)
+            .collect(),
+        Err(_) => [cable].into_iter().collect(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_dialogue_triggers(
+    mut commands: Commands,
+    mut triggers: Query<(Entity, &DialogueTrigger, &mut TriggerState, Ref<GlobalTransform>)>,
+    sparks: Query<(Entity, &Spark, &Transform, &SparkController)>,
+    cables: Query<&Cable>,
+    spans: Query<(Entity, &TowerSpan)>,
+    cable_index: Res<CableSpatialIndex>,
+    mut fired: ResMut<FiredTriggers>,
+    fired_file: Option<Res<FiredTriggersFile>>,
+    mut text_queue: ResMut<TextQueue>,
+    time: Res<Time>,
+) {
+    for (trigger_entity, trigger, mut state, trigger_transform) in &mut triggers {
+        state.cooldown_left = (state.cooldown_left - time.delta_secs()).max(0.0);
+        // not placed yet, or fired by the grid instead
+        if trigger_transform.is_added() || matches!(trigger.shape, TriggerShape::Grid { .. }) { continue; }
+        let center = trigger_transform.translation();
+
+        // cables are indexed as they are generated, so the closest one may change until they all are
+        if let TriggerShape::CablePoint { .. } = trigger.shape
+            && (state.cables.is_empty() || cable_index.is_changed()) {
+            state.cables = cable_index.nearest(center, f32::INFINITY)
+                .map(|hit| span_cables(hit.cable, &spans))
+                .unwrap_or_default();
+        }
+
+        let mut triggered_by = None;
+        for (spark_entity, spark, spark_transform, controller) in &sparks {
+            // only the player's sparks tell the story
+            if !matches!(controller, SparkController::Keyboard | SparkController::Gamepad(_)) { continue; }
+            let entered = match trigger.shape {
+                TriggerShape::CablePoint { t } => {
+                    let on_cable = state.cables.contains(&spark.connected_to_cable_entity);
+                    let last = match on_cable {
+                        true => state.last_along.insert(spark_entity, spark.dist_along),
+                        false => {
+                            state.last_along.remove(&spark_entity);
+                            None
+                        },
+                    };
+                    let mark = cables.get(spark.connected_to_cable_entity).map_or(0.0, |cable| t * cable.length());
+                    last.is_some_and(|last| passes(last, spark.dist_along, mark))
+                },
+                shape => match shape.contains(center, spark_transform.translation) {
+                    // newly inside
+                    true => state.inside.insert(spark_entity),
+                    false => {
+                        state.inside.remove(&spark_entity);
+                        false
+                    },
+                },
+            };
+            if entered && triggered_by.is_none() {
+                triggered_by = Some(spark_entity);
+            }
+        }
+        let Some(spark_entity) = triggered_by else { continue };
+        fire_trigger(&mut commands, trigger_entity, trigger, &mut state, &mut fired, fired_file.as_deref(), &mut text_queue, Some(spark_entity));
+    }
+}
+
+// fire the triggers waiting for what happened to the grid
+fn check_grid_triggers(
+    event: On<GridEvent>,
+    mut commands: Commands,
+    mut triggers: Query<(Entity, &DialogueTrigger, &mut TriggerState)>,
+    mut fired: ResMut<FiredTriggers>,
+    fired_file: Option<Res<FiredTriggersFile>>,
+    mut text_queue: ResMut<TextQueue>,
+) {
+    let change = match *event {
+        GridEvent::Blackout { .. } => GridChange::Blackout,
+        GridEvent::Restored { .. } => GridChange::Restored,
+        _ => return,
+    };
+    for (trigger_entity, trigger, mut state) in &mut triggers {
+        if trigger.shape != (TriggerShape::Grid { on: change }) { continue; }
+        fire_trigger(&mut commands, trigger_entity, trigger, &mut state, &mut fired, fired_file.as_deref(), &mut text_queue, None);
+    }
+}
+
+// run the trigger's action if its policy lets it
+#[allow(clippy::too_many_arguments)]
+fn fire_trigger(
+    commands: &mut Commands,
+    trigger_entity: Entity,
+    trigger: &DialogueTrigger,
+    state: &mut TriggerState,
+    fired: &mut FiredTriggers,
+    fired_file: Option<&FiredTriggersFile>,
+    text_queue: &mut TextQueue,
+    spark: Option<Entity>,
+) {
+    let ready = match trigger.policy {
+        TriggerPolicy::Once => !fired.contains(&trigger.id),
+        TriggerPolicy::EveryTime => true,
+        TriggerPolicy::Cooldown(_) => state.cooldown_left <= 0.0,
+    };
+    if !ready { return; }
+    debug!("trigger {:?} fired", trigger.id);
+    match trigger.policy {
+        TriggerPolicy::Once => {
+            fired.insert(&trigger.id);
+            if let Some(file) = fired_file
+                && let Err(error) = fired.save(&file.0) {
+                warn!("could not save fired triggers, {}", error);
+            }
+        },
+        TriggerPolicy::Cooldown(seconds) => state.cooldown_left = seconds,
+        TriggerPolicy::EveryTime => {},
+    }
+    match &trigger.action {
+        TriggerAction::Text(texts) => for text in texts {
+            text_queue.push_text(text);
+        },
+        TriggerAction::Speech(texts) => for text in texts {
+            text_queue.push_speech(text, trigger_entity);
+        },
+        TriggerAction::Dialogue { script, node } => commands.trigger(StartDialogue { script: script.clone(), node: node.clone() }),
+    }
+    commands.trigger(DialogueTriggered { entity: trigger_entity, spark });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::prelude::*;
+    use super::*;
+
+    fn trigger_app(shape: TriggerShape, policy: TriggerPolicy) -> (App, Entity) {
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .init_resource::<FiredTriggers>()
+            .init_resource::<TextQueue>()
+            .add_observer(reset_fired_triggers)
+            .add_observer(check_grid_triggers)
+            .add_systems(Update, check_dialogue_triggers);
+        let mut cable = Cable::default();
+        cable.set_segments(vec![Vec3::ZERO, Vec3::X * 100.0]);
+        let cable = app.world_mut().spawn(cable).id();
+        let mut index = CableSpatialIndex::new(10.0);
+        index.insert(cable, &[Vec3::ZERO, Vec3::X * 100.0]);
+        app.insert_resource(index);
+        let spark = app.world_mut().spawn((Spark::new(cable, 1.0), Transform::default())).id();
+        app.world_mut().spawn((
+            DialogueTrigger { id: "tower".into(), shape, action: TriggerAction::Text(vec!["hello".into()]), policy },
+            GlobalTransform::from_translation(Vec3::X * 50.0),
+        ));
+        // the trigger is placed on the first update
+        app.update();
+        (app, spark)
+    }
+
+    // move the spark along its cable and check the triggers, returning whether any text was queued
+    fn move_to(app: &mut App, spark: Entity, along: f32) -> bool {
+        app.world_mut().get_mut::<Spark>(spark).unwrap().dist_along = along;
+        app.world_mut().get_mut::<Transform>(spark).unwrap().translation = Vec3::X * along;
+        app.update();
+        let fired = !app.world().resource::<TextQueue>().is_idle();
+        app.world_mut().resource_mut::<TextQueue>().clear();
+        fired
+    }
+
+    // -- basic --
+    // a once trigger fires when the spark first comes within its radius, and never again
+    #[test]
+    fn test_once() {
+        let (mut app, spark) = trigger_app(TriggerShape::Sphere { radius: 10.0 }, TriggerPolicy::Once);
+        assert!(!move_to(&mut app, spark, 30.0));
+        assert!(move_to(&mut app, spark, 45.0));
+        assert!(!move_to(&mut app, spark, 50.0));
+        assert!(!move_to(&mut app, spark, 80.0));
+        assert!(!move_to(&mut app, spark, 50.0));
+        assert!(app.world().resource::<FiredTriggers>().contains("tower"));
+
+        // until the fired triggers are forgotten
+        app.world_mut().trigger(ResetFiredTriggers);
+        assert!(!move_to(&mut app, spark, 80.0));
+        assert!(move_to(&mut app, spark, 50.0));
+    }
+
+    // a cable point fires whenever the spark passes it, either way, but not within its cooldown
+    #[test]
+    fn test_cable_point_cooldown() {
+        let (mut app, spark) = trigger_app(TriggerShape::CablePoint { t: 0.5 }, TriggerPolicy::Cooldown(10.0));
+        assert!(!move_to(&mut app, spark, 40.0));
+        assert!(move_to(&mut app, spark, 60.0));
+        assert!(!move_to(&mut app, spark, 40.0));
+        // every update counts down the last time step
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(5));
+        assert!(!move_to(&mut app, spark, 60.0));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(6));
+        assert!(move_to(&mut app, spark, 40.0));
+
+        let (mut app, spark) = trigger_app(TriggerShape::CablePoint { t: 0.5 }, TriggerPolicy::EveryTime);
+        assert!(!move_to(&mut app, spark, 40.0));
+        assert!(move_to(&mut app, spark, 60.0));
+        assert!(move_to(&mut app, spark, 40.0));
+    }
+
+    // a cable point watches every phase cable of the span, not just the one closest to the trigger
+    #[test]
+    fn test_cable_point_span() {
+        let (mut app, spark) = trigger_app(TriggerShape::CablePoint { t: 0.5 }, TriggerPolicy::EveryTime);
+        let world = app.world_mut();
+        let span = TowerSpan { from: world.spawn_empty().id(), to: world.spawn_empty().id() };
+        let closest = world.get::<Spark>(spark).unwrap().connected_to_cable_entity;
+        world.entity_mut(closest).insert(span);
+        let mut cable = Cable::default();
+        cable.set_segments(vec![Vec3::Z * 5.0, Vec3::new(100.0, 0.0, 5.0)]);
+        let other = world.spawn((cable, span)).id();
+        // the span is looked up again once the cables are indexed
+        world.resource_mut::<CableSpatialIndex>().set_changed();
+        world.entity_mut(spark).insert(Spark::new(other, 1.0));
+
+        assert!(!move_to(&mut app, spark, 40.0));
+        assert!(move_to(&mut app, spark, 60.0));
+    }
+
+    // a grid trigger fires when the grid goes dark, not when it comes back, wherever the sparks are
+    #[test]
+    fn test_grid_blackout() {
+        let (mut app, spark) = trigger_app(TriggerShape::Grid { on: GridChange::Blackout }, TriggerPolicy::EveryTime);
+        assert!(!move_to(&mut app, spark, 50.0));
+        let fired = |app: &mut App, event: GridEvent| {
+            app.world_mut().trigger(event);
+            let fired = !app.world().resource::<TextQueue>().is_idle();
+            app.world_mut().resource_mut::<TextQueue>().clear();
+            fired
+        };
+        assert!(!fired(&mut app, GridEvent::Restored { cables: 2 }));
+        assert!(fired(&mut app, GridEvent::Blackout { cables: 2 }));
+        assert!(fired(&mut app, GridEvent::Blackout { cables: 1 }));
+    }
+
+    // -- edge cases --
+    // sparks that are not the player's do not fire triggers
+    #[test]
+    fn test_ignores_other_sparks() {
+        let (mut app, spark) = trigger_app(TriggerShape::Box { half_extents: Vec3::splat(5.0) }, TriggerPolicy::EveryTime);
+        app.world_mut().entity_mut(spark).insert(SparkController::Ai(default()));
+        assert!(!move_to(&mut app, spark, 50.0));
+        assert!(!passes(10.0, 10.0, 10.0) && passes(9.0, 10.0, 10.0) && passes(11.0, 10.0, 10.0));
+    }
+}
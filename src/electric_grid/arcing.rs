@@ -0,0 +1,230 @@
+use bevy::{color::palettes::css::{AQUA, GRAY}, prelude::*};
+use super::breakers::*;
+use super::cables::*;
+use super::controllers::*;
+use super::junctions::*;
+use super::phases::*;
+use super::spark_movement::*;
+use super::substation::*;
+
+use crate::actions::Action;
+
+pub struct ArcingPlugin;
+impl Plugin for ArcingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_gizmo_group::<ArcGizmos>()
+        .add_systems(Update, (
+            (recharge_arcing, find_arc_targets, arc_jump_player).chain().after(move_spark),
+            arc_gizmos,
+        ));
+    }
+}
+
+/*
+lets a spark jump across the air gap to a nearby cable, landing at the point of that cable closest to it.
+every jump costs energy, which recharges over time, and the spark has to wait out a cooldown before jumping again.
+cables the spark could jump to are kept up to date in ArcTargets.
+*/
+#[derive(Component, Clone, Debug)]
+#[require(ArcTargets)]
+pub struct Arcing {
+    // how far away a cable may be to be jumped to, in metres
+    pub radius: f32,
+    pub energy: f32,
+    pub max_energy: f32,
+    // energy regained per second
+    pub recharge: f32,
+    // energy spent per jump
+    pub cost: f32,
+    // seconds between jumps
+    pub cooldown: f32,
+    pub(crate) cooldown_left: f32,
+}
+
+impl Default for Arcing {
+    // enough reach to jump between the phases of a tower
+    fn default() -> Self {
+        Arcing { radius: 8.0, energy: 100.0, max_energy: 100.0, recharge: 10.0, cost: 40.0, cooldown: 1.0, cooldown_left: 0.0 }
+    }
+}
+
+impl Arcing {
+    pub fn ready(&self) -> bool {
+        self.cooldown_left <= 0.0 && self.energy >= self.cost
+    }
+}
+
+// the closest point of each cable within reach of an arcing spark, closest first
+#[derive(Component, Default, Debug)]
+pub struct ArcTargets(pub Vec<CableHit>);
+
+// triggered on a spark that jumped from one cable to another
+#[derive(EntityEvent, Debug)]
+pub struct ArcJumped {
+    pub entity: Entity,
+    pub from: Entity,
+    pub to: CableHit,
+}
+
+// the cables near a spark it can jump to, leaving out the cable it is on and cables not rated for its voltage
+pub fn arc_targets<'a>(
+    spark: &Spark,
+    nearby: Vec<CableHit>,
+    rating_of: impl Fn(Entity) -> Option<&'a VoltageRating>,
+) -> Vec<CableHit> {
+    nearby.into_iter()
+        .filter(|hit| hit.cable != spark.connected_to_cable_entity)
+        .filter(|hit| rating_of(hit.cable).is_none_or(|rating| rating.accepts(spark.voltage)))
+        .collect()
+}
+
+fn recharge_arcing(
+    mut sparks: Query<&mut Arcing>,
+    time: Res<Time>,
+) {
+    for mut arcing in &mut sparks {
+        arcing.energy = (arcing.energy + arcing.recharge * time.delta_secs()).min(arcing.max_energy);
+        arcing.cooldown_left = (arcing.cooldown_left - time.delta_secs()).max(0.0);
+    }
+}
+
+fn find_arc_targets(
+    mut sparks: Query<(&Spark, &Transform, &Arcing, &mut ArcTargets)>,
+    index: Res<CableSpatialIndex>,
+    ratings: Query<&VoltageRating>,
+    breakers: Query<&Breaker>,
+) {
+    for (spark, spark_transform, arcing, mut targets) in &mut sparks {
+        targets.0 = arc_targets(spark, index.within(spark_transform.translation, arcing.radius), |cable| ratings.get(cable).ok());
+        // arcing onto a switched off cable would get around the breaker
+        targets.0.retain(|target| conducts(&breakers, target.cable));
+    }
+}
+
+// move the spark onto the target cable, paying for the jump
+pub(crate) fn arc_jump(
+    commands: &mut Commands,
+    spark_entity: Entity,
+    spark: &mut Spark,
+    spark_transform: &mut Transform,
+    arcing: &mut Arcing,
+    target: CableHit,
+    target_phase: Option<&CablePhase>,
+) {
+    debug!("spark arced from cable {} to {}", spark.connected_to_cable_entity, target.cable);
+    let from = spark.connected_to_cable_entity;
+    spark.connected_to_cable_entity = target.cable;
+    spark.dist_along = target.along;
+    spark_transform.translation = target.position;
+    arcing.energy -= arcing.cost;
+    arcing.cooldown_left = arcing.cooldown;
+
+    // arcing is the way across to other circuits, whatever the spark's phase rule
+    if let Some(target_phase) = target_phase
+        && target_phase.phase != spark.phase {
+        commands.trigger(PhaseShifted { entity: spark_entity, from: spark.phase, to: target_phase.phase });
+        spark.phase = target_phase.phase;
+    }
+    // a spark waiting at a junction leaves it behind
+    commands.entity(spark_entity).remove::<AtJunction>();
+    commands.trigger(ArcJumped { entity: spark_entity, from, to: target });
+}
+
+fn arc_jump_player(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut Transform, &mut Arcing, &ArcTargets, &SparkController)>,
+    cable_phases: Query<&CablePhase>,
+    input: ControllerActions,
+) {
+    for (spark_entity, mut spark, mut spark_transform, mut arcing, targets, controller) in &mut sparks {
+        if !input.just_pressed(controller, Action::Arc) { continue; }
+        let Some(target) = targets.0.first() else { continue };
+        if !arcing.ready() {
+            debug!("spark cannot arc yet, {:.1} energy and {:.1}s of cooldown left", arcing.energy, arcing.cooldown_left);
+            continue;
+        }
+        arc_jump(&mut commands, spark_entity, &mut spark, &mut spark_transform, &mut arcing, *target, cable_phases.get(target.cable).ok());
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct ArcGizmos;
+
+// the closest target is where the spark jumps to, the others are shown dimmed
+fn arc_gizmos(
+    mut gizmos: Gizmos<ArcGizmos>,
+    sparks: Query<(&Transform, &Arcing, &ArcTargets)>,
+) {
+    for (spark_transform, arcing, targets) in sparks {
+        for (index, target) in targets.0.iter().enumerate() {
+            let color = match index == 0 && arcing.ready() {
+                true => AQUA,
+                false => GRAY,
+            };
+            gizmos.line(spark_transform.translation, target.position, color);
+            gizmos.sphere(Isometry3d::from_translation(target.position), 0.5, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    fn entity(index: u32) -> Entity {
+        Entity::from_raw_u32(index).unwrap()
+    }
+
+    // -- basic --
+    // every other cable in reach is a target, closest first, landing on the point nearest to the spark
+    #[test]
+    fn test_arc_targets() {
+        let mut index = CableSpatialIndex::default();
+        index.insert(entity(1), &[Vec3::ZERO, Vec3::X * 20.0]);
+        index.insert(entity(2), &[Vec3::new(0.0, 6.0, 0.0), Vec3::new(20.0, 6.0, 0.0)]);
+        index.insert(entity(3), &[Vec3::new(0.0, -7.0, 0.0), Vec3::new(20.0, -7.0, 0.0)]);
+        index.insert(entity(4), &[Vec3::new(0.0, 0.0, 30.0), Vec3::new(20.0, 0.0, 30.0)]);
+
+        let targets = arc_targets(&Spark::new(entity(1), 1.0), index.within(Vec3::X * 5.0, 8.0), |_| None);
+        assert_eq!(targets.iter().map(|target| target.cable).collect::<Vec<_>>(), vec![entity(2), entity(3)]);
+        assert!((targets[0].along - 5.0).abs() < 0.001);
+        assert!((targets[0].position - Vec3::new(5.0, 6.0, 0.0)).length() < 0.001);
+    }
+
+    // cables not rated for the spark's voltage cannot be jumped to
+    #[test]
+    fn test_arc_targets_respect_rating() {
+        let mut index = CableSpatialIndex::default();
+        index.insert(entity(2), &[Vec3::Y, Vec3::new(10.0, 1.0, 0.0)]);
+        let rating = VoltageRating { min: 100_000.0, max: 400_000.0 };
+
+        let spark = Spark::new(entity(1), 1.0);
+        assert!(arc_targets(&spark, index.within(Vec3::ZERO, 8.0), |_| Some(&rating)).is_empty());
+    }
+
+    // jumping pays the cost and starts the cooldown
+    #[test]
+    fn test_arc_jump_costs_energy() {
+        let mut app = App::new();
+        let world = app.world_mut();
+        let (own, target_cable) = (world.spawn_empty().id(), world.spawn_empty().id());
+        let spark_entity = world.spawn(Spark::new(own, 1.0)).id();
+        let target = CableHit { cable: target_cable, along: 3.0, position: Vec3::Y * 6.0, distance: 6.0 };
+
+        let mut spark = Spark::new(own, 1.0);
+        let mut transform = Transform::default();
+        let mut arcing = Arcing::default();
+        let mut commands = world.commands();
+        arc_jump(&mut commands, spark_entity, &mut spark, &mut transform, &mut arcing, target, Some(&CablePhase { circuit: 1, phase: Phase::L3 }));
+        world.flush();
+
+        assert_eq!(spark.connected_to_cable_entity, target_cable);
+        assert_eq!(spark.dist_along, 3.0);
+        assert_eq!(spark.phase, Phase::L3);
+        assert_eq!(transform.translation, Vec3::Y * 6.0);
+        assert!(!arcing.ready());
+        assert_eq!(arcing.energy, 60.0);
+    }
+}
@@ -0,0 +1,266 @@
+use bevy::{color::palettes::css::{LIME, ORANGE_RED, RED}, prelude::*};
+use super::cables::*;
+use super::controllers::*;
+use super::grid_graph::*;
+use super::power_flow::*;
+use super::spark_movement::*;
+
+use crate::actions::Action;
+
+// how close a player spark has to be to a breaker to operate it, in metres
+static SWITCH_REACH: f32 = 10.0;
+
+pub struct BreakersPlugin;
+impl Plugin for BreakersPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_gizmo_group::<BreakerGizmos>()
+        .add_systems(FixedUpdate, trip_overloaded_breakers.after(solve_power_flow))
+        .add_systems(Update, (
+            operate_breaker_player.before(move_spark),
+            breaker_gizmos,
+        ));
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Reflect)]
+pub enum BreakerState {
+    #[default]
+    Closed,
+    // opened on purpose
+    Open,
+    // opened by protection, e.g. on overcurrent. stays open until closed again.
+    Tripped,
+}
+
+impl BreakerState {
+    pub fn conducts(&self) -> bool {
+        *self == BreakerState::Closed
+    }
+}
+
+/*
+a switch that can take a cable, or a connection with every cable attached to it, out of service.
+sparks are not offered cables behind a breaker that does not conduct, and power does not flow through it.
+a spark already on a cable when its breaker opens rides it to the end.
+breakers with a trip current open themselves when the power flow puts more current than that through them.
+*/
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+pub struct Breaker {
+    pub state: BreakerState,
+    // current in amperes above which the breaker trips, never if None
+    pub trip_current: Option<f32>,
+    // whether the player can switch it
+    pub operable: bool,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker { state: default(), trip_current: None, operable: true }
+    }
+}
+
+// triggered on a breaker whose state changed
+#[derive(EntityEvent, Debug)]
+pub struct BreakerSwitched {
+    pub entity: Entity,
+    pub from: BreakerState,
+    pub to: BreakerState,
+}
+
+// put the breaker into the given state, announcing the change if there is one
+pub fn switch_breaker(commands: &mut Commands, entity: Entity, breaker: &mut Breaker, state: BreakerState) {
+    if breaker.state == state { return }
+    debug!("breaker {} switched from {:?} to {:?}", entity, breaker.state, state);
+    commands.trigger(BreakerSwitched { entity, from: breaker.state, to: state });
+    breaker.state = state;
+}
+
+// whether a cable or connection lets sparks and power through. entities without a breaker always do.
+pub(crate) fn conducts(breakers: &Query<&Breaker>, entity: Entity) -> bool {
+    breakers.get(entity).ok().is_none_or(|breaker| breaker.state.conducts())
+}
+
+// the current through a breaker: through its cable, or the largest through any cable attached to its connection
+fn breaker_current(entity: Entity, graph: &GridGraph, flows: &Query<&LineFlow>) -> Option<f32> {
+    match graph.edge(entity) {
+        Some(_) => flows.get(entity).ok().map(|flow| flow.current),
+        None => graph.neighbours(entity)
+            .filter_map(|(cable, _)| flows.get(cable).ok().map(|flow| flow.current))
+            .reduce(f32::max),
+    }
+}
+
+fn trip_overloaded_breakers(
+    mut commands: Commands,
+    mut breakers: Query<(Entity, &mut Breaker)>,
+    graph: Res<GridGraph>,
+    flows: Query<&LineFlow>,
+) {
+    for (entity, mut breaker) in &mut breakers {
+        let Some(trip_current) = breaker.trip_current else { continue };
+        if !breaker.state.conducts() { continue }
+        let Some(current) = breaker_current(entity, &graph, &flows) else { continue };
+        if current > trip_current {
+            info!("breaker {} tripped at {:.0} A, rated for {:.0} A", entity, current, trip_current);
+            switch_breaker(&mut commands, entity, &mut breaker, BreakerState::Tripped);
+        }
+    }
+}
+
+// where a breaker sits: the position of its connection, or the point of its cable closest to the given position
+fn breaker_position(entity: Entity, near: Vec3, connections: &Query<&GlobalTransform, With<CableConnection>>, cables: &Query<&Cable>) -> Option<Vec3> {
+    if let Ok(connection_transform) = connections.get(entity) {
+        return Some(connection_transform.translation());
+    }
+    let cable = cables.get(entity).ok()?;
+    let (along, _) = cable.closest_point(near)?;
+    Some(cable.pos_at_distance(along))
+}
+
+// the switch action switches the closest breaker within reach of the player spark, closing it if it does not conduct and opening it otherwise
+fn operate_breaker_player(
+    mut commands: Commands,
+    sparks: Query<(&Transform, &SparkController), With<Spark>>,
+    mut breakers: Query<(Entity, &mut Breaker)>,
+    connections: Query<&GlobalTransform, With<CableConnection>>,
+    cables: Query<&Cable>,
+    input: ControllerActions,
+) {
+    for (spark_transform, controller) in sparks {
+        if !input.just_pressed(controller, Action::Switch) { continue; }
+        let spark_position = spark_transform.translation;
+        let closest = breakers.iter()
+            .filter(|(_, breaker)| breaker.operable)
+            .filter_map(|(entity, _)| {
+                breaker_position(entity, spark_position, &connections, &cables).map(|position| (entity, position.distance(spark_position)))
+            })
+            .filter(|(_, distance)| *distance <= SWITCH_REACH)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((entity, _)) = closest else { continue };
+        let Ok((_, mut breaker)) = breakers.get_mut(entity) else { continue };
+        let state = match breaker.state.conducts() {
+            true => BreakerState::Open,
+            false => BreakerState::Closed,
+        };
+        switch_breaker(&mut commands, entity, &mut breaker, state);
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct BreakerGizmos;
+
+fn breaker_gizmos(
+    mut gizmos: Gizmos<BreakerGizmos>,
+    breakers: Query<(Entity, &Breaker)>,
+    connections: Query<&GlobalTransform, With<CableConnection>>,
+    cables: Query<&Cable>,
+) {
+    for (entity, breaker) in breakers {
+        // cable breakers are drawn halfway along their cable
+        let position = match cables.get(entity) {
+            Ok(cable) if cable.length() > 0.0 => Some(cable.pos_at_distance(cable.length() / 2.0)),
+            _ => connections.get(entity).ok().map(GlobalTransform::translation),
+        };
+        let Some(position) = position else { continue };
+        let color = match breaker.state {
+            BreakerState::Closed => LIME,
+            BreakerState::Open => ORANGE_RED,
+            BreakerState::Tripped => RED,
+        };
+        gizmos.cuboid(Transform::from_translation(position).with_scale(Vec3::splat(1.5)), color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+    use crate::electric_grid::{junctions::*, phases::*};
+
+    #[derive(Resource, Default)]
+    struct Switched(Vec<(BreakerState, BreakerState)>);
+
+    fn record_switches(app: &mut App) {
+        app
+            .init_resource::<Switched>()
+            .add_observer(|trigger: On<BreakerSwitched>, mut switched: ResMut<Switched>| switched.0.push((trigger.from, trigger.to)));
+    }
+
+    // a cable arriving at a tower with two cables leaving it
+    fn spawn_fork(world: &mut World) -> (Entity, Entity, [Entity; 2]) {
+        let (start, tower) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let incoming = spawn_generated_cable(world, start, tower, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let outgoing = [Vec3::new(10.0, 0.0, 5.0), Vec3::new(10.0, 0.0, -5.0)].map(|end| {
+            let end_connection = world.spawn(CableConnection::default()).id();
+            spawn_generated_cable(world, tower, end_connection, vec![Vec3::ZERO, end])
+        });
+        (incoming, tower, outgoing)
+    }
+
+    fn choices_at(world: &mut World, connection: Entity, cable: Entity) -> Vec<Entity> {
+        world.run_system_once(move |network: CableNetwork| {
+            network.gather_choices(connection, Travel::Forward, &Spark::new(cable, 1.0), PhaseRule::SamePhase)
+                .iter().map(|choice| choice.cable).collect::<Vec<_>>()
+        }).unwrap()
+    }
+
+    // -- basic --
+    // sparks are not offered cables whose breaker is open
+    #[test]
+    fn test_open_cable_not_offered() {
+        let mut world = World::new();
+        let (incoming, tower, outgoing) = spawn_fork(&mut world);
+        world.entity_mut(outgoing[0]).insert(Breaker { state: BreakerState::Open, ..default() });
+        world.entity_mut(outgoing[1]).insert(Breaker::default());
+
+        assert_eq!(choices_at(&mut world, tower, incoming), vec![outgoing[1]]);
+    }
+
+    // a tripped breaker on a connection blocks every way through it
+    #[test]
+    fn test_tripped_connection_blocks() {
+        let mut world = World::new();
+        let (incoming, tower, _) = spawn_fork(&mut world);
+        world.entity_mut(tower).insert(Breaker { state: BreakerState::Tripped, ..default() });
+
+        assert!(choices_at(&mut world, tower, incoming).is_empty());
+    }
+
+    // too much current through a breaker trips it, and the tripped line carries no more power
+    #[test]
+    fn test_overcurrent_trips() {
+        let mut app = App::new();
+        app.add_plugins((GridGraphPlugin, PowerFlowPlugin));
+        record_switches(&mut app);
+        let world = app.world_mut();
+        let generator = world.spawn(Generator { voltage: 10_000.0 }).id();
+        let load = world.spawn(Load { power: 1_000_000.0, ..default() }).id();
+        let cable = spawn_generated_cable(world, generator, load, vec![Vec3::ZERO, Vec3::X * 1000.0]);
+        world.entity_mut(cable).insert(Breaker { trip_current: Some(50.0), ..default() });
+        world.flush();
+
+        world.run_system_once(solve_power_flow).unwrap();
+        world.run_system_once(trip_overloaded_breakers).unwrap();
+        assert_eq!(world.get::<Breaker>(cable).unwrap().state, BreakerState::Tripped);
+        assert_eq!(world.resource::<Switched>().0, vec![(BreakerState::Closed, BreakerState::Tripped)]);
+
+        world.run_system_once(solve_power_flow).unwrap();
+        assert_eq!(world.get::<LineFlow>(cable).unwrap().current, 0.0);
+        assert_eq!(world.get::<BusVoltage>(load).unwrap().magnitude, 0.0);
+    }
+
+    // -- edge cases --
+    // switching a breaker into the state it is already in announces nothing
+    #[test]
+    fn test_switch_to_same_state() {
+        let mut app = App::new();
+        record_switches(&mut app);
+        let world = app.world_mut();
+        let entity = world.spawn_empty().id();
+        let mut breaker = Breaker::default();
+        switch_breaker(&mut world.commands(), entity, &mut breaker, BreakerState::Closed);
+        world.flush();
+        assert!(world.resource::<Switched>().0.is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+
+// how many bisection steps are taken when solving for the catenary parameter. each step halves the search range in log space.
+static SOLVER_ITERATIONS: u32 = 64;
+// smallest catenary parameter allowed, relative to the horizontal span. below this the cable hangs kilometres deep and cosh overflows.
+static MIN_PARAMETER_RATIO: f64 = 1.0 / 80.0;
+
+/*
+physical description of a hanging cable. a catenary is fully determined by its endpoints and one more parameter,
+which can be given in whichever way is most convenient.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum CatenarySpec {
+    // total length of the cable in metres. lengths shorter than the straight line between the endpoints give a taut cable.
+    Length(f32),
+    // horizontal component of the cable tension in newtons, and the weight of the cable in newtons per metre.
+    HorizontalTension { tension: f32, weight: f32 },
+    // largest vertical distance in metres between the cable and the straight line joining its endpoints.
+    MaxSag(f32),
+}
+
+pub(super) fn get_catenary(t: f32, start_pos: Vec3, end_pos: Vec3, spec: CatenarySpec) -> Option<Vec3> {
+
+    // negative or non finite parameters
+    match spec {
+        CatenarySpec::Length(length) if !(length >= 0.0 && length.is_finite()) => return None,
+        CatenarySpec::HorizontalTension { tension, weight } if !(tension > 0.0 && weight > 0.0 && (tension / weight).is_finite()) => return None,
+        CatenarySpec::MaxSag(sag) if !(sag >= 0.0 && sag.is_finite()) => return None,
+        _ => {}
+    }
+
+    let dxz = end_pos.xz() - start_pos.xz();
+    let dy = (end_pos.y - start_pos.y) as f64;
+    let dx = dxz.length() as f64;
+    let straight = start_pos.lerp(end_pos, t);
+
+    // a vertical cable hangs straight down, as does a cable with equal endpoints
+    if dx == 0.0 {
+        return Some(straight)
+    }
+
+    let a = match catenary_parameter(dx, dy, spec) {
+        Some(a) => a,
+        // taut cable
+        None => return Some(straight),
+    };
+
+    let local_y = catenary_2d_helper(a, dx, dy, t as f64);
+    if !local_y.is_finite() {
+        return Some(straight)
+    }
+
+    // map back to 3d
+    let out = t * dxz.length() * Vec3::new(dxz.x, 0.0, dxz.y).normalize() + local_y as f32 * Vec3::Y;
+    Some(start_pos + out)
+}
+
+// the catenary parameter a = horizontal tension / weight per metre for the given span. None if the cable is taut.
+fn catenary_parameter(dx: f64, dy: f64, spec: CatenarySpec) -> Option<f64> {
+    match spec {
+        CatenarySpec::HorizontalTension { tension, weight } => Some((tension as f64 / weight as f64).max(dx * MIN_PARAMETER_RATIO)),
+        CatenarySpec::Length(length) => {
+            let length = length as f64;
+            if length * length <= dx * dx + dy * dy { return None }
+            // the horizontal projection of the cable's length satisfies sqrt(L² - dy²) = 2a sinh(dx / 2a), decreasing in a
+            let target = (length * length - dy * dy).sqrt();
+            solve_decreasing(dx, |a| 2.0 * a * (dx / (2.0 * a)).sinh() - target)
+        },
+        CatenarySpec::MaxSag(sag) => {
+            if sag <= 0.0 { return None }
+            solve_decreasing(dx, |a| max_sag(a, dx, dy) - sag as f64)
+        },
+    }
+}
+
+// find the root of a function that decreases with a, searching a range of a scaled to the horizontal span.
+// very small values of a give a cable that sags all the way down, very large values give a nearly straight cable.
+fn solve_decreasing(dx: f64, f: impl Fn(f64) -> f64) -> Option<f64> {
+    let (mut low, mut high) = ((dx * MIN_PARAMETER_RATIO).ln(), (dx * 1e4).ln());
+    // asking for more sag than the solver range allows, use the deepest cable it can represent
+    if f(low.exp()) <= 0.0 { return Some(low.exp()) }
+    // asking for less sag than the solver range allows, the cable is effectively taut
+    if f(high.exp()) >= 0.0 { return None }
+    for _ in 0..SOLVER_ITERATIONS {
+        let mid = 0.5 * (low + high);
+        match f(mid.exp()) > 0.0 {
+            true => low = mid,
+            false => high = mid,
+        }
+    }
+    Some((0.5 * (low + high)).exp())
+}
+
+// horizontal position of the lowest point of the catenary through (0, 0) and (dx, dy). may lie outside of the span.
+fn catenary_vertex(a: f64, dx: f64, dy: f64) -> f64 {
+    dx / 2.0 - a * (dy / (2.0 * a * (dx / (2.0 * a)).sinh())).asinh()
+}
+
+fn max_sag(a: f64, dx: f64, dy: f64) -> f64 {
+    let x0 = catenary_vertex(a, dx, dy);
+    // the sag is largest where the cable is parallel to the chord
+    let x = (x0 + a * (dy / dx).asinh()).clamp(0.0, dx);
+    let chord_y = dy * x / dx;
+    let cable_y = a * (((x - x0) / a).cosh() - (x0 / a).cosh());
+    chord_y - cable_y
+}
+
+fn catenary_2d_helper(a: f64, dx: f64, dy: f64, t: f64) -> f64 {
+    let x0 = catenary_vertex(a, dx, dy);
+
+    let raw_catenary = |x: f64| a * (((x - x0) / a).cosh() - (x0 / a).cosh());
+    raw_catenary(t * dx)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    static SPECS: [CatenarySpec; 3] = [
+        CatenarySpec::Length(60.0),
+        CatenarySpec::HorizontalTension { tension: 2000.0, weight: 15.0 },
+        CatenarySpec::MaxSag(4.0),
+    ];
+
+    fn sampled_length(start: Vec3, end: Vec3, spec: CatenarySpec) -> f32 {
+        let samples: Vec<Vec3> = (0..=1000).map(|i| get_catenary(i as f32 / 1000.0, start, end, spec).unwrap()).collect();
+        samples.windows(2).map(|pair| pair[0].distance(pair[1])).sum()
+    }
+
+    // -- basic --
+    // endpoints are at the right places
+    #[test]
+    fn test_endpoints_correct() {
+        let (exp_start, exp_end) = (Vec3::new(-2.0, 4.0, 5.2), Vec3::new(30.0, -4.7, -21.23));
+
+        for spec in SPECS {
+            let start = get_catenary(0.0, exp_start, exp_end, spec).unwrap();
+            let end = get_catenary(1.0, exp_start, exp_end, spec).unwrap();
+            assert!((start - exp_start).length() < 0.001);
+            assert!((end - exp_end).length() < 0.01);
+        }
+    }
+
+    // a cable given by its length is as long as asked for
+    #[test]
+    fn test_length_respected() {
+        let (start, end) = (Vec3::new(0.0, 10.0, 0.0), Vec3::new(50.0, 20.0, 0.0));
+        let length = sampled_length(start, end, CatenarySpec::Length(60.0));
+        assert!((length - 60.0).abs() < 0.05);
+    }
+
+    // a cable given by its sag dips that far below the chord
+    #[test]
+    fn test_sag_respected() {
+        let (start, end) = (Vec3::ZERO, Vec3::new(50.0, 0.0, 0.0));
+        let mid = get_catenary(0.5, start, end, CatenarySpec::MaxSag(4.0)).unwrap();
+        assert!((mid.y + 4.0).abs() < 0.001);
+    }
+
+    // -- edge cases --
+    // giving equal start and end positions
+    #[test]
+    fn test_equal() {
+        let (exp_start, exp_end) = (Vec3::new(5.0, 4.2, 2.04), Vec3::new(5.0, 4.2, 2.04));
+        for spec in SPECS {
+            let start = get_catenary(0.0, exp_start, exp_end, spec).unwrap();
+            let end = get_catenary(1.0, exp_start, exp_end, spec).unwrap();
+            assert!((start - exp_start).length() < 0.001);
+            assert!((end - exp_end).length() < 0.001);
+
+            let mid = get_catenary(0.5, exp_start, exp_end, spec);
+            assert!(mid.is_some());
+        }
+    }
+
+    // giving start and end positions with the same x and z components
+    #[test]
+    fn test_vertical() {
+        let (exp_start, exp_end) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        for spec in SPECS {
+            let start = get_catenary(0.0, exp_start, exp_end, spec).unwrap();
+            let end = get_catenary(1.0, exp_start, exp_end, spec).unwrap();
+            assert!((start - exp_start).length() < 0.001);
+            assert!((end - exp_end).length() < 0.001);
+
+            let mid = get_catenary(0.5, exp_start, exp_end, spec);
+            assert!(mid.is_some());
+        }
+    }
+
+    // a length shorter than the span pulls the cable taut
+    #[test]
+    fn test_taut() {
+        let (start, end) = (Vec3::ZERO, Vec3::new(50.0, 10.0, 0.0));
+        let mid = get_catenary(0.5, start, end, CatenarySpec::Length(10.0)).unwrap();
+        assert!((mid - start.lerp(end, 0.5)).length() < 0.001);
+    }
+
+    // invalid parameters are rejected, extreme ones never produce NaN
+    #[test]
+    fn test_nan_free() {
+        let (start, end) = (Vec3::ZERO, Vec3::new(500.0, -300.0, 20.0));
+        assert!(get_catenary(0.5, start, end, CatenarySpec::Length(-1.0)).is_none());
+        assert!(get_catenary(0.5, start, end, CatenarySpec::MaxSag(f32::NAN)).is_none());
+        assert!(get_catenary(0.5, start, end, CatenarySpec::HorizontalTension { tension: 0.0, weight: 1.0 }).is_none());
+
+        let extreme = [
+            CatenarySpec::Length(1e6),
+            CatenarySpec::MaxSag(1e6),
+            CatenarySpec::MaxSag(1e-6),
+            CatenarySpec::HorizontalTension { tension: 1e-3, weight: 1e3 },
+            CatenarySpec::HorizontalTension { tension: 1e9, weight: 1e-3 },
+        ];
+        for spec in extreme {
+            for i in 0..=10 {
+                let pos = get_catenary(i as f32 / 10.0, start, end, spec).unwrap();
+                assert!(pos.is_finite());
+            }
+        }
+    }
+}
@@ -1,22 +1,34 @@
 use bevy::{color::palettes::css::{BLUE, GREY, RED}, prelude::*};
 use bevy_polyline::prelude::*;
+use catenary::*;
 use parabola::*;
+use spatial_index::*;
 
+pub use catenary::CatenarySpec;
+pub use spatial_index::{CableHit, CableSpatialIndex};
+
+mod catenary;
 mod parabola;
+mod spatial_index;
 
-static HANG: f32 = 2.0;
+pub static HANG: f32 = 2.0;
 static CABLE_THICKNESS: f32 = 3.0;
 
 pub struct CablesPlugin;
 impl Plugin for CablesPlugin {
     fn build(&self, app: &mut App) {
         app
+        .init_resource::<CableSpatialIndex>()
         .init_gizmo_group::<CableGizmos>()
         .insert_gizmo_config(CableGizmos, GizmoConfig {
             depth_bias: -1.0,
             ..default()
         })
-        .add_systems(Last, generate_added_cables)
+        .add_observer(despawn_disconnected_cable::<StartsFrom>)
+        .add_observer(despawn_disconnected_cable::<EndsAt>)
+        .add_observer(index_generated_cable)
+        .add_observer(unindex_removed_cable)
+        .add_systems(Last, generate_cables)
         .add_systems(Update, cable_gizmos);
     }
 }
@@ -26,6 +38,7 @@ a component for designating an entity with a global position as a cable connecti
 ideally, this component should be inserted in an entity containing a transform. if not, a transform will be created for it.
 inserting this component will also insert two relationshiptarget components (CablesStartingHere and CablesEndingHere) that
 will keep track of cables connecting to it in an oriented way.
+despawning a connection despawns every cable connected to it, together with their polyline assets.
 */
 #[derive(Component, Reflect, Default)]
 #[reflect(Component, Default)]
@@ -37,11 +50,11 @@ pub struct CableConnection {
 }
 
 #[derive(Component ,Default)]
-#[relationship_target(relationship = StartsFrom)]
+#[relationship_target(relationship = StartsFrom, linked_spawn)]
 pub struct CablesStartingHere(Vec<Entity>);
 
 #[derive(Component ,Default)]
-#[relationship_target(relationship = EndsAt)]
+#[relationship_target(relationship = EndsAt, linked_spawn)]
 pub struct CablesEndingHere(Vec<Entity>);
 
 #[derive(Component, Reflect)]
@@ -52,79 +65,243 @@ pub struct StartsFrom(pub Entity);
 #[relationship(relationship_target = CablesEndingHere)]
 pub struct EndsAt(pub Entity);
 
+// the two towers a cable is strung between, the same for every phase cable of the span
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TowerSpan {
+    pub from: Entity,
+    pub to: Entity,
+}
+
 #[derive(Component)]
 pub struct Cable {
     generated: bool,
-    segment_num: u64,
+    pub segment_num: u64,
     segments: Vec<Vec3>,
+    // cumulative length of the polyline up to each point in segments, so arc_lengths[0] is always 0 and the last entry is the cable length
+    arc_lengths: Vec<f32>,
     pub color: LinearRgba,
-    hang: f32
+    pub curve: CurveModel,
+    pub hang: f32
+}
+
+// a cable already generated along the given points, from one connection to another, for tests that need no scene
+#[cfg(test)]
+pub(crate) fn spawn_generated_cable(world: &mut World, from: Entity, to: Entity, points: Vec<Vec3>) -> Entity {
+    let mut cable = Cable::default();
+    cable.set_segments(points);
+    world.spawn((cable, StartsFrom(from), EndsAt(to))).id()
+}
+
+// the shape a cable takes between its endpoints
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub enum CurveModel {
+    // cheap approximation of the sag, driven by the unitless hang of the cable
+    #[default]
+    Parabola,
+    // physically accurate hanging cable
+    Catenary(CatenarySpec),
 }
 
 impl Cable {
-    pub fn get_pos_along(&self, t: f32) -> Vec3 {
-        if !Interval::UNIT.contains(t) {
-            error!("cable position requested for parameter outside unit interval");
+    // total length of the generated cable in metres. 0 if the cable has not been generated yet.
+    pub fn length(&self) -> f32 {
+        self.arc_lengths.last().copied().unwrap_or(0.0)
+    }
+
+    // position of the point at the given distance in metres along the cable, measured from its start.
+    pub fn pos_at_distance(&self, distance: f32) -> Vec3 {
+        if !(0.0..=self.length()).contains(&distance) {
+            error!("cable position requested for distance outside of the cable");
+        }
+        match self.segment_at_distance(distance) {
+            Some((index, fraction)) => self.segments[index].lerp(self.segments[index + 1], fraction),
+            None => self.segments.first().copied().unwrap_or_default(),
         }
-        // TODO: make it so there is a sample curve already in the cable struct, maybe replace the segments, as this would already have that data.
-        SampleAutoCurve::new(Interval::UNIT, self.segments.clone()).unwrap().sample(t).unwrap()
+    }
+
+    // direction of the cable at the given distance in metres along it, pointing from its start towards its end.
+    pub fn tangent_at_distance(&self, distance: f32) -> Option<Dir3> {
+        let (index, _) = self.segment_at_distance(distance)?;
+        Dir3::new(self.segments[index + 1] - self.segments[index]).ok()
+    }
+
+    // distance along the cable of the point on it closest to the given position, and how far away from the position that point is.
+    // None if the cable has not been generated yet.
+    pub fn closest_point(&self, point: Vec3) -> Option<(f32, f32)> {
+        self.segments.windows(2).zip(&self.arc_lengths)
+            .map(|(pair, segment_start)| {
+                let segment = pair[1] - pair[0];
+                let fraction = match segment.length_squared() > 0.0 {
+                    true => ((point - pair[0]).dot(segment) / segment.length_squared()).clamp(0.0, 1.0),
+                    false => 0.0,
+                };
+                (segment_start + fraction * segment.length(), point.distance(pair[0] + fraction * segment))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    // the sampled points of the generated cable, from its start to its end. empty if the cable has not been generated yet.
+    pub fn points(&self) -> &[Vec3] {
+        &self.segments
+    }
+
+    // direction a spark travels in when leaving the start of the cable. None if the cable has not been generated yet.
+    pub fn start_direction(&self) -> Option<Dir3> {
+        self.tangent_at_distance(0.0)
+    }
+
+    // direction a spark travels in when arriving at the end of the cable. None if the cable has not been generated yet.
+    pub fn end_direction(&self) -> Option<Dir3> {
+        self.tangent_at_distance(self.length())
+    }
+
+    // replace the sampled points of the cable, keeping the arc length table in sync with them
+    pub(crate) fn set_segments(&mut self, segments: Vec<Vec3>) {
+        self.arc_lengths = std::iter::once(0.0)
+            .chain(segments.windows(2).scan(0.0, |length, pair| {
+                *length += pair[0].distance(pair[1]);
+                Some(*length)
+            }))
+            .collect();
+        self.segments = segments;
+    }
 
+    // index of the polyline segment containing the given distance, and how far along that segment it is in [0, 1]
+    fn segment_at_distance(&self, distance: f32) -> Option<(usize, f32)> {
+        if self.segments.len() < 2 { return None }
+        let distance = distance.clamp(0.0, self.length());
+        let index = self.arc_lengths
+            .partition_point(|&length| length <= distance)
+            .saturating_sub(1)
+            .min(self.segments.len() - 2);
+        let segment_length = self.arc_lengths[index + 1] - self.arc_lengths[index];
+        let fraction = match segment_length > 0.0 {
+            true => (distance - self.arc_lengths[index]) / segment_length,
+            false => 0.0,
+        };
+        Some((index, fraction))
     }
 }
 
 impl Default for Cable {
-    fn default() -> Self { Cable { generated: false, segment_num: 10, segments: Vec::new(), color: GREY.into(), hang: 1.0 } }
+    fn default() -> Self { Cable { generated: false, segment_num: 10, segments: Vec::new(), arc_lengths: Vec::new(), color: GREY.into(), curve: default(), hang: 1.0 } }
 }
 
 // spawn a cable with given endpoints.
 // this only creates the entity with base components. the actual meshes of the cables will be created the next time the cable generating system runs.
 pub fn spawn_cable(commands: &mut Commands, start_point: &Entity, end_point: &Entity, hang: Option<f32>) -> Entity {
     
+    spawn_cable_from(commands, start_point, end_point, Cable {
+        hang: hang.unwrap_or(HANG),
+        ..default()
+    })
+}
+
+// spawn a cable with given endpoints that hangs in the shape of a catenary.
+pub fn spawn_catenary_cable(commands: &mut Commands, start_point: &Entity, end_point: &Entity, spec: CatenarySpec) -> Entity {
+
+    spawn_cable_from(commands, start_point, end_point, Cable {
+        curve: CurveModel::Catenary(spec),
+        ..default()
+    })
+}
+
+// spawn a fully configured cable with given endpoints.
+pub fn spawn_cable_from(commands: &mut Commands, start_point: &Entity, end_point: &Entity, cable: Cable) -> Entity {
+
     commands.spawn((
         Name::new("Cable"),
-        Cable {
-            hang: hang.unwrap_or(HANG),
-            ..default()
-        },
+        cable,
         StartsFrom(*start_point),
         EndsAt(*end_point),
     )).id()
 }
 
-// generate meshes for cables that have been added in the last tick.
-fn generate_added_cables(
+// sample the curve of the cable at evenly spaced parameters. falls back to a straight line if the curve parameters are invalid.
+fn sample_cable_curve(cable: &Cable, start_pos: Vec3, end_pos: Vec3) -> Vec<Vec3> {
+    let curve = |t: f32| match cable.curve {
+        CurveModel::Parabola => get_parabola(t, start_pos, end_pos, cable.hang),
+        CurveModel::Catenary(spec) => get_catenary(t, start_pos, end_pos, spec),
+    };
+    let samples: Option<Vec<Vec3>> = (0..=cable.segment_num).map(|x| curve(x as f32 / cable.segment_num as f32)).collect();
+    samples.unwrap_or_else(|| {
+        error!("invalid curve parameters {:?} for cable, drawing it straight", cable.curve);
+        vec![start_pos, end_pos]
+    })
+}
+
+// a cable that lost one of its endpoints cannot be drawn or travelled on anymore.
+// despawning the connection already takes its cables with it, this also covers the relationship being removed by hand.
+// polyline and material handles are only held by the cable, so their assets are freed with it.
+fn despawn_disconnected_cable<R: Component>(
+    trigger: On<Remove, R>,
+    cables: Query<(), With<Cable>>,
     mut commands: Commands,
-    added_cables: Query<(Entity, &mut Cable, &StartsFrom, &EndsAt), Added<Cable>>,
-    cable_connections: Query<(&GlobalTransform, &CableConnection)>,
+) {
+    if cables.contains(trigger.entity) {
+        commands.entity(trigger.entity).try_despawn();
+    }
+}
+
+// triggered on a cable entity every time its geometry has been (re)generated
+#[derive(EntityEvent)]
+pub struct CableGenerated {
+    pub entity: Entity,
+}
+
+// generate meshes for cables that are new, were edited, or whose connection points moved since the last tick.
+// existing polyline assets are updated in place so regenerating a cable does not leak assets.
+#[allow(clippy::type_complexity)]
+fn generate_cables(
+    mut commands: Commands,
+    cables: Query<(Entity, &mut Cable, &StartsFrom, &EndsAt, Option<&PolylineHandle>, Option<&PolylineMaterialHandle>)>,
+    cable_connections: Query<(Ref<GlobalTransform>, Ref<CableConnection>)>,
     mut polylines: ResMut<Assets<Polyline>>,
     mut polyline_materials: ResMut<Assets<PolylineMaterial>>,
 ) {
     
-    for (cable_entity, mut cable, cable_start, cable_end) in added_cables {
-        let (start_transform, start_connection) = cable_connections.get(cable_start.0).unwrap();
-        let (end_transform, end_connection) = cable_connections.get(cable_end.0).unwrap();
+    for (cable_entity, mut cable, cable_start, cable_end, polyline, material) in cables {
+        let (Ok((start_transform, start_connection)), Ok((end_transform, end_connection))) = 
+            (cable_connections.get(cable_start.0), cable_connections.get(cable_end.0)) else { continue };
+
+        let endpoints_moved = start_transform.is_changed() || start_connection.is_changed()
+            || end_transform.is_changed() || end_connection.is_changed();
+        if cable.generated && !cable.is_changed() && !endpoints_moved { continue; }
 
         let start_pos = start_transform.translation() + start_connection.connection_point_offset;
         let end_pos = end_transform.translation() + end_connection.connection_point_offset;
 
-        // create FunctionCurve and sample at segments
-        let curve = FunctionCurve::new(Interval::UNIT, |t| get_parabola(t, start_pos, end_pos, cable.hang).unwrap());
-        let samples: Vec<Vec3> = (0..=cable.segment_num).map(|x| curve.sample(x as f32 / cable.segment_num as f32).unwrap()).collect();
-
-        // insert polyline
-        debug!("generating added cable with endpoints at {:?} and {:?}", start_pos, end_pos);
-        commands.entity(cable_entity).insert(PolylineBundle {
-            polyline: PolylineHandle(polylines.add(Polyline { vertices: samples.clone() })),
-            material: PolylineMaterialHandle(polyline_materials.add(PolylineMaterial {
-                width: CABLE_THICKNESS,
-                color: cable.color,
-                perspective: false,
-                ..default()
-            })),
-            ..default()
-        });
+        // sample the cable's curve at segments
+        let samples = sample_cable_curve(&cable, start_pos, end_pos);
+
+        match (polyline.and_then(|handle| polylines.get_mut(&handle.0)), material.and_then(|handle| polyline_materials.get_mut(&handle.0))) {
+            // update existing polyline
+            (Some(existing_polyline), Some(existing_material)) => {
+                debug!("regenerating cable with endpoints at {:?} and {:?}", start_pos, end_pos);
+                existing_polyline.vertices = samples.clone();
+                existing_material.color = cable.color;
+            },
+            // insert polyline
+            _ => {
+                debug!("generating added cable with endpoints at {:?} and {:?}", start_pos, end_pos);
+                commands.entity(cable_entity).insert(PolylineBundle {
+                    polyline: PolylineHandle(polylines.add(Polyline { vertices: samples.clone() })),
+                    material: PolylineMaterialHandle(polyline_materials.add(PolylineMaterial {
+                        width: CABLE_THICKNESS,
+                        color: cable.color,
+                        perspective: false,
+                        ..default()
+                    })),
+                    ..default()
+                });
+            }
+        }
+
+        // writing the generated data must not count as a change, or the cable would regenerate every tick
+        let cable = cable.bypass_change_detection();
         cable.generated = true;
-        cable.segments = samples;
+        cable.set_segments(samples);
+        commands.trigger(CableGenerated { entity: cable_entity });
     }
 }
 
@@ -138,8 +315,8 @@ fn cable_gizmos(
     cable_connections: Query<(&GlobalTransform, &CableConnection)>,
 ) {
     for (from, to) in cables {
-        let (from_transform, from_conn) = cable_connections.get(from.0).unwrap();
-        let (to_transform, to_conn) = cable_connections.get(to.0).unwrap();
+        let (Ok((from_transform, from_conn)), Ok((to_transform, to_conn))) = 
+            (cable_connections.get(from.0), cable_connections.get(to.0)) else { continue };
         gizmos.sphere(from_transform.to_isometry(), 0.1, RED);
         gizmos.line(from_transform.translation(), from_transform.transform_point(from_conn.connection_point_offset), RED);
         gizmos.sphere(to_transform.to_isometry(), 0.1, BLUE);
@@ -177,4 +354,122 @@ mod tests {
         assert_eq!(from.index(), exposed_from.0.index());
         assert_eq!(to.index(), exposed_to.0.index());
     }
+
+    fn spawn_some_connections(world: &mut World) -> (Entity, Entity) {
+        let from = world.spawn((CableConnection::default(), GlobalTransform::from_translation(Vec3::ZERO))).id();
+        let to = world.spawn((CableConnection::default(), GlobalTransform::from_translation(Vec3::X * 10.0))).id();
+        (from, to)
+    }
+
+    fn cable_test_app() -> App {
+        let mut app = App::new();
+        app
+            .init_resource::<Assets<Polyline>>()
+            .init_resource::<Assets<PolylineMaterial>>()
+            .init_resource::<CableSpatialIndex>()
+            .add_observer(index_generated_cable)
+            .add_observer(unindex_removed_cable)
+            .add_systems(Update, generate_cables);
+        app
+    }
+
+    // moving a connection point regenerates the cable in place, without adding new polyline assets
+    #[test]
+    fn test_cable_regenerates_when_endpoint_moves() {
+        let mut app = cable_test_app();
+        let (from, to) = spawn_some_connections(app.world_mut());
+        let cable_entity = spawn_cable(&mut app.world_mut().commands(), &from, &to, Some(0.0));
+        app.update();
+
+        let cable = app.world().get::<Cable>(cable_entity).unwrap();
+        assert!(cable.generated);
+        assert!((cable.length() - 10.0).abs() < 0.001);
+
+        app.world_mut().entity_mut(to).insert(GlobalTransform::from_translation(Vec3::X * 20.0));
+        app.update();
+
+        let cable = app.world().get::<Cable>(cable_entity).unwrap();
+        assert!((cable.length() - 20.0).abs() < 0.001);
+        assert_eq!(app.world().resource::<Assets<Polyline>>().len(), 1);
+        let polyline_handle = app.world().get::<PolylineHandle>(cable_entity).unwrap();
+        let polyline = app.world().resource::<Assets<Polyline>>().get(&polyline_handle.0).unwrap();
+        assert_eq!(polyline.vertices.last(), Some(&(Vec3::X * 20.0)));
+    }
+
+    // editing the cable itself regenerates it as well
+    #[test]
+    fn test_cable_regenerates_when_hang_changes() {
+        let mut app = cable_test_app();
+        let (from, to) = spawn_some_connections(app.world_mut());
+        let cable_entity = spawn_cable(&mut app.world_mut().commands(), &from, &to, Some(0.0));
+        app.update();
+
+        app.world_mut().get_mut::<Cable>(cable_entity).unwrap().hang = 3.0;
+        app.update();
+
+        let cable = app.world().get::<Cable>(cable_entity).unwrap();
+        assert!(cable.length() > 10.5);
+    }
+
+    // despawning a tower takes its connections and every cable attached to them along
+    #[test]
+    fn test_cable_despawned_with_tower() {
+        let mut app = cable_test_app();
+        app
+            .add_observer(despawn_disconnected_cable::<StartsFrom>)
+            .add_observer(despawn_disconnected_cable::<EndsAt>);
+        let (from, to) = spawn_some_connections(app.world_mut());
+        let tower = app.world_mut().spawn(Transform::default()).add_child(to).id();
+        let cable_entity = spawn_cable(&mut app.world_mut().commands(), &from, &to, None);
+        app.update();
+        assert!(app.world().resource::<CableSpatialIndex>().contains(cable_entity));
+
+        app.world_mut().despawn(tower);
+        app.update();
+
+        assert!(app.world().get_entity(to).is_err());
+        assert!(app.world().get_entity(cable_entity).is_err());
+        assert!(!app.world().resource::<CableSpatialIndex>().contains(cable_entity));
+        assert!(app.world().get::<CablesStartingHere>(from).is_none_or(|cables| cables.collection().is_empty()));
+    }
+
+    #[test]
+    fn test_closest_point() {
+        let mut cable = Cable::default();
+        assert_eq!(cable.closest_point(Vec3::ZERO), None);
+
+        cable.set_segments(vec![Vec3::ZERO, Vec3::X * 10.0, Vec3::new(10.0, 0.0, 10.0)]);
+        let (along, distance) = cable.closest_point(Vec3::new(4.0, 3.0, 0.0)).unwrap();
+        assert!((along - 4.0).abs() < 0.001);
+        assert!((distance - 3.0).abs() < 0.001);
+
+        let (along, distance) = cable.closest_point(Vec3::new(12.0, 0.0, 5.0)).unwrap();
+        assert!((along - 15.0).abs() < 0.001);
+        assert!((distance - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_arc_length_queries() {
+        let mut cable = Cable::default();
+        cable.set_segments(vec![Vec3::ZERO, Vec3::new(3.0, 4.0, 0.0), Vec3::new(3.0, 4.0, 10.0)]);
+
+        assert!((cable.length() - 15.0).abs() < 0.001);
+        assert!((cable.pos_at_distance(0.0) - Vec3::ZERO).length() < 0.001);
+        assert!((cable.pos_at_distance(2.5) - Vec3::new(1.5, 2.0, 0.0)).length() < 0.001);
+        assert!((cable.pos_at_distance(10.0) - Vec3::new(3.0, 4.0, 5.0)).length() < 0.001);
+        assert!((cable.pos_at_distance(15.0) - Vec3::new(3.0, 4.0, 10.0)).length() < 0.001);
+
+        assert_eq!(cable.tangent_at_distance(1.0), Dir3::new(Vec3::new(3.0, 4.0, 0.0)).ok());
+        assert_eq!(cable.tangent_at_distance(12.0), Some(Dir3::Z));
+        assert_eq!(cable.end_direction(), Some(Dir3::Z));
+    }
+
+    // a cable that has not been generated yet has no length and no direction, but does not panic
+    #[test]
+    fn test_arc_length_ungenerated() {
+        let cable = Cable::default();
+        assert_eq!(cable.length(), 0.0);
+        assert_eq!(cable.start_direction(), None);
+        assert_eq!(cable.pos_at_distance(0.0), Vec3::ZERO);
+    }
 }
\ No newline at end of file
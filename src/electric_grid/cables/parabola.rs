@@ -12,8 +12,9 @@ pub(super) fn get_parabola(t: f32, start_pos: Vec3, end_pos: Vec3, hang: f32) ->
     let dx = dxz.length();
     let k = (dy+hang).max(hang);
 
+    // a cable without hang that does not go up is pulled straight, t is the fraction of the way to the end, not a distance
     if k == 0.0 {
-        return Some(start_pos.move_towards(end_pos, t))
+        return Some(start_pos.lerp(end_pos, t))
     }
 
     // compute 2d parabola  
@@ -84,6 +85,16 @@ mod tests {
 
     }
 
+    // a cable without hang going down is straight, and reaches its end however long it is
+    #[test]
+    fn test_no_hang() {
+        let (exp_start, exp_end) = (Vec3::new(0.0, 10.0, 0.0), Vec3::new(20.0, 4.0, 5.0));
+        for t in [0.0, 0.25, 0.5, 1.0] {
+            let point = get_parabola(t, exp_start, exp_end, 0.0).unwrap();
+            assert!((point - exp_start.lerp(exp_end, t)).length() < 0.001);
+        }
+    }
+
     // giving start and end positions with the same x and z components
     #[test]
     fn test_vertical() {
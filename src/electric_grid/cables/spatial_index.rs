@@ -0,0 +1,357 @@
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
+use super::*;
+
+// edge length of the grid cells in metres. cable segments are a few metres long, so most of them fall into a single cell.
+pub static DEFAULT_CELL_SIZE: f32 = 10.0;
+
+/*
+uniform grid over the segments of every generated cable, answering which cables are near a point or along a ray
+without going through every cable. it keeps its own copy of the cable geometry, updated whenever a cable is generated
+or removed, so queries only need the resource.
+*/
+#[derive(Resource)]
+pub struct CableSpatialIndex {
+    cell_size: f32,
+    // the segments overlapping each cell, as the cable they belong to and the index of their first point
+    cells: HashMap<IVec3, Vec<(Entity, usize)>>,
+    cables: HashMap<Entity, IndexedCable>,
+    // smallest and largest cell in use, never shrunk when cables are removed
+    bounds: Option<(IVec3, IVec3)>,
+}
+
+struct IndexedCable {
+    points: Vec<Vec3>,
+    arc_lengths: Vec<f32>,
+    cells: HashSet<IVec3>,
+}
+
+// a point on a cable found by a query
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CableHit {
+    pub cable: Entity,
+    // metres along the cable, measured from its start
+    pub along: f32,
+    pub position: Vec3,
+    // metres from the query point, or along the ray for ray queries
+    pub distance: f32,
+}
+
+impl Default for CableSpatialIndex {
+    fn default() -> Self { CableSpatialIndex::new(DEFAULT_CELL_SIZE) }
+}
+
+impl CableSpatialIndex {
+    pub fn new(cell_size: f32) -> Self {
+        CableSpatialIndex { cell_size, cells: HashMap::new(), cables: HashMap::new(), bounds: None }
+    }
+
+    // add the cable with the given points, replacing whatever was indexed for it before
+    pub fn insert(&mut self, cable_entity: Entity, points: &[Vec3]) {
+        self.remove(cable_entity);
+        let arc_lengths = std::iter::once(0.0)
+            .chain(points.windows(2).scan(0.0, |length, pair| {
+                *length += pair[0].distance(pair[1]);
+                Some(*length)
+            }))
+            .collect();
+        let mut cells = HashSet::new();
+        for (segment, pair) in points.windows(2).enumerate() {
+            let (min, max) = (self.cell_of(pair[0].min(pair[1])), self.cell_of(pair[0].max(pair[1])));
+            for cell in cells_between(min, max) {
+                self.cells.entry(cell).or_default().push((cable_entity, segment));
+                cells.insert(cell);
+            }
+            self.bounds = Some(match self.bounds {
+                Some((low, high)) => (low.min(min), high.max(max)),
+                None => (min, max),
+            });
+        }
+        self.cables.insert(cable_entity, IndexedCable { points: points.to_vec(), arc_lengths, cells });
+    }
+
+    pub fn remove(&mut self, cable_entity: Entity) {
+        let Some(indexed) = self.cables.remove(&cable_entity) else { return };
+        for cell in indexed.cells {
+            let Some(segments) = self.cells.get_mut(&cell) else { continue };
+            segments.retain(|(entity, _)| *entity != cable_entity);
+            if segments.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    pub fn contains(&self, cable_entity: Entity) -> bool {
+        self.cables.contains_key(&cable_entity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cables.is_empty()
+    }
+
+    // the closest point of any cable to the given point, no further than max_distance away
+    pub fn nearest(&self, point: Vec3, max_distance: f32) -> Option<CableHit> {
+        self.nearest_filtered(point, max_distance, |_| true)
+    }
+
+    // like nearest, only considering cables the filter accepts
+    pub fn nearest_filtered(&self, point: Vec3, max_distance: f32, filter: impl Fn(Entity) -> bool) -> Option<CableHit> {
+        let (low, high) = self.bounds?;
+        let center = self.cell_of(point);
+        // rings of cells around the point, searched outwards until no closer cable can be found in the next ring
+        let max_ring = (center - low).abs().max((high - center).abs()).max_element()
+            .min((max_distance / self.cell_size).ceil().min(i32::MAX as f32) as i32);
+        let mut best: Option<CableHit> = None;
+        let mut tested = HashSet::new();
+        for ring in 0..=max_ring {
+            // going through every cable is cheaper than visiting a huge ring of mostly empty cells
+            let ring_cells = (2 * ring as usize + 1).pow(3);
+            if ring_cells > self.cells.len() * 8 {
+                return self.closest_per_cable(point, self.cells.values().flatten(), &filter)
+                    .min_by(|a, b| a.distance.total_cmp(&b.distance))
+                    .filter(|hit| hit.distance <= max_distance);
+            }
+            let segments = cells_between(center - ring, center + ring)
+                .filter(|cell| (*cell - center).abs().max_element() == ring)
+                .filter_map(|cell| self.cells.get(&cell))
+                .flatten()
+                .filter(|segment| tested.insert(**segment));
+            for hit in self.closest_per_cable(point, segments, &filter) {
+                if best.is_none_or(|best| hit.distance < best.distance) {
+                    best = Some(hit);
+                }
+            }
+            // anything in the next ring is at least this far away
+            if best.is_some_and(|best| best.distance <= ring as f32 * self.cell_size) { break; }
+        }
+        best.filter(|hit| hit.distance <= max_distance)
+    }
+
+    // the closest point of every cable passing within the radius of the point, closest first
+    pub fn within(&self, point: Vec3, radius: f32) -> Vec<CableHit> {
+        let segments: HashSet<&(Entity, usize)> = cells_between(self.cell_of(point - radius), self.cell_of(point + radius))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .collect();
+        let mut hits: Vec<CableHit> = self.closest_per_cable(point, segments.into_iter(), &|_| true)
+            .filter(|hit| hit.distance <= radius)
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    // the first cable the ray passes within thickness of, up to max_distance along the ray.
+    // the thickness should not be larger than a cell.
+    pub fn raycast(&self, ray: Ray3d, max_distance: f32, thickness: f32) -> Option<CableHit> {
+        let (low, high) = self.bounds?;
+        // only the part of the ray crossing the indexed cells needs to be walked
+        let (bounds_min, bounds_max) = ((low - 1).as_vec3() * self.cell_size, (high + 2).as_vec3() * self.cell_size);
+        let (t_enter, t_exit) = ray_box(ray, bounds_min, bounds_max)?;
+        let t_end = t_exit.min(max_distance);
+        if t_enter > t_end { return None }
+
+        let dir = *ray.direction;
+        let mut t = t_enter;
+        let mut cell = self.cell_of(ray.get_point(t_enter));
+        let step = IVec3::new(dir.x.signum() as i32, dir.y.signum() as i32, dir.z.signum() as i32);
+        let next_boundary = |cell: IVec3, axis: usize| {
+            let boundary = (cell[axis] + (step[axis] > 0) as i32) as f32 * self.cell_size;
+            match dir[axis] == 0.0 {
+                true => f32::INFINITY,
+                false => (boundary - ray.origin[axis]) / dir[axis],
+            }
+        };
+        let mut tested = HashSet::new();
+        let mut best: Option<CableHit> = None;
+        // a hit found in a neighbouring cell may lie up to a cell diagonal further along the ray than the current cell
+        let reach = self.cell_size * 3f32.sqrt();
+
+        while t <= t_end && best.is_none_or(|best| best.distance + reach >= t) {
+            let segments = cells_between(cell - 1, cell + 1)
+                .filter_map(|cell| self.cells.get(&cell))
+                .flatten()
+                .filter(|segment| tested.insert(**segment));
+            for (cable_entity, segment) in segments {
+                let indexed = &self.cables[cable_entity];
+                let (a, b) = (indexed.points[*segment], indexed.points[*segment + 1]);
+                let (ray_t, fraction) = ray_segment_closest(ray, a, b);
+                let position = a.lerp(b, fraction);
+                if ray_t > max_distance || ray.get_point(ray_t).distance(position) > thickness { continue; }
+                if best.is_none_or(|best| ray_t < best.distance) {
+                    let along = indexed.arc_lengths[*segment] + fraction * a.distance(b);
+                    best = Some(CableHit { cable: *cable_entity, along, position, distance: ray_t });
+                }
+            }
+            // step into the neighbouring cell the ray crosses into first
+            let (axis, boundary) = (0..3)
+                .map(|axis| (axis, next_boundary(cell, axis)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            if !boundary.is_finite() { break; }
+            cell[axis] += step[axis];
+            t = boundary;
+        }
+        best
+    }
+
+    fn cell_of(&self, point: Vec3) -> IVec3 {
+        (point / self.cell_size).floor().as_ivec3()
+    }
+
+    // the closest point of each cable among the given segments
+    fn closest_per_cable<'a>(
+        &'a self,
+        point: Vec3,
+        segments: impl Iterator<Item = &'a (Entity, usize)>,
+        filter: &'a impl Fn(Entity) -> bool,
+    ) -> impl Iterator<Item = CableHit> + 'a {
+        let mut closest: HashMap<Entity, CableHit> = HashMap::new();
+        for (cable_entity, segment) in segments {
+            if !filter(*cable_entity) { continue; }
+            let indexed = &self.cables[cable_entity];
+            let (a, b) = (indexed.points[*segment], indexed.points[*segment + 1]);
+            let fraction = closest_fraction(point, a, b);
+            let position = a.lerp(b, fraction);
+            let hit = CableHit {
+                cable: *cable_entity,
+                along: indexed.arc_lengths[*segment] + fraction * a.distance(b),
+                position,
+                distance: point.distance(position),
+            };
+            if closest.get(cable_entity).is_none_or(|closest| hit.distance < closest.distance) {
+                closest.insert(*cable_entity, hit);
+            }
+        }
+        closest.into_values()
+    }
+}
+
+fn cells_between(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    (min.x..=max.x).flat_map(move |x| (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z))))
+}
+
+// fraction along the segment from a to b of the point closest to p
+fn closest_fraction(p: Vec3, a: Vec3, b: Vec3) -> f32 {
+    let segment = b - a;
+    match segment.length_squared() > 0.0 {
+        true => ((p - a).dot(segment) / segment.length_squared()).clamp(0.0, 1.0),
+        false => 0.0,
+    }
+}
+
+// distance along the ray and fraction along the segment of the closest approach between the two
+fn ray_segment_closest(ray: Ray3d, a: Vec3, b: Vec3) -> (f32, f32) {
+    let (dir, segment, offset) = (*ray.direction, b - a, ray.origin - a);
+    let (dir_segment, segment_length_squared) = (dir.dot(segment), segment.length_squared());
+    let denominator = segment_length_squared - dir_segment * dir_segment;
+    // for parallel lines any point works, start from the segment's start
+    let mut fraction = match denominator > 1e-6 && segment_length_squared > 0.0 {
+        true => ((segment.dot(offset) - dir_segment * dir.dot(offset)) / denominator).clamp(0.0, 1.0),
+        false => 0.0,
+    };
+    // clamping the fraction moves the closest point on the ray, and clamping the ray in turn moves the closest point on the segment
+    let ray_t = (a + segment * fraction - ray.origin).dot(dir).max(0.0);
+    fraction = closest_fraction(ray.get_point(ray_t), a, b);
+    ((a + segment * fraction - ray.origin).dot(dir).max(0.0), fraction)
+}
+
+// distances along the ray at which it enters and leaves the box, None if it misses it
+fn ray_box(ray: Ray3d, min: Vec3, max: Vec3) -> Option<(f32, f32)> {
+    let inverse = ray.direction.recip();
+    let (t1, t2) = ((min - ray.origin) * inverse, (max - ray.origin) * inverse);
+    // nan from a zero direction component with the origin on a slab boundary counts as inside
+    let (t_min, t_max) = (t1.min(t2), t1.max(t2));
+    let enter = [t_min.x, t_min.y, t_min.z].into_iter().filter(|t| !t.is_nan()).fold(0.0, f32::max);
+    let exit = [t_max.x, t_max.y, t_max.z].into_iter().filter(|t| !t.is_nan()).fold(f32::INFINITY, f32::min);
+    (enter <= exit).then_some((enter, exit))
+}
+
+pub(super) fn index_generated_cable(
+    trigger: On<CableGenerated>,
+    cables: Query<&Cable>,
+    mut index: ResMut<CableSpatialIndex>,
+) {
+    let Ok(cable) = cables.get(trigger.entity) else { return };
+    index.insert(trigger.entity, cable.points());
+}
+
+pub(super) fn unindex_removed_cable(
+    trigger: On<Remove, Cable>,
+    mut index: ResMut<CableSpatialIndex>,
+) {
+    index.remove(trigger.entity);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    fn entity(index: u32) -> Entity {
+        Entity::from_raw_u32(index).unwrap()
+    }
+
+    // a few parallel cables 5 metres apart, plus one far away
+    fn test_index() -> CableSpatialIndex {
+        let mut index = CableSpatialIndex::default();
+        for (cable, z) in [(1, 0.0), (2, 5.0), (3, 10.0)] {
+            let points: Vec<Vec3> = (0..=10).map(|i| Vec3::new(i as f32 * 5.0, 20.0, z)).collect();
+            index.insert(entity(cable), &points);
+        }
+        index.insert(entity(4), &[Vec3::new(500.0, 0.0, 500.0), Vec3::new(520.0, 0.0, 500.0)]);
+        index
+    }
+
+    // -- basic --
+    #[test]
+    fn test_nearest() {
+        let index = test_index();
+        let hit = index.nearest(Vec3::new(12.0, 20.0, 6.0), f32::INFINITY).unwrap();
+        assert_eq!(hit.cable, entity(2));
+        assert!((hit.along - 12.0).abs() < 0.001);
+        assert!((hit.distance - 1.0).abs() < 0.001);
+
+        // far from everything, the search widens until it finds the lone cable
+        let hit = index.nearest(Vec3::new(510.0, 3.0, 490.0), f32::INFINITY).unwrap();
+        assert_eq!(hit.cable, entity(4));
+        assert!(index.nearest(Vec3::new(510.0, 3.0, 490.0), 5.0).is_none());
+        assert_eq!(index.nearest_filtered(Vec3::new(12.0, 20.0, 6.0), f32::INFINITY, |cable| cable != entity(2)).unwrap().cable, entity(3));
+    }
+
+    #[test]
+    fn test_within() {
+        let index = test_index();
+        let hits = index.within(Vec3::new(20.0, 20.0, 1.0), 5.0);
+        assert_eq!(hits.iter().map(|hit| hit.cable).collect::<Vec<_>>(), vec![entity(1), entity(2)]);
+        assert!((hits[1].distance - 4.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_raycast() {
+        let index = test_index();
+        // looking down the row of cables from the side hits the closest one first
+        let ray = Ray3d::new(Vec3::new(30.0, 20.0, -40.0), Dir3::Z);
+        let hit = index.raycast(ray, f32::INFINITY, 0.5).unwrap();
+        assert_eq!(hit.cable, entity(1));
+        assert!((hit.distance - 40.0).abs() < 0.001);
+        assert!((hit.along - 30.0).abs() < 0.001);
+
+        // passing above all of them
+        assert!(index.raycast(Ray3d::new(Vec3::new(30.0, 25.0, -40.0), Dir3::Z), f32::INFINITY, 0.5).is_none());
+        assert!(index.raycast(ray, 30.0, 0.5).is_none());
+    }
+
+    // -- edge cases --
+    // regenerating a cable replaces its old geometry, removing it takes it out of every query
+    #[test]
+    fn test_reinsert_and_remove() {
+        let mut index = test_index();
+        index.insert(entity(1), &[Vec3::new(0.0, 100.0, 0.0), Vec3::new(50.0, 100.0, 0.0)]);
+        assert_eq!(index.nearest(Vec3::new(20.0, 20.0, -1.0), f32::INFINITY).unwrap().cable, entity(2));
+        assert_eq!(index.nearest(Vec3::new(20.0, 99.0, 0.0), f32::INFINITY).unwrap().cable, entity(1));
+
+        index.remove(entity(1));
+        assert!(!index.contains(entity(1)));
+        assert_eq!(index.nearest(Vec3::new(20.0, 99.0, 0.0), f32::INFINITY).unwrap().cable, entity(2));
+        assert!(CableSpatialIndex::default().nearest(Vec3::ZERO, f32::INFINITY).is_none());
+    }
+}
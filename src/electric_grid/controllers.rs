@@ -0,0 +1,299 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use super::cables::*;
+use super::junctions::*;
+use super::spark_movement::*;
+
+use crate::actions::*;
+
+pub struct ControllersPlugin;
+impl Plugin for ControllersPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_systems(Update, (
+            (device_throttle, scripted_throttle, ai_throttle),
+            choose_junction_controller,
+        ).before(move_spark));
+    }
+}
+
+/*
+what drives a spark. every spark is moved each frame by its throttle, which its controller sets:
+keyboard and gamepad sparks follow the player's input, scripted sparks follow a route of cables and ai sparks roam on their own.
+sparks with the Controller junction policy leave decisions at junctions to their controller as well,
+and so do scripted and ai sparks with the Player policy, as there is no player to decide for them.
+*/
+#[derive(Component, Clone, Default, Debug)]
+#[require(SparkThrottle)]
+pub enum SparkController {
+    #[default]
+    Keyboard,
+    Gamepad(Entity),
+    Scripted(ScriptedRoute),
+    Ai(AiRoamer),
+}
+
+impl SparkController {
+    // driven by a player through an input device
+    pub fn is_device(&self) -> bool {
+        matches!(self, SparkController::Keyboard | SparkController::Gamepad(_))
+    }
+}
+
+// how fast the spark wants to move along its cable, from -1 towards its start to 1 towards its end
+#[derive(Component, Clone, Copy, Default, PartialEq, Debug)]
+pub struct SparkThrottle(pub f32);
+
+// the cables a scripted spark rides, in order. it stops at the far end of the last one.
+#[derive(Clone, Default, Debug)]
+pub struct ScriptedRoute {
+    pub cables: Vec<Entity>,
+}
+
+impl ScriptedRoute {
+    // the cable after the one the spark is on, None once it is on the last cable or off the route
+    pub fn next_after(&self, cable: Entity) -> Option<Entity> {
+        let index = self.cables.iter().position(|route_cable| *route_cable == cable)?;
+        self.cables.get(index + 1).copied()
+    }
+}
+
+// keeps going one way and turns around at the end of the line
+#[derive(Clone, Copy, Debug)]
+pub struct AiRoamer {
+    pub direction: f32,
+}
+
+impl Default for AiRoamer {
+    fn default() -> Self {
+        AiRoamer { direction: 1.0 }
+    }
+}
+
+// the actions of the devices controlling player sparks
+#[derive(SystemParam)]
+pub(crate) struct ControllerActions<'w> {
+    actions: Option<Res<'w, Actions>>,
+}
+
+impl ControllerActions<'_> {
+    // the actions of the spark's keyboard or gamepad, None for sparks not controlled by a device
+    pub fn of(&self, controller: &SparkController) -> Option<&ActionState> {
+        let actions = self.actions.as_ref()?;
+        match controller {
+            SparkController::Keyboard => Some(&actions.keyboard),
+            SparkController::Gamepad(gamepad) => actions.gamepad(*gamepad),
+            _ => None,
+        }
+    }
+
+    pub fn just_pressed(&self, controller: &SparkController, action: Action) -> bool {
+        self.of(controller).is_some_and(|state| state.just_pressed(action))
+    }
+
+    pub fn pressed(&self, controller: &SparkController, action: Action) -> bool {
+        self.of(controller).is_some_and(|state| state.pressed(action))
+    }
+
+    // forward against back, as far as they are pushed
+    fn throttle(&self, controller: &SparkController) -> f32 {
+        self.of(controller).map_or(0.0, |state| state.value(Action::Forward) - state.value(Action::Back))
+    }
+}
+
+fn device_throttle(
+    mut sparks: Query<(&SparkController, &mut SparkThrottle)>,
+    input: ControllerActions,
+) {
+    for (controller, mut throttle) in &mut sparks {
+        if matches!(controller, SparkController::Keyboard | SparkController::Gamepad(_)) {
+            throttle.0 = input.throttle(controller);
+        }
+    }
+}
+
+// which way to ride the cable to get to the next one on the route: towards the end it shares with it
+pub(crate) fn route_throttle(route: &ScriptedRoute, cable: Entity, cables: &Query<(&StartsFrom, &EndsAt)>) -> f32 {
+    let Some(next) = route.next_after(cable) else {
+        // last cable of the route, ride it to its end
+        return match route.cables.last() == Some(&cable) {
+            true => 1.0,
+            false => 0.0,
+        };
+    };
+    let (Ok((_, end)), Ok((next_start, next_end))) = (cables.get(cable), cables.get(next)) else { return 0.0 };
+    match end.0 == next_start.0 || end.0 == next_end.0 {
+        true => 1.0,
+        false => -1.0,
+    }
+}
+
+fn scripted_throttle(
+    mut sparks: Query<(&Spark, &SparkController, &mut SparkThrottle)>,
+    cables: Query<(&StartsFrom, &EndsAt)>,
+) {
+    for (spark, controller, mut throttle) in &mut sparks {
+        let SparkController::Scripted(route) = controller else { continue };
+        throttle.0 = route_throttle(route, spark.connected_to_cable_entity, &cables);
+    }
+}
+
+// turn around when stuck at the end of the line
+fn ai_throttle(
+    mut sparks: Query<(&Spark, &mut SparkController, &mut SparkThrottle), Without<AtJunction>>,
+    cables: Query<&Cable>,
+) {
+    for (spark, mut controller, mut throttle) in &mut sparks {
+        let SparkController::Ai(roamer) = controller.as_mut() else { continue };
+        let Ok(cable) = cables.get(spark.connected_to_cable_entity) else { continue };
+        let at_end = match roamer.direction > 0.0 {
+            true => spark.dist_along >= cable.length(),
+            false => spark.dist_along <= 0.0,
+        };
+        if at_end {
+            roamer.direction = -roamer.direction;
+        }
+        throttle.0 = roamer.direction;
+    }
+}
+
+// scripted sparks take the next cable of their route, ai sparks the straightest way
+fn choose_junction_controller(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut AtJunction, &JunctionPolicy, &SparkController)>,
+    cables: Query<&Cable>,
+) {
+    for (spark_entity, mut spark, mut junction, policy, controller) in &mut sparks {
+        let decides = match policy {
+            JunctionPolicy::Controller => true,
+            JunctionPolicy::Player => !controller.is_device(),
+            _ => false,
+        };
+        if !decides { continue; }
+        let selected = match controller {
+            SparkController::Scripted(route) => route.next_after(spark.connected_to_cable_entity)
+                .and_then(|next| junction.choices.iter().position(|choice| choice.cable == next)),
+            SparkController::Ai(_) => straightest_choice(junction.incoming, &junction.choices),
+            _ => None,
+        };
+        match selected {
+            Some(selected) => {
+                junction.selected = selected;
+                commit_choice(&mut commands, spark_entity, &mut spark, &junction, &cables);
+            },
+            // off the route, or a controller that cannot decide, the spark turns back
+            None => {
+                commands.entity(spark_entity).remove::<AtJunction>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
+    // -- basic --
+    // every spark is moved by its own throttle
+    #[test]
+    fn test_all_sparks_move() {
+        let mut world = World::new();
+        let mut time = Time::<()>::default();
+        time.advance_by(std::time::Duration::from_secs(1));
+        world.insert_resource(time);
+        let (a, b, c) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let first = spawn_generated_cable(&mut world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let second = spawn_generated_cable(&mut world, b, c, vec![Vec3::Z * 10.0, Vec3::new(10.0, 0.0, 10.0)]);
+        let forward = world.spawn((Spark::new(first, 2.0), SparkThrottle(1.0))).id();
+        let mut backward = Spark::new(second, 3.0);
+        backward.dist_along = 5.0;
+        let backward = world.spawn((backward, SparkThrottle(-1.0))).id();
+
+        world.run_system_once(move_spark).unwrap();
+        assert_eq!(world.get::<Spark>(forward).unwrap().dist_along, 2.0);
+        assert_eq!(world.get::<Spark>(backward).unwrap().dist_along, 2.0);
+    }
+
+    // a scripted spark rides each cable towards the next one of its route, whichever way around it is attached
+    #[test]
+    fn test_route_throttle() {
+        let mut world = World::new();
+        let (a, b, c) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let first = spawn_generated_cable(&mut world, b, a, vec![Vec3::X * 10.0, Vec3::ZERO]);
+        let second = spawn_generated_cable(&mut world, b, c, vec![Vec3::X * 10.0, Vec3::X * 20.0]);
+        let route = ScriptedRoute { cables: vec![first, second] };
+
+        let throttles = world.run_system_once(move |cables: Query<(&StartsFrom, &EndsAt)>| {
+            (route_throttle(&route, first, &cables), route_throttle(&route, second, &cables), route_throttle(&route, a, &cables))
+        }).unwrap();
+        assert_eq!(throttles, (-1.0, 1.0, 0.0));
+    }
+
+    // at a junction, a scripted spark takes the cable its route continues on
+    #[test]
+    fn test_scripted_junction() {
+        let mut world = World::new();
+        let (a, b) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let incoming = spawn_generated_cable(&mut world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let outgoing: Vec<Entity> = [Vec3::new(20.0, 0.0, 5.0), Vec3::new(20.0, 0.0, -5.0)].iter()
+            .map(|end| {
+                let end_connection = world.spawn(CableConnection::default()).id();
+                spawn_generated_cable(&mut world, b, end_connection, vec![Vec3::X * 10.0, *end])
+            })
+            .collect();
+        let route = SparkController::Scripted(ScriptedRoute { cables: vec![incoming, outgoing[1]] });
+        let choices = outgoing.iter().map(|cable| JunctionChoice { cable: *cable, direction: Dir3::X, travel: Travel::Forward, voltage: SPARK_VOLTAGE, phase: None }).collect();
+        let spark = world.spawn((
+            Spark::new(incoming, 1.0),
+            JunctionPolicy::Controller,
+            route,
+            AtJunction::new(b, Travel::Forward, Dir3::X, choices, 0.5),
+        )).id();
+
+        world.run_system_once(choose_junction_controller).unwrap();
+        assert_eq!(world.get::<Spark>(spark).unwrap().connected_to_cable_entity, outgoing[1]);
+        assert!(world.get::<AtJunction>(spark).is_none());
+    }
+
+    // -- edge cases --
+    // an ai spark turns around at the end of the line
+    #[test]
+    fn test_ai_turns_around() {
+        let mut world = World::new();
+        let (a, b) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let cable = spawn_generated_cable(&mut world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let mut spark = Spark::new(cable, 1.0);
+        spark.dist_along = 10.0;
+        let spark = world.spawn((spark, SparkController::Ai(default()))).id();
+
+        world.run_system_once(ai_throttle).unwrap();
+        assert_eq!(world.get::<SparkThrottle>(spark).unwrap().0, -1.0);
+    }
+
+    // an ai spark left on the default policy still gets through a fork, taking the straightest way
+    #[test]
+    fn test_ai_default_policy_junction() {
+        let mut world = World::new();
+        let (a, b) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let incoming = spawn_generated_cable(&mut world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let outgoing: Vec<Entity> = [Vec3::new(20.0, 0.0, 10.0), Vec3::new(20.0, 0.0, 1.0)].iter()
+            .map(|end| {
+                let end_connection = world.spawn(CableConnection::default()).id();
+                spawn_generated_cable(&mut world, b, end_connection, vec![Vec3::X * 10.0, *end])
+            })
+            .collect();
+        let choices = outgoing.iter().zip([Vec3::new(10.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 1.0)])
+            .map(|(cable, direction)| JunctionChoice { cable: *cable, direction: Dir3::new(direction).unwrap(), travel: Travel::Forward, voltage: SPARK_VOLTAGE, phase: None })
+            .collect();
+        let spark = world.spawn((
+            Spark::new(incoming, 1.0),
+            JunctionPolicy::default(),
+            SparkController::Ai(default()),
+            AtJunction::new(b, Travel::Forward, Dir3::X, choices, 0.5),
+        )).id();
+
+        world.run_system_once(choose_junction_controller).unwrap();
+        assert_eq!(world.get::<Spark>(spark).unwrap().connected_to_cable_entity, outgoing[1]);
+        assert!(world.get::<AtJunction>(spark).is_none());
+    }
+}
@@ -0,0 +1,437 @@
+use bevy::{platform::collections::HashSet, prelude::*};
+use bevy_polyline::prelude::*;
+use super::breakers::*;
+use super::cables::*;
+use super::grid_graph::*;
+use super::power_flow::*;
+use super::Tower;
+
+// color dark cables are drawn in
+static BLACKOUT_COLOR: LinearRgba = LinearRgba { red: 0.02, green: 0.02, blue: 0.02, alpha: 1.0 };
+
+pub struct FaultsPlugin;
+impl Plugin for FaultsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<GridTimeline>()
+        .add_observer(apply_fault)
+        .add_observer(repair_fault)
+        .add_observer(reclose_onto_fault)
+        .add_observer(paint_blackout)
+        .add_observer(paint_restored)
+        .add_observer(repaint_regenerated_cable)
+        .add_systems(Update, update_blackout.run_if(grid_changed));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Reflect)]
+pub enum FaultKind {
+    // the conductors touch each other or the ground
+    ShortCircuit,
+    // the cable broke and fell
+    LineDown,
+}
+
+/*
+triggered on a cable to make it fail. the breakers closest to it on every side trip to isolate it,
+and until they do, everything downstream of the fault goes dark with it.
+past the breakers, whatever is cut off from every supply stays dark as well.
+*/
+#[derive(EntityEvent, Debug)]
+pub struct Fault {
+    pub entity: Entity,
+    pub kind: FaultKind,
+}
+
+// triggered on a faulted cable to fix it. power only comes back once the breakers that tripped are closed again.
+#[derive(EntityEvent, Debug)]
+pub struct Repair {
+    pub entity: Entity,
+}
+
+// a cable that has failed and has not been repaired yet
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Faulted {
+    pub kind: FaultKind,
+}
+
+// a cable, connection or tower without power
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct Blackout;
+
+// triggered as it is recorded on the timeline, so anything can react to what happens to the grid
+#[derive(Event, Clone, Copy, PartialEq, Debug)]
+pub enum GridEvent {
+    Fault { cable: Entity, kind: FaultKind },
+    Repaired { cable: Entity },
+    BreakerSwitched { breaker: Entity, to: BreakerState },
+    // the number of cables that lost or got back power
+    Blackout { cables: usize },
+    Restored { cables: usize },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimelineEntry {
+    // seconds since startup
+    pub time: f32,
+    pub event: GridEvent,
+}
+
+// everything that happened to the grid, oldest first, for scripted sequences to react to
+#[derive(Resource, Default, Debug)]
+pub struct GridTimeline {
+    pub entries: Vec<TimelineEntry>,
+}
+
+impl GridTimeline {
+    pub fn record(&mut self, time: f32, event: GridEvent) {
+        self.entries.push(TimelineEntry { time, event });
+    }
+
+    // entries recorded at or after the given time
+    pub fn since(&self, time: f32) -> &[TimelineEntry] {
+        let start = self.entries.partition_point(|entry| entry.time < time);
+        &self.entries[start..]
+    }
+}
+
+// record the event on the timeline and trigger it
+fn record(commands: &mut Commands, timeline: &mut GridTimeline, time: &Time, event: GridEvent) {
+    timeline.record(time.elapsed_secs(), event);
+    commands.trigger(event);
+}
+
+// the breakers closest to the cable on every side, the ones that have to be open for the cable to be isolated
+pub fn protection_breakers(graph: &GridGraph, cable: Entity, has_breaker: impl Fn(Entity) -> bool) -> Vec<Entity> {
+    if has_breaker(cable) { return vec![cable] }
+    let Some(edge) = graph.edge(cable) else { return Vec::new() };
+    let mut breakers = Vec::new();
+    let mut visited: HashSet<Entity> = HashSet::from_iter([cable]);
+    let mut stack = vec![edge.from, edge.to];
+    while let Some(connection) = stack.pop() {
+        if !visited.insert(connection) { continue }
+        if has_breaker(connection) {
+            breakers.push(connection);
+            continue;
+        }
+        for (next_cable, next_connection) in graph.neighbours(connection) {
+            if !visited.insert(next_cable) { continue }
+            match has_breaker(next_cable) {
+                true => breakers.push(next_cable),
+                false => stack.push(next_connection),
+            }
+        }
+    }
+    breakers
+}
+
+/*
+cables and connections without power. faults not isolated by open breakers pull down everything downstream of them,
+following cables from their start to their end, the rest gets power from the supplies through conducting cables and connections that are not faulted.
+*/
+pub fn dark_region(graph: &GridGraph, supplies: &[Entity], conducts: impl Fn(Entity) -> bool, faulted: &HashSet<Entity>) -> HashSet<Entity> {
+    // flood from the given starts through everything that conducts and is not blocked, only from the start of cables to their end if downstream
+    let flood = |starts: Vec<Entity>, blocked: &HashSet<Entity>, downstream: bool| {
+        let mut reached = HashSet::new();
+        let mut stack: Vec<Entity> = starts.into_iter().filter(|start| conducts(*start) && !blocked.contains(start)).collect();
+        while let Some(connection) = stack.pop() {
+            if !reached.insert(connection) { continue }
+            for (cable, next) in graph.neighbours(connection) {
+                if !conducts(cable) || blocked.contains(&cable) { continue }
+                if downstream && graph.edge(cable).is_none_or(|edge| edge.from != connection) { continue }
+                reached.insert(cable);
+                if conducts(next) && !blocked.contains(&next) {
+                    stack.push(next);
+                }
+            }
+        }
+        reached
+    };
+
+    let mut shorted = HashSet::new();
+    for cable in faulted.iter().filter(|cable| conducts(**cable)) {
+        let Some(edge) = graph.edge(*cable) else { continue };
+        shorted.insert(*cable);
+        shorted.extend(flood(vec![edge.to], &HashSet::new(), true));
+    }
+    let mut blocked = shorted;
+    blocked.extend(faulted.iter().copied());
+    let energized = flood(supplies.to_vec(), &blocked, false);
+
+    graph.nodes().chain(graph.edges().map(|(cable, _)| cable))
+        .filter(|entity| !energized.contains(entity))
+        .collect()
+}
+
+// where power comes from: the generators, or the start of every line if the grid has none
+pub fn supplies(graph: &GridGraph, generators: impl Iterator<Item = Entity>) -> Vec<Entity> {
+    let generators: Vec<Entity> = generators.filter(|generator| graph.contains_node(*generator)).collect();
+    if !generators.is_empty() { return generators }
+    graph.nodes()
+        .filter(|connection| graph.degree(*connection) > 0)
+        .filter(|connection| graph.neighbours(*connection).all(|(cable, _)| graph.edge(cable).is_some_and(|edge| edge.from == *connection)))
+        .collect()
+}
+
+// trip every closed breaker that protects the cable
+fn protect(commands: &mut Commands, graph: &GridGraph, cable: Entity, breakers: &mut Query<&mut Breaker>) {
+    for breaker_entity in protection_breakers(graph, cable, |entity| breakers.contains(entity)) {
+        let Ok(mut breaker) = breakers.get_mut(breaker_entity) else { continue };
+        if breaker.state.conducts() {
+            switch_breaker(commands, breaker_entity, &mut breaker, BreakerState::Tripped);
+        }
+    }
+}
+
+fn apply_fault(
+    trigger: On<Fault>,
+    mut commands: Commands,
+    graph: Res<GridGraph>,
+    mut breakers: Query<&mut Breaker>,
+    mut timeline: ResMut<GridTimeline>,
+    time: Res<Time>,
+) {
+    info!("{:?} on cable {}", trigger.kind, trigger.entity);
+    commands.entity(trigger.entity).try_insert(Faulted { kind: trigger.kind });
+    record(&mut commands, &mut timeline, &time, GridEvent::Fault { cable: trigger.entity, kind: trigger.kind });
+    protect(&mut commands, &graph, trigger.entity, &mut breakers);
+}
+
+fn repair_fault(
+    trigger: On<Repair>,
+    mut commands: Commands,
+    faulted: Query<(), With<Faulted>>,
+    mut timeline: ResMut<GridTimeline>,
+    time: Res<Time>,
+) {
+    if !faulted.contains(trigger.entity) { return }
+    commands.entity(trigger.entity).remove::<Faulted>();
+    record(&mut commands, &mut timeline, &time, GridEvent::Repaired { cable: trigger.entity });
+}
+
+// closing a breaker that still has a fault behind it trips it again right away, so faults have to be repaired first
+fn reclose_onto_fault(
+    trigger: On<BreakerSwitched>,
+    mut commands: Commands,
+    graph: Res<GridGraph>,
+    mut breakers: Query<&mut Breaker>,
+    faulted: Query<Entity, With<Faulted>>,
+    mut timeline: ResMut<GridTimeline>,
+    time: Res<Time>,
+) {
+    record(&mut commands, &mut timeline, &time, GridEvent::BreakerSwitched { breaker: trigger.entity, to: trigger.to });
+    if trigger.to != BreakerState::Closed { return }
+    for cable in &faulted {
+        protect(&mut commands, &graph, cable, &mut breakers);
+    }
+}
+
+// whether anything the dark region depends on changed since the last frame
+#[allow(clippy::type_complexity)]
+fn grid_changed(
+    graph: Res<GridGraph>,
+    changed: Query<(), Or<(Changed<Breaker>, Changed<Faulted>, Changed<Generator>)>>,
+    mut repaired: RemovedComponents<Faulted>,
+    mut removed_breakers: RemovedComponents<Breaker>,
+    mut removed_generators: RemovedComponents<Generator>,
+) -> bool {
+    // every removal has to be read, so it is not seen again next frame
+    let removed = repaired.read().count() + removed_breakers.read().count() + removed_generators.read().count();
+    graph.is_changed() || !changed.is_empty() || removed > 0
+}
+
+// keep the Blackout markers in sync with the state of the grid. towers are dark when any of their connections is.
+#[allow(clippy::too_many_arguments)]
+fn update_blackout(
+    mut commands: Commands,
+    graph: Res<GridGraph>,
+    breakers: Query<&Breaker>,
+    faulted: Query<Entity, With<Faulted>>,
+    generators: Query<Entity, With<Generator>>,
+    dark: Query<Entity, With<Blackout>>,
+    cables: Query<(), With<Cable>>,
+    ancestors: Query<&ChildOf>,
+    towers: Query<(), With<Tower>>,
+    mut timeline: ResMut<GridTimeline>,
+    time: Res<Time>,
+) {
+    let supplies = supplies(&graph, generators.iter());
+    let faulted: HashSet<Entity> = faulted.iter().collect();
+    let mut now_dark = dark_region(&graph, &supplies, |entity| conducts(&breakers, entity), &faulted);
+    let dark_connections: Vec<Entity> = now_dark.iter().copied().filter(|entity| graph.contains_node(*entity)).collect();
+    for connection in dark_connections {
+        now_dark.extend(ancestors.iter_ancestors(connection).filter(|ancestor| towers.contains(*ancestor)));
+    }
+
+    let was_dark: HashSet<Entity> = dark.iter().collect();
+    let (mut lost, mut restored) = (0, 0);
+    for entity in now_dark.difference(&was_dark) {
+        commands.entity(*entity).try_insert(Blackout);
+        lost += cables.contains(*entity) as usize;
+    }
+    for entity in was_dark.difference(&now_dark) {
+        commands.entity(*entity).try_remove::<Blackout>();
+        restored += cables.contains(*entity) as usize;
+    }
+    if lost > 0 {
+        record(&mut commands, &mut timeline, &time, GridEvent::Blackout { cables: lost });
+    }
+    if restored > 0 {
+        record(&mut commands, &mut timeline, &time, GridEvent::Restored { cables: restored });
+    }
+}
+
+fn set_cable_material_color(entity: Entity, color: LinearRgba, materials: &Query<&PolylineMaterialHandle>, polyline_materials: &mut Assets<PolylineMaterial>) {
+    if let Some(material) = materials.get(entity).ok().and_then(|handle| polyline_materials.get_mut(&handle.0)) {
+        material.color = color;
+    }
+}
+
+fn paint_blackout(
+    trigger: On<Add, Blackout>,
+    materials: Query<&PolylineMaterialHandle>,
+    mut polyline_materials: ResMut<Assets<PolylineMaterial>>,
+) {
+    set_cable_material_color(trigger.entity, BLACKOUT_COLOR, &materials, &mut polyline_materials);
+}
+
+fn paint_restored(
+    trigger: On<Remove, Blackout>,
+    cables: Query<&Cable>,
+    materials: Query<&PolylineMaterialHandle>,
+    mut polyline_materials: ResMut<Assets<PolylineMaterial>>,
+) {
+    let Ok(cable) = cables.get(trigger.entity) else { return };
+    set_cable_material_color(trigger.entity, cable.color, &materials, &mut polyline_materials);
+}
+
+// regenerating a cable resets its color, dark cables have to be painted again
+fn repaint_regenerated_cable(
+    trigger: On<CableGenerated>,
+    dark: Query<(), With<Blackout>>,
+    materials: Query<&PolylineMaterialHandle>,
+    mut polyline_materials: ResMut<Assets<PolylineMaterial>>,
+) {
+    if dark.contains(trigger.entity) {
+        set_cable_material_color(trigger.entity, BLACKOUT_COLOR, &materials, &mut polyline_materials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    struct TestLine {
+        connections: Vec<Entity>,
+        cables: Vec<Entity>,
+    }
+
+    // a straight line of cables from the first connection to the last
+    fn spawn_line(world: &mut World, length: usize) -> TestLine {
+        let connections: Vec<Entity> = (0..length).map(|_| world.spawn(CableConnection::default()).id()).collect();
+        let cables = connections.windows(2)
+            .map(|pair| spawn_generated_cable(world, pair[0], pair[1], vec![Vec3::ZERO, Vec3::X * 10.0]))
+            .collect();
+        world.flush();
+        TestLine { connections, cables }
+    }
+
+    // every grid event triggered, in order
+    #[derive(Resource, Default)]
+    struct Observed(Vec<GridEvent>);
+
+    fn faults_test_app() -> App {
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .init_resource::<Assets<PolylineMaterial>>()
+            .init_resource::<Observed>()
+            .add_plugins((GridGraphPlugin, FaultsPlugin))
+            .add_observer(|event: On<GridEvent>, mut observed: ResMut<Observed>| observed.0.push(*event));
+        app
+    }
+
+    fn is_dark(app: &App, entity: Entity) -> bool {
+        app.world().get::<Blackout>(entity).is_some()
+    }
+
+    // -- basic --
+    // without a breaker to isolate it, a fault takes the line down from where it is, the supply side stays powered
+    #[test]
+    fn test_unprotected_fault_darkens_downstream() {
+        let mut app = faults_test_app();
+        let line = spawn_line(app.world_mut(), 5);
+        app.update();
+        assert!(!line.cables.iter().any(|cable| is_dark(&app, *cable)));
+
+        app.world_mut().trigger(Fault { entity: line.cables[2], kind: FaultKind::ShortCircuit });
+        app.update();
+        assert!(line.cables[2..].iter().chain(&line.connections[3..]).all(|entity| is_dark(&app, *entity)));
+        assert!(!line.cables[..2].iter().chain(&line.connections[..3]).any(|entity| is_dark(&app, *entity)));
+    }
+
+    // a breaker between the supply and the fault trips, keeping the line up to it powered
+    #[test]
+    fn test_breaker_isolates_fault() {
+        let mut app = faults_test_app();
+        let line = spawn_line(app.world_mut(), 5);
+        app.world_mut().entity_mut(line.connections[1]).insert(Breaker::default());
+        app.world_mut().trigger(Fault { entity: line.cables[2], kind: FaultKind::LineDown });
+        app.update();
+
+        assert_eq!(app.world().get::<Breaker>(line.connections[1]).unwrap().state, BreakerState::Tripped);
+        assert!(!is_dark(&app, line.connections[0]) && !is_dark(&app, line.cables[0]));
+        assert!(line.cables[1..].iter().all(|cable| is_dark(&app, *cable)));
+
+        let events: Vec<GridEvent> = app.world().resource::<GridTimeline>().entries.iter().map(|entry| entry.event).collect();
+        assert_eq!(app.world().resource::<Observed>().0, events);
+        assert_eq!(events, vec![
+            GridEvent::Fault { cable: line.cables[2], kind: FaultKind::LineDown },
+            GridEvent::BreakerSwitched { breaker: line.connections[1], to: BreakerState::Tripped },
+            GridEvent::Blackout { cables: 3 },
+        ]);
+    }
+
+    // closing the breaker before the repair trips it again, after the repair power comes back
+    #[test]
+    fn test_restoration_sequence() {
+        let mut app = faults_test_app();
+        let line = spawn_line(app.world_mut(), 4);
+        app.world_mut().entity_mut(line.cables[1]).insert(Breaker::default());
+        app.world_mut().trigger(Fault { entity: line.cables[2], kind: FaultKind::ShortCircuit });
+        app.update();
+
+        let close = |app: &mut App| {
+            let world = app.world_mut();
+            let mut breaker = *world.get::<Breaker>(line.cables[1]).unwrap();
+            switch_breaker(&mut world.commands(), line.cables[1], &mut breaker, BreakerState::Closed);
+            world.entity_mut(line.cables[1]).insert(breaker);
+            world.flush();
+        };
+        close(&mut app);
+        app.update();
+        assert_eq!(app.world().get::<Breaker>(line.cables[1]).unwrap().state, BreakerState::Tripped);
+        assert!(is_dark(&app, line.cables[2]));
+
+        app.world_mut().trigger(Repair { entity: line.cables[2] });
+        close(&mut app);
+        app.update();
+        assert_eq!(app.world().get::<Breaker>(line.cables[1]).unwrap().state, BreakerState::Closed);
+        assert!(!line.cables.iter().any(|cable| is_dark(&app, *cable)));
+        let timeline = app.world().resource::<GridTimeline>();
+        assert_eq!(timeline.entries.last().map(|entry| entry.event), Some(GridEvent::Restored { cables: 2 }));
+    }
+
+    // -- edge cases --
+    // a fault on a cable with its own breaker only needs that breaker
+    #[test]
+    fn test_protection_by_own_breaker() {
+        let mut app = faults_test_app();
+        let line = spawn_line(app.world_mut(), 3);
+        let graph = app.world().resource::<GridGraph>();
+        assert_eq!(protection_breakers(graph, line.cables[0], |entity| entity == line.cables[0]), vec![line.cables[0]]);
+        assert!(protection_breakers(graph, line.cables[0], |_| false).is_empty());
+        assert_eq!(supplies(graph, std::iter::empty()), vec![line.connections[0]]);
+    }
+}
@@ -0,0 +1,416 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
+use super::cables::*;
+
+pub struct GridGraphPlugin;
+impl Plugin for GridGraphPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<GridGraph>()
+        .add_observer(add_connection_node)
+        .add_observer(remove_connection_node)
+        .add_observer(attach_cable_edge::<StartsFrom>)
+        .add_observer(attach_cable_edge::<EndsAt>)
+        .add_observer(detach_cable_edge::<StartsFrom>)
+        .add_observer(detach_cable_edge::<EndsAt>)
+        .add_observer(update_cable_edge_length);
+    }
+}
+
+/*
+the topology of the grid: cable connections are the nodes and cables the edges between them.
+it is kept in sync with the StartsFrom and EndsAt relationships of cables, so it never has to be rebuilt by hand.
+edges are undirected, since sparks ride cables both ways, and weighted by the length of the generated cable.
+cables that have not been generated yet weigh nothing until they are.
+the ports of a substation are separate nodes, the graph does not cross transformers.
+*/
+#[derive(Resource, Default, Debug)]
+pub struct GridGraph {
+    // every cable touching each connection
+    nodes: HashMap<Entity, Vec<Entity>>,
+    edges: HashMap<Entity, GridEdge>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GridEdge {
+    pub from: Entity,
+    pub to: Entity,
+    pub length: f32,
+}
+
+impl GridEdge {
+    // the end of the edge across from the given connection
+    pub fn other(&self, connection: Entity) -> Entity {
+        match self.from == connection {
+            true => self.to,
+            false => self.from,
+        }
+    }
+}
+
+// a route through the grid, connections[i] and connections[i + 1] being the ends of cables[i]
+#[derive(Clone, PartialEq, Debug)]
+pub struct GridPath {
+    pub connections: Vec<Entity>,
+    pub cables: Vec<Entity>,
+    pub length: f32,
+}
+
+impl GridGraph {
+    pub fn add_node(&mut self, connection: Entity) {
+        self.nodes.entry(connection).or_default();
+    }
+
+    // removes the connection together with every cable touching it
+    pub fn remove_node(&mut self, connection: Entity) {
+        for cable in self.nodes.remove(&connection).unwrap_or_default() {
+            self.remove_edge(cable);
+        }
+    }
+
+    // adds the cable between the two connections, replacing its previous ends if it was already in the graph
+    pub fn add_edge(&mut self, cable: Entity, from: Entity, to: Entity, length: f32) {
+        self.remove_edge(cable);
+        self.nodes.entry(from).or_default().push(cable);
+        if to != from {
+            self.nodes.entry(to).or_default().push(cable);
+        }
+        self.edges.insert(cable, GridEdge { from, to, length });
+    }
+
+    pub fn remove_edge(&mut self, cable: Entity) {
+        let Some(edge) = self.edges.remove(&cable) else { return };
+        for end in [edge.from, edge.to] {
+            if let Some(cables) = self.nodes.get_mut(&end) {
+                cables.retain(|other| *other != cable);
+            }
+        }
+    }
+
+    pub fn set_length(&mut self, cable: Entity, length: f32) {
+        if let Some(edge) = self.edges.get_mut(&cable) {
+            edge.length = length;
+        }
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (Entity, &GridEdge)> + '_ {
+        self.edges.iter().map(|(cable, edge)| (*cable, edge))
+    }
+
+    pub fn edge(&self, cable: Entity) -> Option<&GridEdge> {
+        self.edges.get(&cable)
+    }
+
+    pub fn contains_node(&self, connection: Entity) -> bool {
+        self.nodes.contains_key(&connection)
+    }
+
+    // every cable touching the connection, with the connection at its other end
+    pub fn neighbours(&self, connection: Entity) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.nodes.get(&connection).into_iter().flatten()
+            .filter_map(move |cable| self.edges.get(cable).map(|edge| (*cable, edge.other(connection))))
+    }
+
+    pub fn degree(&self, connection: Entity) -> usize {
+        self.nodes.get(&connection).map_or(0, Vec::len)
+    }
+
+    // connections only one cable leads to, where a spark can go no further. a cable looping back to where it started leads on.
+    pub fn dead_ends(&self) -> Vec<Entity> {
+        self.nodes.iter()
+            .filter(|(_, cables)| cables.len() == 1 && self.edges.get(&cables[0]).is_some_and(|edge| edge.from != edge.to))
+            .map(|(connection, _)| *connection)
+            .collect()
+    }
+
+    // the connections of each separate part of the grid
+    pub fn components(&self) -> Vec<Vec<Entity>> {
+        let mut sets = UnionFind::new(self.nodes());
+        for edge in self.edges.values() {
+            sets.union(edge.from, edge.to);
+        }
+        let mut components: HashMap<Entity, Vec<Entity>> = HashMap::new();
+        for connection in self.nodes() {
+            components.entry(sets.find(connection)).or_default().push(connection);
+        }
+        components.into_values().collect()
+    }
+
+    pub fn connected(&self, a: Entity, b: Entity) -> bool {
+        self.shortest_path(a, b).is_some()
+    }
+
+    // the shortest route between two connections by cable length. None if no cables lead from one to the other.
+    pub fn shortest_path(&self, from: Entity, to: Entity) -> Option<GridPath> {
+        if !self.contains_node(from) || !self.contains_node(to) { return None }
+
+        // the cable each connection was first reached through at its shortest distance
+        let mut reached_through: HashMap<Entity, Entity> = HashMap::new();
+        let mut distances: HashMap<Entity, f32> = HashMap::from_iter([(from, 0.0)]);
+        let mut queue = BinaryHeap::from([Visit { length: 0.0, connection: from }]);
+        while let Some(Visit { length, connection }) = queue.pop() {
+            if connection == to { break }
+            if length > distances[&connection] { continue }
+            for (cable, next) in self.neighbours(connection) {
+                let next_length = length + self.edges[&cable].length;
+                if distances.get(&next).is_none_or(|known| next_length < *known) {
+                    distances.insert(next, next_length);
+                    reached_through.insert(next, cable);
+                    queue.push(Visit { length: next_length, connection: next });
+                }
+            }
+        }
+
+        let length = *distances.get(&to)?;
+        let (mut connections, mut cables) = (vec![to], vec![]);
+        let mut connection = to;
+        while connection != from {
+            let cable = reached_through[&connection];
+            connection = self.edges[&cable].other(connection);
+            cables.push(cable);
+            connections.push(connection);
+        }
+        connections.reverse();
+        cables.reverse();
+        Some(GridPath { connections, cables, length })
+    }
+
+    /*
+    every cable connected to the given one, itself included, following cables both ways.
+    this is what is wired together, not where a spark can ride: sparks only take cables the way junctions offer them,
+    and pass through substations, which this stops at. CableNetwork::reachable_cables answers where a spark can get to.
+    */
+    pub fn connected_cables(&self, cable: Entity) -> HashSet<Entity> {
+        let Some(edge) = self.edges.get(&cable) else { return HashSet::new() };
+        let mut reached = HashSet::from_iter([cable]);
+        let mut visited = HashSet::new();
+        let mut stack = vec![edge.from, edge.to];
+        while let Some(connection) = stack.pop() {
+            if !visited.insert(connection) { continue }
+            for (cable, next) in self.neighbours(connection) {
+                reached.insert(cable);
+                stack.push(next);
+            }
+        }
+        reached
+    }
+}
+
+// a connection waiting in the shortest path queue, the closest one comes out first
+#[derive(PartialEq)]
+struct Visit {
+    length: f32,
+    connection: Entity,
+}
+
+impl Eq for Visit {}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.length.total_cmp(&self.length).then_with(|| self.connection.cmp(&other.connection))
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// disjoint sets of connections, with path halving
+struct UnionFind {
+    parents: HashMap<Entity, Entity>,
+}
+
+impl UnionFind {
+    fn new(nodes: impl Iterator<Item = Entity>) -> Self {
+        UnionFind { parents: nodes.map(|node| (node, node)).collect() }
+    }
+
+    fn find(&mut self, mut node: Entity) -> Entity {
+        while self.parents[&node] != node {
+            let grandparent = self.parents[&self.parents[&node]];
+            self.parents.insert(node, grandparent);
+            node = grandparent;
+        }
+        node
+    }
+
+    fn union(&mut self, a: Entity, b: Entity) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents.insert(a, b);
+        }
+    }
+}
+
+fn add_connection_node(
+    trigger: On<Add, CableConnection>,
+    mut graph: ResMut<GridGraph>,
+) {
+    graph.add_node(trigger.entity);
+}
+
+fn remove_connection_node(
+    trigger: On<Remove, CableConnection>,
+    mut graph: ResMut<GridGraph>,
+) {
+    graph.remove_node(trigger.entity);
+}
+
+// a cable joins the graph once it has both of its ends. inserting a relationship again rewires it.
+fn attach_cable_edge<R: Component>(
+    trigger: On<Insert, R>,
+    cables: Query<(&Cable, &StartsFrom, &EndsAt)>,
+    mut graph: ResMut<GridGraph>,
+) {
+    let Ok((cable, from, to)) = cables.get(trigger.entity) else { return };
+    graph.add_edge(trigger.entity, from.0, to.0, cable.length());
+}
+
+fn detach_cable_edge<R: Component>(
+    trigger: On<Remove, R>,
+    mut graph: ResMut<GridGraph>,
+) {
+    graph.remove_edge(trigger.entity);
+}
+
+fn update_cable_edge_length(
+    trigger: On<CableGenerated>,
+    cables: Query<&Cable>,
+    mut graph: ResMut<GridGraph>,
+) {
+    if let Ok(cable) = cables.get(trigger.entity) {
+        graph.set_length(trigger.entity, cable.length());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    fn graph_test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(GridGraphPlugin);
+        app
+    }
+
+    fn spawn_connection(world: &mut World) -> Entity {
+        world.spawn(CableConnection::default()).id()
+    }
+
+    // -- basic --
+    // cables join and leave the graph with their relationships
+    #[test]
+    fn test_graph_follows_relationships() {
+        let mut app = graph_test_app();
+        let world = app.world_mut();
+        let (a, b, c) = (spawn_connection(world), spawn_connection(world), spawn_connection(world));
+        let cable = spawn_cable(&mut world.commands(), &a, &b, None);
+        world.flush();
+
+        let graph = world.resource::<GridGraph>();
+        assert_eq!(graph.nodes().count(), 3);
+        assert_eq!(graph.edge(cable).map(|edge| (edge.from, edge.to)), Some((a, b)));
+        assert_eq!(graph.neighbours(a).collect::<Vec<_>>(), vec![(cable, b)]);
+
+        world.entity_mut(cable).insert(EndsAt(c));
+        world.flush();
+        let graph = world.resource::<GridGraph>();
+        assert_eq!(graph.edge(cable).map(|edge| edge.to), Some(c));
+        assert_eq!(graph.degree(b), 0);
+
+        world.despawn(cable);
+        world.flush();
+        let graph = world.resource::<GridGraph>();
+        assert!(graph.edge(cable).is_none());
+        assert_eq!(graph.degree(a), 0);
+    }
+
+    // two lines that do not touch are separate components
+    #[test]
+    fn test_components() {
+        let mut app = graph_test_app();
+        let world = app.world_mut();
+        let line: Vec<Entity> = (0..3).map(|_| spawn_connection(world)).collect();
+        let other_line: Vec<Entity> = (0..2).map(|_| spawn_connection(world)).collect();
+        spawn_generated_cable(world, line[0], line[1], vec![Vec3::ZERO, Vec3::X * 10.0]);
+        spawn_generated_cable(world, line[1], line[2], vec![Vec3::ZERO, Vec3::X * 10.0]);
+        spawn_generated_cable(world, other_line[0], other_line[1], vec![Vec3::ZERO, Vec3::X * 10.0]);
+        world.flush();
+
+        let graph = world.resource::<GridGraph>();
+        let mut sizes: Vec<usize> = graph.components().iter().map(Vec::len).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![2, 3]);
+        assert!(graph.connected(line[0], line[2]));
+        assert!(!graph.connected(line[0], other_line[1]));
+    }
+
+    // the shortest path goes around a long direct cable
+    #[test]
+    fn test_shortest_path() {
+        let mut app = graph_test_app();
+        let world = app.world_mut();
+        let (a, b, c) = (spawn_connection(world), spawn_connection(world), spawn_connection(world));
+        spawn_generated_cable(world, a, c, vec![Vec3::ZERO, Vec3::X * 30.0]);
+        let first = spawn_generated_cable(world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        // attached the other way around, the path still uses it
+        let second = spawn_generated_cable(world, c, b, vec![Vec3::ZERO, Vec3::X * 12.0]);
+        world.flush();
+
+        let path = world.resource::<GridGraph>().shortest_path(a, c).unwrap();
+        assert_eq!(path.connections, vec![a, b, c]);
+        assert_eq!(path.cables, vec![first, second]);
+        assert!((path.length - 22.0).abs() < 0.001);
+    }
+
+    // everything wired to a cable is connected to it, and the ends of the line are dead ends but not a cable looping back
+    #[test]
+    fn test_connected_cables() {
+        let mut app = graph_test_app();
+        let world = app.world_mut();
+        let (a, b, c, d) = (spawn_connection(world), spawn_connection(world), spawn_connection(world), spawn_connection(world));
+        let first = spawn_generated_cable(world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let second = spawn_generated_cable(world, b, c, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let elsewhere = spawn_generated_cable(world, d, d, vec![Vec3::ZERO, Vec3::ZERO]);
+        world.flush();
+
+        let graph = world.resource::<GridGraph>();
+        let connected = graph.connected_cables(second);
+        assert_eq!(connected, HashSet::from_iter([first, second]));
+        assert!(!connected.contains(&elsewhere));
+
+        let mut dead_ends = graph.dead_ends();
+        dead_ends.sort();
+        let mut expected = vec![a, c];
+        expected.sort();
+        assert_eq!(dead_ends, expected);
+    }
+
+    // -- edge cases --
+    // no path leads to a connection without cables, and the path to itself is empty
+    #[test]
+    fn test_path_edge_cases() {
+        let mut app = graph_test_app();
+        let world = app.world_mut();
+        let (a, b, lonely) = (spawn_connection(world), spawn_connection(world), spawn_connection(world));
+        spawn_generated_cable(world, a, b, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        world.flush();
+
+        let graph = world.resource::<GridGraph>();
+        assert!(graph.shortest_path(a, lonely).is_none());
+        assert_eq!(graph.shortest_path(a, a), Some(GridPath { connections: vec![a], cables: vec![], length: 0.0 }));
+
+        world.despawn(b);
+        world.flush();
+        let graph = world.resource::<GridGraph>();
+        assert!(!graph.contains_node(b));
+        assert_eq!(graph.edges().count(), 0);
+    }
+}
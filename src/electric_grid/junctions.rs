@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use bevy::{color::palettes::css::{ORANGE, WHITE}, ecs::system::SystemParam, platform::collections::HashSet, prelude::*};
+use super::breakers::*;
+use super::cables::*;
+use super::controllers::*;
+use super::phases::*;
+use super::spark_movement::*;
+use super::substation::*;
+
+use crate::actions::Action;
+
+static CHOICE_ARROW_LENGTH: f32 = 4.0;
+
+pub struct JunctionsPlugin;
+impl Plugin for JunctionsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_gizmo_group::<JunctionGizmos>()
+        .add_systems(Update, (
+            (choose_junction_player, choose_junction_auto).before(move_spark),
+            junction_gizmos,
+        ));
+    }
+}
+
+/*
+how a spark decides which cable to take when it reaches a connection with more than one way onwards.
+player sparks wait at the junction until a choice is made with directional input on the device controlling them,
+sparks without a device leave the choice to their controller, and every other policy resolves on the next frame so AI and test sparks never get stuck.
+*/
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JunctionPolicy {
+    #[default]
+    Player,
+    // take the cable that bends the least away from the incoming direction
+    Straightest,
+    // take the first candidate, in the order they are listed on the connection
+    First,
+    // the spark's controller decides, e.g. following a scripted route
+    Controller,
+}
+
+// the direction a spark is moving along its cable, as seen from the cable's own start/end orientation
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Travel {
+    Forward,
+    Backward,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct JunctionChoice {
+    pub cable: Entity,
+    // world space direction the spark would be moving in right after taking this cable
+    pub direction: Dir3,
+    // direction the spark would be moving along the cable. cables leaving a substation may be attached either way around
+    pub travel: Travel,
+    // voltage the spark would have on this cable
+    pub voltage: f32,
+    // phase the cable carries, if any
+    pub phase: Option<Phase>,
+}
+
+/*
+inserted on a spark that has stopped at a connection with several candidate cables.
+travel is the direction the spark was moving in when it arrived.
+choices are sorted from leftmost to rightmost relative to the incoming direction, so directional input maps onto them naturally.
+*/
+#[derive(Component, Debug)]
+pub struct AtJunction {
+    pub connection: Entity,
+    pub travel: Travel,
+    pub incoming: Dir3,
+    pub choices: Vec<JunctionChoice>,
+    pub selected: usize,
+    // distance the spark overshot the junction by, carried onto the chosen cable
+    pub(crate) leftover: f32,
+}
+
+impl AtJunction {
+    pub(crate) fn new(connection: Entity, travel: Travel, incoming: Dir3, mut choices: Vec<JunctionChoice>, leftover: f32) -> Self {
+        choices.sort_by(|a, b| signed_turn(incoming, a.direction).total_cmp(&signed_turn(incoming, b.direction)));
+        let selected = straightest_choice(incoming, &choices).unwrap_or(0);
+        AtJunction { connection, travel, incoming, choices, selected, leftover }
+    }
+
+    pub fn selected_choice(&self) -> Option<&JunctionChoice> {
+        self.choices.get(self.selected)
+    }
+}
+
+// everything needed to find out where a spark can go from a connection
+#[derive(SystemParam)]
+#[allow(clippy::type_complexity)]
+pub(crate) struct CableNetwork<'w, 's> {
+    pub cables: Query<'w, 's, (&'static Cable, &'static StartsFrom, &'static EndsAt, Option<&'static VoltageRating>, Option<&'static CablePhase>)>,
+    cable_start_connections: Query<'w, 's, &'static CablesStartingHere>,
+    cable_end_connections: Query<'w, 's, &'static CablesEndingHere>,
+    ports: Query<'w, 's, (&'static SubstationPort, &'static ChildOf)>,
+    substations: Query<'w, 's, (&'static Substation, &'static Children)>,
+    breakers: Query<'w, 's, &'static Breaker>,
+}
+
+impl CableNetwork<'_, '_> {
+    // collect every cable the spark may continue on when reaching `connection` while travelling in the given direction,
+    // given its voltage, phase and the phase rule it follows.
+    // at a substation port, the spark continues from the ports on the other side of the substation instead.
+    // connections and cables behind a breaker that does not conduct lead nowhere.
+    pub(crate) fn gather_choices(&self, connection: Entity, travel: Travel, spark: &Spark, phase_rule: PhaseRule) -> Vec<JunctionChoice> {
+        if !conducts(&self.breakers, connection) { return Vec::new() }
+        let voltage = spark.voltage;
+        let mut candidates: Vec<(Entity, Travel, f32)> = Vec::new();
+        match self.substation_exits(connection, voltage) {
+            Some(exits) => for (exit, voltage) in exits.into_iter().filter(|(exit, _)| conducts(&self.breakers, *exit)) {
+                for travel in [Travel::Forward, Travel::Backward] {
+                    candidates.extend(self.cables_leaving(exit, travel).into_iter().map(|cable_entity| (cable_entity, travel, voltage)));
+                }
+            },
+            None => candidates.extend(self.cables_leaving(connection, travel).into_iter().map(|cable_entity| (cable_entity, travel, voltage))),
+        }
+
+        candidates.into_iter()
+            .filter(|(cable_entity, _, voltage)| self.can_ride(*cable_entity, *voltage, spark.phase, phase_rule))
+            .filter_map(|(cable_entity, travel, voltage)| {
+                let (cable, _, _, _, cable_phase) = self.cables.get(cable_entity).ok()?;
+                let phase = cable_phase.map(|cable_phase| cable_phase.phase);
+                let direction = match travel {
+                    Travel::Forward => cable.start_direction(),
+                    Travel::Backward => cable.end_direction().map(|dir| -dir),
+                };
+                // cables that are not generated yet have no geometry to ride on
+                direction.map(|direction| JunctionChoice { cable: cable_entity, direction, travel, voltage, phase })
+            })
+            .collect()
+    }
+
+    // whether a spark with the given voltage and phase may get on the cable: it conducts, is rated for the voltage and the phase rule allows its phase
+    pub(crate) fn can_ride(&self, cable_entity: Entity, voltage: f32, phase: Phase, phase_rule: PhaseRule) -> bool {
+        let Ok((_, _, _, rating, cable_phase)) = self.cables.get(cable_entity) else { return false };
+        conducts(&self.breakers, cable_entity)
+            && rating.is_none_or(|rating| rating.accepts(voltage))
+            && phase_rule.allows(phase, cable_phase.map(|cable_phase| cable_phase.phase))
+    }
+
+    /*
+    every cable a spark can get to from its own cable heading the given way, its own cable included.
+    the spark goes the way it would on the move: at every connection it is only offered what gather_choices offers it,
+    and a spark whose policy chooses for it only ever takes the cable that policy picks.
+    each cable is followed with the voltage and phase the spark first reaches it with.
+    */
+    pub(crate) fn reachable_cables(&self, spark: &Spark, travel: Travel, policy: JunctionPolicy, phase_rule: PhaseRule) -> HashSet<Entity> {
+        let mut reached = HashSet::from_iter([spark.connected_to_cable_entity]);
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([(spark.connected_to_cable_entity, travel, spark.voltage, spark.phase)]);
+        while let Some((cable_entity, travel, voltage, phase)) = queue.pop_front() {
+            if !visited.insert((cable_entity, travel)) { continue; }
+            let Ok((cable, starts_from, ends_at, _, _)) = self.cables.get(cable_entity) else { continue };
+            let (connection, incoming) = match travel {
+                Travel::Forward => (ends_at.0, cable.end_direction()),
+                Travel::Backward => (starts_from.0, cable.start_direction().map(|dir| -dir)),
+            };
+            let mut rider = Spark::new(cable_entity, spark.speed);
+            rider.voltage = voltage;
+            rider.phase = phase;
+            let choices = self.gather_choices(connection, travel, &rider, phase_rule);
+            let Some(first) = choices.first() else { continue };
+            // ordered the way the spark would see them at the junction
+            let junction = AtJunction::new(connection, travel, incoming.unwrap_or(first.direction), choices, 0.0);
+            let taken = match policy {
+                _ if junction.choices.len() == 1 => Some(0),
+                JunctionPolicy::Player | JunctionPolicy::Controller => None,
+                JunctionPolicy::Straightest => straightest_choice(junction.incoming, &junction.choices),
+                JunctionPolicy::First => Some(0),
+            };
+            for (index, choice) in junction.choices.iter().enumerate() {
+                if taken.is_some_and(|taken| taken != index) { continue; }
+                reached.insert(choice.cable);
+                queue.push_back((choice.cable, choice.travel, choice.voltage, choice.phase.unwrap_or(phase)));
+            }
+        }
+        reached
+    }
+
+    // cables a spark travelling in the given direction can leave the connection on
+    fn cables_leaving(&self, connection: Entity, travel: Travel) -> Vec<Entity> {
+        match travel {
+            Travel::Forward => self.cable_start_connections.get(connection).map(|c| c.collection().to_vec()).unwrap_or_default(),
+            Travel::Backward => self.cable_end_connections.get(connection).map(|c| c.collection().to_vec()).unwrap_or_default(),
+        }
+    }
+
+    // if the connection is a substation port, the ports on the other side and the voltage the spark leaves them with
+    fn substation_exits(&self, connection: Entity, voltage: f32) -> Option<Vec<(Entity, f32)>> {
+        let (port, parent) = self.ports.get(connection).ok()?;
+        let (substation, children) = self.substations.get(parent.parent()).ok()?;
+        let side = port.side.opposite();
+        let voltage = substation.transform_voltage(voltage, side);
+        Some(children.iter()
+            .filter(|child| self.ports.get(*child).is_ok_and(|(port, _)| port.side == side))
+            .map(|child| (child, voltage))
+            .collect())
+    }
+}
+
+// angle in radians of the turn from `incoming` to `outgoing` around the vertical axis. negative turns left, positive turns right.
+fn signed_turn(incoming: Dir3, outgoing: Dir3) -> f32 {
+    let (a, b) = (incoming.xz(), outgoing.xz());
+    if a == Vec2::ZERO || b == Vec2::ZERO {
+        return 0.0;
+    }
+    a.angle_to(b)
+}
+
+// index of the choice most aligned with the incoming direction. ties keep the earlier choice, so the result is deterministic.
+pub fn straightest_choice(incoming: Dir3, choices: &[JunctionChoice]) -> Option<usize> {
+    let mut best: Option<(usize, f32)> = None;
+    for (index, choice) in choices.iter().enumerate() {
+        let alignment = incoming.dot(*choice.direction);
+        if best.is_none_or(|(_, best_alignment)| alignment > best_alignment) {
+            best = Some((index, alignment));
+        }
+    }
+    best.map(|(index, _)| index)
+}
+
+// move the spark onto the selected cable, carrying the leftover distance over.
+pub(crate) fn commit_choice(
+    commands: &mut Commands,
+    spark_entity: Entity,
+    spark: &mut Spark,
+    junction: &AtJunction,
+    cables: &Query<&Cable>,
+) {
+    let Some(choice) = junction.selected_choice() else { return };
+    let Ok(cable) = cables.get(choice.cable) else { return };
+    spark.connected_to_cable_entity = choice.cable;
+    spark.dist_along = match choice.travel {
+        Travel::Forward => junction.leftover,
+        Travel::Backward => cable.length() - junction.leftover,
+    };
+    if spark.voltage != choice.voltage {
+        commands.trigger(VoltageChanged { entity: spark_entity, from: spark.voltage, to: choice.voltage });
+        spark.voltage = choice.voltage;
+    }
+    if let Some(phase) = choice.phase
+        && phase != spark.phase {
+        commands.trigger(PhaseShifted { entity: spark_entity, from: spark.phase, to: phase });
+        spark.phase = phase;
+    }
+    commands.entity(spark_entity).remove::<AtJunction>();
+}
+
+fn choose_junction_player(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut AtJunction, &JunctionPolicy, &SparkController)>,
+    cables: Query<&Cable>,
+    input: ControllerActions,
+) {
+    for (spark_entity, mut spark, mut junction, policy, controller) in &mut sparks {
+        if *policy != JunctionPolicy::Player || !controller.is_device() { continue; }
+
+        if input.just_pressed(controller, Action::ChooseBranch) {
+            let value = input.of(controller).map_or(0.0, |state| state.value(Action::ChooseBranch));
+            junction.selected = match value < 0.0 {
+                true => junction.selected.saturating_sub(1),
+                false => (junction.selected + 1).min(junction.choices.len().saturating_sub(1)),
+            };
+        }
+
+        // continuing in the same direction takes the selected cable, turning around leaves the junction the way we came
+        let (onwards, back) = match junction.travel {
+            Travel::Forward => (Action::Forward, Action::Back),
+            Travel::Backward => (Action::Back, Action::Forward),
+        };
+        if input.just_pressed(controller, onwards) {
+            debug!("spark took junction choice {} at connection {}", junction.selected, junction.connection);
+            commit_choice(&mut commands, spark_entity, &mut spark, &junction, &cables);
+        } else if input.just_pressed(controller, back) {
+            commands.entity(spark_entity).remove::<AtJunction>();
+        }
+    }
+}
+
+fn choose_junction_auto(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut AtJunction, &JunctionPolicy)>,
+    cables: Query<&Cable>,
+) {
+    for (spark_entity, mut spark, mut junction, policy) in &mut sparks {
+        junction.selected = match policy {
+            JunctionPolicy::Player | JunctionPolicy::Controller => continue,
+            JunctionPolicy::Straightest => straightest_choice(junction.incoming, &junction.choices).unwrap_or(0),
+            JunctionPolicy::First => 0,
+        };
+        commit_choice(&mut commands, spark_entity, &mut spark, &junction, &cables);
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct JunctionGizmos;
+
+fn junction_gizmos(
+    mut gizmos: Gizmos<JunctionGizmos>,
+    sparks: Query<(&GlobalTransform, &AtJunction)>,
+) {
+    for (spark_transform, junction) in sparks {
+        let origin = spark_transform.translation();
+        for (index, choice) in junction.choices.iter().enumerate() {
+            let color = if index == junction.selected { ORANGE } else { WHITE };
+            gizmos.arrow(origin, origin + choice.direction * CHOICE_ARROW_LENGTH, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
+    fn choice(index: u32, direction: Vec3) -> JunctionChoice {
+        JunctionChoice { cable: Entity::from_raw_u32(index).unwrap(), direction: Dir3::new(direction).unwrap(), travel: Travel::Forward, voltage: 0.0, phase: None }
+    }
+
+    #[test]
+    fn test_straightest_choice() {
+        let choices = [
+            choice(1, Vec3::new(1.0, 0.0, 1.0)),
+            choice(2, Vec3::new(1.0, 0.1, 0.0)),
+            choice(3, Vec3::new(0.0, 0.0, -1.0)),
+        ];
+        assert_eq!(straightest_choice(Dir3::X, &choices), Some(1));
+        assert_eq!(straightest_choice(Dir3::X, &[]), None);
+    }
+
+    // equally good choices resolve to the first one, so the default policy is deterministic
+    #[test]
+    fn test_straightest_choice_tie() {
+        let choices = [
+            choice(1, Vec3::new(1.0, 0.0, 1.0)),
+            choice(2, Vec3::new(1.0, 0.0, -1.0)),
+        ];
+        assert_eq!(straightest_choice(Dir3::X, &choices), Some(0));
+    }
+
+    // choices are ordered left to right relative to the incoming direction
+    #[test]
+    fn test_choices_sorted_left_to_right() {
+        let junction = AtJunction::new(
+            Entity::PLACEHOLDER,
+            Travel::Forward,
+            Dir3::X,
+            vec![
+                choice(1, Vec3::new(1.0, 0.0, 1.0)),
+                choice(2, Vec3::new(1.0, 0.0, -1.0)),
+                choice(3, Vec3::X),
+            ],
+            0.0,
+        );
+        let order: Vec<Entity> = junction.choices.iter().map(|c| c.cable).collect();
+        assert_eq!(order, vec![choice(2, Vec3::X).cable, choice(3, Vec3::X).cable, choice(1, Vec3::X).cable]);
+        assert_eq!(junction.selected_choice().unwrap().cable, choice(3, Vec3::X).cable);
+    }
+
+    // a spark only reaches what it could ride to: an open breaker and a cable of another phase stop it
+    #[test]
+    fn test_reachable_cables() {
+        let mut world = World::new();
+        let connections: Vec<Entity> = (0..5).map(|_| world.spawn(CableConnection::default()).id()).collect();
+        let first = spawn_generated_cable(&mut world, connections[0], connections[1], vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let [same_phase, other_phase, open] = [(2, Vec3::new(20.0, 0.0, 5.0)), (3, Vec3::new(20.0, 0.0, -5.0)), (4, Vec3::new(20.0, 0.0, 0.0))]
+            .map(|(end, point)| spawn_generated_cable(&mut world, connections[1], connections[end], vec![Vec3::X * 10.0, point]));
+        world.entity_mut(same_phase).insert(CablePhase { circuit: 0, phase: Phase::L1 });
+        world.entity_mut(other_phase).insert(CablePhase { circuit: 0, phase: Phase::L2 });
+        world.entity_mut(open).insert(Breaker { state: BreakerState::Open, ..default() });
+        let beyond = spawn_generated_cable(&mut world, connections[4], connections[0], vec![Vec3::new(20.0, 0.0, 0.0), Vec3::ZERO]);
+
+        let reachable = |world: &mut World, travel, policy, phase_rule| world.run_system_once(move |network: CableNetwork| {
+            network.reachable_cables(&Spark::new(first, 1.0), travel, policy, phase_rule)
+        }).unwrap();
+        let forward = reachable(&mut world, Travel::Forward, JunctionPolicy::Player, PhaseRule::SamePhase);
+        assert_eq!(forward, HashSet::from_iter([first, same_phase]));
+        let shifting = reachable(&mut world, Travel::Forward, JunctionPolicy::Player, PhaseRule::Shift);
+        assert_eq!(shifting, HashSet::from_iter([first, same_phase, other_phase]));
+        // backwards, the way around the loop does not pass the breaker
+        let backward = reachable(&mut world, Travel::Backward, JunctionPolicy::Player, PhaseRule::SamePhase);
+        assert!(backward.contains(&beyond) && !backward.contains(&other_phase));
+    }
+}
@@ -1,10 +1,36 @@
 use bevy::{platform::collections::HashMap, prelude::*, scene::SceneInstanceReady};
 use bevy_polyline::PolylinePlugin;
+use arcing::*;
+use breakers::*;
 use cables::*;
+use controllers::*;
+use faults::*;
+use grid_graph::*;
+use junctions::*;
+use phases::*;
+use power_flow::*;
+use spark_contacts::*;
 use spark_movement::*;
+use spark_physics::*;
+use substation::*;
+use tower_route::*;
 
+use crate::terrain::Heightfield;
+
+pub mod arcing;
+pub mod breakers;
 pub mod cables;
+pub mod controllers;
+pub mod faults;
+pub mod grid_graph;
+pub mod junctions;
+pub mod phases;
+pub mod power_flow;
+pub mod spark_contacts;
 pub mod spark_movement;
+pub mod spark_physics;
+pub mod substation;
+pub mod tower_route;
 
 
 pub struct ElectricGridPlugin;
@@ -12,9 +38,21 @@ impl Plugin for ElectricGridPlugin {
     fn build(&self, app: &mut App) {
         app
         .add_plugins((
+            ArcingPlugin,
+            BreakersPlugin,
             CablesPlugin,
+            ControllersPlugin,
+            FaultsPlugin,
+            GridGraphPlugin,
+            JunctionsPlugin,
+            PhasesPlugin,
             PolylinePlugin,
+            PowerFlowPlugin,
+            SparkContactsPlugin,
             SparkMovementPlugin,
+            SparkPhysicsPlugin,
+            SubstationPlugin,
+            TowerRoutePlugin,
         ))
         .add_observer(connect_cables)
         .add_observer(use_tower_spawners)
@@ -33,79 +71,144 @@ fn load_models(
     commands.insert_resource(TowerScene(tower_scene));
 }
 
+/*
+spawns a line of towers at the given positions, each one wired to the previous one once its scene is ready.
+the spawner entity despawns itself right after. if it is the child of another entity, the towers become children of that entity.
+towers placed on the ground need the Heightfield resource to exist by the time the spawner is added.
+*/
 #[derive(Component, Default)]
-pub struct TowerSpawner(pub Vec<Vec3>);
+pub struct TowerSpawner {
+    pub positions: Vec<Vec3>,
+    // tower model to use, the default transmission tower if None
+    pub scene: Option<Handle<Gltf>>,
+    pub wiring: Wiring,
+    pub placement: Placement,
+}
+
+// how the y of tower positions is used
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub enum Placement {
+    // positions are used as they are
+    #[default]
+    Exact,
+    // towers stand on the terrain, y is their height above the ground
+    OnGround,
+}
+
+// how the cables between consecutive towers of a line are set up
+#[derive(Clone, Default, Debug)]
+pub struct Wiring {
+    // hang of the cables of each span, in tower order. empty uses the default hang, a single value is used for every span
+    pub hangs: Vec<f32>,
+    pub cable_color: Option<Color>,
+    // connection indices to wire between towers, every index present on both towers if None
+    pub indices: Option<Vec<u32>>,
+    // voltages the cables can carry, any voltage if None
+    pub voltage_rating: Option<VoltageRating>,
+}
+
+impl Wiring {
+    fn span_hang(&self, span: usize) -> Option<f32> {
+        match self.hangs.as_slice() {
+            [hang] => Some(*hang),
+            hangs => hangs.get(span).copied(),
+        }
+    }
+}
 
 #[derive(Component)]
 struct Tower {
-    prev: Option<Entity>
+    prev: Option<Entity>,
+    // settings for the cables running to the previous tower
+    hang: Option<f32>,
+    cable_color: Option<Color>,
+    indices: Option<Vec<u32>>,
+    voltage_rating: Option<VoltageRating>,
 }
 
 impl TowerSpawner {
     // TODO: test
-    pub fn spawn(&self, commands: &mut Commands, gltf_assets: &Res<Assets<Gltf>>, tower_scene: &Res<TowerScene>) {
-        let tower_gltf = gltf_assets.get(&tower_scene.0).unwrap();
-        let mut pos_dir_iter = self.0.clone().into_iter()
-            .zip(get_dirs(&self.0).into_iter());
-
-        let (pos, dir) = pos_dir_iter.next().unwrap();
-        let mut last_entity = commands.spawn((
-            Name::new("Transmission Tower"),
-            Transform::from_translation(pos).looking_to(dir, Vec3::Y),
-            SceneRoot(tower_gltf.scenes[0].clone()),
-            Tower{ prev: None },
-        )).id();
-        loop {
-            match pos_dir_iter.next() {
-                Some((pos, dir)) => {
-                    last_entity = commands.spawn((
-                        Name::new("Transmission Tower"),
-                        Transform::from_translation(pos).looking_to(dir, Vec3::Y),
-                        SceneRoot(tower_gltf.scenes[0].clone()),
-                        Tower{ prev: Some(last_entity.clone()) },
-                    )).id();
+    pub fn spawn(&self, commands: &mut Commands, gltf_assets: &Res<Assets<Gltf>>, tower_scene: &Res<TowerScene>, parent: Option<Entity>, terrain: Option<&Heightfield>) {
+        let tower_gltf = gltf_assets.get(self.scene.as_ref().unwrap_or(&tower_scene.0)).unwrap();
+        let positions = self.placed_positions(terrain);
+        let pos_dir_iter = positions.clone().into_iter()
+            .zip(get_dirs(&positions));
+
+        let mut last_entity: Option<Entity> = None;
+        for (index, (pos, dir)) in pos_dir_iter.enumerate() {
+            let span = index.checked_sub(1);
+            let mut tower = commands.spawn((
+                Name::new("Transmission Tower"),
+                Transform::from_translation(pos).looking_to(dir, Vec3::Y),
+                SceneRoot(tower_gltf.scenes[0].clone()),
+                Tower {
+                    prev: last_entity,
+                    hang: span.and_then(|span| self.wiring.span_hang(span)),
+                    cable_color: self.wiring.cable_color,
+                    indices: self.wiring.indices.clone(),
+                    voltage_rating: self.wiring.voltage_rating,
                 },
-                None => break,
+            ));
+            if let Some(parent) = parent {
+                tower.insert(ChildOf(parent));
             }
+            last_entity = Some(tower.id());
+        }
+    }
+
+    // tower positions with the placement applied
+    fn placed_positions(&self, terrain: Option<&Heightfield>) -> Vec<Vec3> {
+        match (self.placement, terrain) {
+            (Placement::Exact, _) => self.positions.clone(),
+            (Placement::OnGround, Some(terrain)) => self.positions.iter().map(|pos| terrain.snap_to_ground(*pos)).collect(),
+            (Placement::OnGround, None) => {
+                error!("towers should be placed on the ground, but there is no terrain");
+                self.positions.clone()
+            },
         }
     }
 }
 
 fn use_tower_spawners(
     trigger: On<Add, TowerSpawner>,
-    tower_spawners: Query<&TowerSpawner>,
+    tower_spawners: Query<(&TowerSpawner, Option<&ChildOf>)>,
     mut commands: Commands, 
     gltf_assets: Res<Assets<Gltf>>, 
     tower_scene: If<Res<TowerScene>>,
+    terrain: Option<Res<Heightfield>>,
 ) {
-    tower_spawners.get(trigger.entity).unwrap().spawn(&mut commands, &gltf_assets, &tower_scene);
+    let (tower_spawner, parent) = tower_spawners.get(trigger.entity).unwrap();
+    tower_spawner.spawn(&mut commands, &gltf_assets, &tower_scene, parent.map(ChildOf::parent), terrain.as_deref());
     commands.entity(trigger.entity).despawn();
 }
 
-// TODO: test
-fn get_dirs(spawn_positions: &Vec<Vec3>) -> Vec<Dir3> {
-    if spawn_positions.is_empty() { unimplemented!("get_dirs cannot take an empty Vec") }
-    if spawn_positions.len() == 1 {
-        return vec![Dir3::X];
-    }
-    let mut dir_vec: Vec<Dir3> = Vec::new();
-    let mut iter = spawn_positions.iter();
-    let mut last_vec = iter.next().unwrap();
-    loop {
-        match iter.next() {
-            Some(this_vec) => {
-                let dir = this_vec - last_vec;
-                let dir = Vec3::new(dir.x, 0.0, dir.z);
-                dir_vec.push(Dir3::new(dir).unwrap());
-                last_vec = this_vec;
-            },
-            None => {
-                dir_vec.push(dir_vec.last().unwrap().clone());
-                break;
-            }
-        }
+// facing direction of each tower in a line, looking along the line on the horizontal plane.
+// towers at a bend face along the bisector of their incoming and outgoing spans, so their cross arms split the angle evenly.
+fn get_dirs(spawn_positions: &[Vec3]) -> Vec<Dir3> {
+    match spawn_positions.len() {
+        0 => return Vec::new(),
+        1 => return vec![Dir3::X],
+        _ => {},
     }
-    dir_vec
+    let span_dirs: Vec<Vec3> = spawn_positions.windows(2)
+        .map(|pair| {
+            let dir = pair[1] - pair[0];
+            Vec3::new(dir.x, 0.0, dir.z).normalize_or_zero()
+        })
+        .collect();
+    (0..spawn_positions.len())
+        .map(|index| {
+            let incoming = index.checked_sub(1).map(|prev| span_dirs[prev]);
+            let outgoing = span_dirs.get(index).copied();
+            let dir = match (incoming, outgoing) {
+                // a line doubling back on itself has no bisector, keep facing the incoming span
+                (Some(incoming), Some(outgoing)) => (incoming + outgoing).try_normalize().unwrap_or(incoming),
+                (Some(dir), None) | (None, Some(dir)) => dir,
+                (None, None) => unreachable!(),
+            };
+            Dir3::new(dir).unwrap_or(Dir3::X)
+        })
+        .collect()
 }
 
 // TODO: test
@@ -113,7 +216,8 @@ fn connect_cables(
     trigger: On<SceneInstanceReady>,
     towers: Query<&Tower>,
     children: Query<&Children>,
-    connections: Query<&CableConnection>,
+    connections: Query<(&CableConnection, Option<&Name>)>,
+    phase_layout: Res<PhaseLayout>,
     mut commands: Commands,
 ) {
     let tower = towers.get(trigger.entity);
@@ -127,27 +231,45 @@ fn connect_cables(
     }
     let prev_tower_entity = tower.prev.unwrap();
 
-    let found_connections = get_cable_connections_in_scene(&tower_entity, &children, &connections);
-    let prev_found_connetions = get_cable_connections_in_scene(&prev_tower_entity, &children, &connections);
+    let found_connections = get_cable_connections_in_scene(&tower_entity, &children, &connections, &phase_layout);
+    let prev_found_connetions = get_cable_connections_in_scene(&prev_tower_entity, &children, &connections, &phase_layout);
     for (index, connection_entity) in found_connections {
+        if tower.indices.as_ref().is_some_and(|indices| !indices.contains(&index)) { continue; }
         if let Some(prev_connection_entity) = prev_found_connetions.get(&index) {
-            spawn_cable(&mut commands, &prev_connection_entity, &connection_entity, None);
+            let cable_phase = phase_layout.phase_of(index);
+            let mut cable = Cable::default();
+            cable.hang = tower.hang.unwrap_or(HANG);
+            // a color given for the line wins over the color of the phase
+            let color = tower.cable_color.or_else(|| cable_phase.and_then(|cable_phase| phase_layout.color_of(cable_phase.phase)));
+            if let Some(color) = color {
+                cable.color = color.into();
+            }
+            let cable_entity = spawn_cable_from(&mut commands, prev_connection_entity, &connection_entity, cable);
+            commands.entity(cable_entity).insert(TowerSpan { from: prev_tower_entity, to: tower_entity });
+            if let Some(rating) = tower.voltage_rating {
+                commands.entity(cable_entity).insert(rating);
+            }
+            if let Some(cable_phase) = cable_phase {
+                commands.entity(cable_entity).insert(cable_phase);
+            }
         }
     }
     
 }
 
-// TODO: test
+// connections are keyed by the index the phase layout wires them by, so connections sharing one only get one cable between them
 fn get_cable_connections_in_scene(
     scene_entity: &Entity, 
     children: &Query<&Children>,
-    connections: & Query<&CableConnection>,
+    connections: &Query<(&CableConnection, Option<&Name>)>,
+    phase_layout: &PhaseLayout,
 ) -> HashMap<u32, Entity> {
     let mut found_connections: HashMap<u32, Entity> = HashMap::new();
     for entity in children.iter_descendants_depth_first(*scene_entity) {
-        if let Ok(connection) = connections.get(entity) {
-            debug!("found connection in tower with index {}", connection.index);
-            found_connections.insert(connection.index, entity);
+        if let Ok((connection, name)) = connections.get(entity) {
+            let index = phase_layout.connection_index(connection, name);
+            debug!("found connection in tower with index {}", index);
+            found_connections.insert(index, entity);
         }
     }
     found_connections
@@ -155,8 +277,58 @@ fn get_cable_connections_in_scene(
 
 #[cfg(test)]
 mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
     #[test]
     fn test() {
         assert!(true);
     }
+
+    #[test]
+    fn test_dirs_straight_line() {
+        let dirs = get_dirs(&[Vec3::ZERO, Vec3::new(10.0, 5.0, 0.0), Vec3::new(20.0, -3.0, 0.0)]);
+        assert_eq!(dirs, vec![Dir3::X, Dir3::X, Dir3::X]);
+        assert_eq!(get_dirs(&[Vec3::ZERO]), vec![Dir3::X]);
+        assert!(get_dirs(&[]).is_empty());
+    }
+
+    // the tower model's insulators all have index 0, and are told apart by the names of their nodes
+    #[test]
+    fn test_connections_by_name() {
+        let mut world = World::new();
+        let tower = world.spawn_empty().id();
+        let insulators: Vec<Entity> = ["CERAMICS.000", "CERAMICS.001", "CERAMICS_002"].into_iter()
+            .map(|name| world.spawn((CableConnection::default(), Name::new(name), ChildOf(tower))).id())
+            .collect();
+        // connections of other models keep their own index
+        let other = world.spawn((CableConnection { index: 4, ..default() }, ChildOf(tower))).id();
+
+        let found = world.run_system_once(move |children: Query<&Children>, connections: Query<(&CableConnection, Option<&Name>)>| {
+            get_cable_connections_in_scene(&tower, &children, &connections, &PhaseLayout::default())
+        }).unwrap();
+        assert_eq!(found.len(), 4);
+        assert_eq!((found[&0], found[&1], found[&2], found[&4]), (insulators[0], insulators[1], insulators[2], other));
+    }
+
+    // a tower at a right angle bend faces diagonally, the end towers face along their only span
+    #[test]
+    fn test_dirs_bisect_bends() {
+        let dirs = get_dirs(&[Vec3::ZERO, Vec3::X * 10.0, Vec3::new(10.0, 0.0, 10.0)]);
+        assert_eq!(dirs[0], Dir3::X);
+        assert!(dirs[1].dot(Vec3::new(1.0, 0.0, 1.0).normalize()) > 0.999);
+        assert_eq!(dirs[2], Dir3::Z);
+    }
+
+    #[test]
+    fn test_placed_on_ground() {
+        let terrain = Heightfield::new(Vec3::ZERO, Vec2::splat(100.0), UVec2::splat(2), vec![0.0, 10.0, 0.0, 10.0]).unwrap();
+        let mut spawner = TowerSpawner {
+            positions: vec![Vec3::new(0.0, 2.0, 50.0), Vec3::new(50.0, 0.0, 50.0)],
+            ..default()
+        };
+        assert_eq!(spawner.placed_positions(Some(&terrain)), spawner.positions);
+        spawner.placement = Placement::OnGround;
+        assert_eq!(spawner.placed_positions(Some(&terrain)), vec![Vec3::new(0.0, 2.0, 50.0), Vec3::new(50.0, 5.0, 50.0)]);
+    }
 }
\ No newline at end of file
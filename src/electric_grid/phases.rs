@@ -0,0 +1,195 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+use super::cables::*;
+
+pub struct PhasesPlugin;
+impl Plugin for PhasesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<PhaseLayout>();
+    }
+}
+
+// the conductors of a three-phase circuit, plus the ground wire strung above them
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Reflect)]
+pub enum Phase {
+    L1,
+    L2,
+    L3,
+    Ground,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [Phase::L1, Phase::L2, Phase::L3, Phase::Ground];
+}
+
+// which circuit and phase a cable belongs to. cables without it carry no particular phase and accept any spark.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug, Reflect)]
+pub struct CablePhase {
+    pub circuit: u32,
+    pub phase: Phase,
+}
+
+/*
+how the connection indices of a tower map onto circuits and phases, and the color each phase is drawn in.
+indices that are not listed are wired as plain cables without a phase.
+the default layout has two circuits, indices 0 to 2 carrying L1 to L3 of the first and 3 its ground wire, 4 to 7 the same for the second.
+the shipped tower model gives all three of its insulator connections index 0, so they are told apart by the name of their node,
+numbered 0, 1 and 2 here, and every span carries L1 to L3 of the first circuit.
+*/
+#[derive(Resource, Clone, Debug)]
+pub struct PhaseLayout {
+    pub phases: HashMap<u32, CablePhase>,
+    pub colors: HashMap<Phase, Color>,
+    // connection indices by the name of the model node the connection is on, in place of the index the model gives it
+    pub named_connections: HashMap<String, u32>,
+}
+
+impl Default for PhaseLayout {
+    fn default() -> Self {
+        let phases = (0..8)
+            .map(|index| (index, CablePhase { circuit: index / 4, phase: Phase::ALL[(index % 4) as usize] }))
+            .collect();
+        // iec 60446 conductor colors
+        let colors = HashMap::from_iter([
+            (Phase::L1, Color::srgb(0.45, 0.25, 0.1)),
+            (Phase::L2, Color::srgb(0.1, 0.1, 0.1)),
+            (Phase::L3, Color::srgb(0.5, 0.5, 0.5)),
+            (Phase::Ground, Color::srgb(0.3, 0.6, 0.2)),
+        ]);
+        let named_connections = HashMap::from_iter([
+            ("CERAMICS.000".to_string(), 0),
+            ("CERAMICS.001".to_string(), 1),
+            ("CERAMICS_002".to_string(), 2),
+        ]);
+        PhaseLayout { phases, colors, named_connections }
+    }
+}
+
+impl PhaseLayout {
+    pub fn phase_of(&self, index: u32) -> Option<CablePhase> {
+        self.phases.get(&index).copied()
+    }
+
+    pub fn color_of(&self, phase: Phase) -> Option<Color> {
+        self.colors.get(&phase).copied()
+    }
+
+    // the index a connection is wired by, from the name of its node if that is listed
+    pub fn connection_index(&self, connection: &CableConnection, name: Option<&Name>) -> u32 {
+        name.and_then(|name| self.named_connections.get(name.as_str()))
+            .copied()
+            .unwrap_or(connection.index)
+    }
+}
+
+/*
+what a spark may do when the cables leaving a tower carry different phases.
+sparks take on the phase of every cable they enter.
+*/
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PhaseRule {
+    // only cables of the spark's own phase are offered
+    #[default]
+    SamePhase,
+    // any phase may be taken, switching announces a PhaseShifted event
+    Shift,
+}
+
+impl PhaseRule {
+    // whether a spark in the given phase may enter a cable of the other phase
+    pub fn allows(&self, spark_phase: Phase, cable_phase: Option<Phase>) -> bool {
+        match (self, cable_phase) {
+            (_, None) | (PhaseRule::Shift, _) => true,
+            (PhaseRule::SamePhase, Some(cable_phase)) => cable_phase == spark_phase,
+        }
+    }
+}
+
+// triggered on a spark that entered a cable of a different phase than its own
+#[derive(EntityEvent, Debug)]
+pub struct PhaseShifted {
+    pub entity: Entity,
+    pub from: Phase,
+    pub to: Phase,
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+    use crate::electric_grid::{junctions::*, spark_movement::*};
+
+    // one cable per phase leaving the end of the first cable
+    fn spawn_bundle(world: &mut World) -> (Entity, HashMap<Phase, Entity>) {
+        let (start, tower) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let incoming = spawn_generated_cable(world, start, tower, vec![Vec3::X * -10.0, Vec3::ZERO]);
+        world.entity_mut(incoming).insert(CablePhase { circuit: 0, phase: Phase::L2 });
+
+        let outgoing = Phase::ALL.iter().enumerate()
+            .map(|(index, phase)| {
+                let end = world.spawn(CableConnection::default()).id();
+                let cable = spawn_generated_cable(world, tower, end, vec![Vec3::ZERO, Vec3::new(10.0, 0.0, index as f32 - 1.5)]);
+                world.entity_mut(cable).insert(CablePhase { circuit: 0, phase: *phase });
+                (*phase, cable)
+            })
+            .collect();
+        (incoming, outgoing)
+    }
+
+    fn run_to_end(world: &mut World, spark: Entity) -> Option<AtJunction> {
+        world.run_system_once(move |mut sparks: Query<(&mut Spark, &mut Transform, &PhaseRule)>, network: CableNetwork| {
+            let (mut spark, mut transform, rule) = sparks.get_mut(spark).unwrap();
+            set_spark_transform_and_dist_along(&mut spark, &mut transform, *rule, &network)
+        }).unwrap()
+    }
+
+    // -- basic --
+    #[test]
+    fn test_default_layout() {
+        let layout = PhaseLayout::default();
+        assert_eq!(layout.phase_of(1), Some(CablePhase { circuit: 0, phase: Phase::L2 }));
+        assert_eq!(layout.phase_of(7), Some(CablePhase { circuit: 1, phase: Phase::Ground }));
+        assert_eq!(layout.phase_of(8), None);
+        assert!(Phase::ALL.iter().all(|phase| layout.color_of(*phase).is_some()));
+    }
+
+    // a spark that must keep its phase continues onto the only cable of the same phase
+    #[test]
+    fn test_same_phase_only() {
+        let mut world = World::new();
+        let (incoming, outgoing) = spawn_bundle(&mut world);
+        let mut spark = Spark::new(incoming, 1.0);
+        spark.phase = Phase::L2;
+        spark.dist_along = 11.0;
+        let spark = world.spawn(spark).id();
+
+        assert!(run_to_end(&mut world, spark).is_none());
+        let spark = world.get::<Spark>(spark).unwrap();
+        assert_eq!(spark.connected_to_cable_entity, outgoing[&Phase::L2]);
+        assert_eq!(spark.phase, Phase::L2);
+    }
+
+    // a spark allowed to shift phases is offered every cable of the bundle
+    #[test]
+    fn test_shift_offers_every_phase() {
+        let mut world = World::new();
+        let (incoming, _) = spawn_bundle(&mut world);
+        let mut spark = Spark::new(incoming, 1.0);
+        spark.phase = Phase::L2;
+        spark.dist_along = 11.0;
+        let spark = world.spawn((spark, PhaseRule::Shift)).id();
+
+        let junction = run_to_end(&mut world, spark).unwrap();
+        assert_eq!(junction.choices.len(), 4);
+        assert!(junction.choices.iter().any(|choice| choice.phase == Some(Phase::L3)));
+    }
+
+    // -- edge cases --
+    // cables without a phase are open to every spark
+    #[test]
+    fn test_unphased_cable_allowed() {
+        assert!(PhaseRule::SamePhase.allows(Phase::L1, None));
+        assert!(!PhaseRule::SamePhase.allows(Phase::L1, Some(Phase::Ground)));
+        assert!(PhaseRule::Shift.allows(Phase::L1, Some(Phase::Ground)));
+    }
+}
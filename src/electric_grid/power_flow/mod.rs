@@ -0,0 +1,288 @@
+use bevy::{ecs::component::Mutable, platform::collections::{HashMap, HashSet}, prelude::*};
+use super::breakers::*;
+use super::cables::*;
+use super::grid_graph::*;
+
+pub use complex::Complex;
+pub use solver::{Bus, Line, Network, solve_ac, solve_dc};
+
+mod complex;
+mod solver;
+
+pub struct PowerFlowPlugin;
+impl Plugin for PowerFlowPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<PowerFlowSettings>()
+        .init_resource::<PowerFlowReport>()
+        .add_systems(FixedUpdate, solve_power_flow.run_if(power_flow_inputs_changed));
+    }
+}
+
+/*
+what a cable is made of, which together with its length gives its impedance.
+cables without a conductor type are taken to be steel reinforced aluminium, like most overhead lines.
+*/
+#[derive(Component, Clone, Copy, Default, PartialEq, Eq, Debug, Reflect)]
+pub enum ConductorType {
+    #[default]
+    Acsr,
+    Aluminium,
+    Copper,
+}
+
+impl ConductorType {
+    // resistance and reactance at 50 Hz, in ohms per kilometre
+    pub fn impedance_per_km(&self) -> Complex {
+        match self {
+            ConductorType::Acsr => Complex::new(0.06, 0.3),
+            ConductorType::Aluminium => Complex::new(0.1, 0.35),
+            ConductorType::Copper => Complex::new(0.04, 0.3),
+        }
+    }
+
+    pub fn impedance(&self, length: f32) -> Complex {
+        self.impedance_per_km() * (length as f64 / 1000.0)
+    }
+}
+
+// a cable connection fed by a power plant, held at the given voltage in volts
+#[derive(Component, Clone, Copy, Debug)]
+#[require(CableConnection)]
+pub struct Generator {
+    pub voltage: f32,
+}
+
+// a cable connection drawing power from the grid, in watts and vars
+#[derive(Component, Clone, Copy, Default, Debug)]
+#[require(CableConnection)]
+pub struct Load {
+    pub power: f32,
+    pub reactive_power: f32,
+}
+
+// the solved voltage of a cable connection. connections cut off from every generator are at 0 volts.
+#[derive(Component, Clone, Copy, Default, PartialEq, Debug)]
+pub struct BusVoltage {
+    pub magnitude: f32,
+    // phase angle in radians relative to the generators, always 0 for dc
+    pub angle: f32,
+}
+
+// the solved flow through a cable, positive when flowing from its start to its end
+#[derive(Component, Clone, Copy, Default, PartialEq, Debug)]
+pub struct LineFlow {
+    // current in amperes, always positive
+    pub current: f32,
+    // power entering the cable at its start, in watts and vars
+    pub power: f32,
+    pub reactive_power: f32,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum PowerFlowMode {
+    // nodal analysis with resistances only, cheap and always solvable
+    Dc,
+    // phasor load flow including the reactance of the lines and reactive loads
+    #[default]
+    Ac,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct PowerFlowSettings {
+    pub mode: PowerFlowMode,
+    // gauss-seidel iterations per fixed tick before giving up
+    pub max_iterations: usize,
+    // largest change of any voltage, in volts, for the ac solution to count as converged
+    pub tolerance: f64,
+}
+
+impl Default for PowerFlowSettings {
+    fn default() -> Self {
+        PowerFlowSettings { mode: default(), max_iterations: 100, tolerance: 1e-3 }
+    }
+}
+
+// how the last solve went. results of a solve that did not converge are written anyway, but should not be trusted.
+#[derive(Resource, Clone, Copy, Default, Debug)]
+pub struct PowerFlowReport {
+    pub converged: bool,
+    pub iterations: usize,
+}
+
+// every connection in the grid graph is a bus and every cable a line between two of them.
+// cables switched off by a breaker, on themselves or on either of their connections, are left out.
+pub(crate) fn build_network(
+    graph: &GridGraph,
+    generators: &Query<&Generator>,
+    loads: &Query<&Load>,
+    conductors: &Query<&ConductorType>,
+    breakers: &Query<&Breaker>,
+) -> (Network, Vec<Entity>, Vec<Entity>) {
+    let bus_entities: Vec<Entity> = graph.nodes().collect();
+    let index: HashMap<Entity, usize> = bus_entities.iter().enumerate().map(|(index, entity)| (*entity, index)).collect();
+    let buses = bus_entities.iter()
+        .map(|entity| Bus {
+            source: generators.get(*entity).ok().map(|generator| generator.voltage as f64),
+            load: loads.get(*entity).map_or(Complex::ZERO, |load| Complex::new(load.power as f64, load.reactive_power as f64)),
+        })
+        .collect();
+
+    let (line_entities, lines) = graph.edges()
+        .filter(|(cable, edge)| [*cable, edge.from, edge.to].iter().all(|entity| conducts(breakers, *entity)))
+        .map(|(cable, edge)| {
+            let conductor = conductors.get(cable).copied().unwrap_or_default();
+            (cable, Line { from: index[&edge.from], to: index[&edge.to], impedance: conductor.impedance(edge.length) })
+        })
+        .unzip();
+    (Network { buses, lines }, bus_entities, line_entities)
+}
+
+// the grid, its generators, loads, conductors or breakers changed since the last solve
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn power_flow_inputs_changed(
+    graph: Res<GridGraph>,
+    settings: Res<PowerFlowSettings>,
+    changed: Query<(), Or<(Changed<Generator>, Changed<Load>, Changed<ConductorType>, Changed<Breaker>)>>,
+    mut removed_generators: RemovedComponents<Generator>,
+    mut removed_loads: RemovedComponents<Load>,
+    mut removed_conductors: RemovedComponents<ConductorType>,
+    mut removed_breakers: RemovedComponents<Breaker>,
+) -> bool {
+    // every removal has to be read, so it is not seen again next tick
+    let removed = removed_generators.read().count() + removed_loads.read().count()
+        + removed_conductors.read().count() + removed_breakers.read().count();
+    graph.is_changed() || settings.is_changed() || !changed.is_empty() || removed > 0
+}
+
+// write a result in place, only touching the component if it changed, or insert it where it is missing
+fn write_result<C: Component<Mutability = Mutable> + PartialEq>(commands: &mut Commands, results: &mut Query<&mut C>, entity: Entity, value: C) {
+    match results.get_mut(entity) {
+        Ok(mut result) => { result.set_if_neq(value); },
+        Err(_) => { commands.entity(entity).try_insert(value); },
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn solve_power_flow(
+    mut commands: Commands,
+    graph: Res<GridGraph>,
+    settings: Res<PowerFlowSettings>,
+    mut report: ResMut<PowerFlowReport>,
+    generators: Query<&Generator>,
+    loads: Query<&Load>,
+    conductors: Query<&ConductorType>,
+    breakers: Query<&Breaker>,
+    mut voltages: Query<&mut BusVoltage>,
+    mut flows: Query<&mut LineFlow>,
+    flowing: Query<Entity, With<LineFlow>>,
+) {
+    let (network, bus_entities, line_entities) = build_network(&graph, &generators, &loads, &conductors, &breakers);
+    let solution = match settings.mode {
+        PowerFlowMode::Dc => solve_dc(&network),
+        PowerFlowMode::Ac => solve_ac(&network, settings.max_iterations, settings.tolerance),
+    };
+    if !solution.converged && report.converged {
+        warn!("power flow did not converge after {} iterations", solution.iterations);
+    }
+    *report = PowerFlowReport { converged: solution.converged, iterations: solution.iterations };
+
+    for (bus_entity, voltage) in bus_entities.iter().zip(&solution.voltages) {
+        write_result(&mut commands, &mut voltages, *bus_entity, BusVoltage { magnitude: voltage.norm() as f32, angle: voltage.arg() as f32 });
+    }
+    for (line, line_entity) in line_entities.iter().enumerate() {
+        let power = solution.line_power(&network, line);
+        write_result(&mut commands, &mut flows, *line_entity, LineFlow {
+            current: solution.currents[line].norm() as f32,
+            power: power.re as f32,
+            reactive_power: power.im as f32,
+        });
+    }
+    // cables left out of the network carry nothing
+    let solved: HashSet<Entity> = line_entities.into_iter().collect();
+    for cable in flowing.iter().filter(|cable| !solved.contains(cable)) {
+        write_result(&mut commands, &mut flows, cable, LineFlow::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
+    fn spawn_straight_cable(world: &mut World, from: Entity, to: Entity, length: f32, conductor: ConductorType) -> Entity {
+        let cable = spawn_generated_cable(world, from, to, vec![Vec3::ZERO, Vec3::X * length]);
+        world.entity_mut(cable).insert(conductor);
+        cable
+    }
+
+    // -- basic --
+    // a generator feeding a load through a long line: the voltage drops along it and the line carries the load
+    #[test]
+    fn test_generator_feeds_load() {
+        let mut app = App::new();
+        app.add_plugins((GridGraphPlugin, PowerFlowPlugin));
+        let world = app.world_mut();
+        let generator = world.spawn(Generator { voltage: 20_000.0 }).id();
+        let tower = world.spawn(CableConnection::default()).id();
+        let load = world.spawn(Load { power: 1_000_000.0, reactive_power: 200_000.0 }).id();
+        let first = spawn_straight_cable(world, generator, tower, 5_000.0, ConductorType::Acsr);
+        let second = spawn_straight_cable(world, load, tower, 5_000.0, ConductorType::Copper);
+        world.flush();
+
+        world.run_system_once(solve_power_flow).unwrap();
+        assert!(world.resource::<PowerFlowReport>().converged);
+
+        let voltage = |entity| world.get::<BusVoltage>(entity).copied().unwrap();
+        assert_eq!(voltage(generator).magnitude, 20_000.0);
+        assert!(voltage(tower).magnitude < 20_000.0);
+        assert!(voltage(load).magnitude < voltage(tower).magnitude);
+        assert!(voltage(load).angle < 0.0);
+
+        let first_flow = world.get::<LineFlow>(first).copied().unwrap();
+        let second_flow = world.get::<LineFlow>(second).copied().unwrap();
+        assert!(first_flow.power > 1_000_000.0);
+        // the second cable is attached from the load, so the power the load draws flows against its direction
+        assert!((second_flow.power + 1_000_000.0).abs() < 10.0);
+        assert!((first_flow.current - second_flow.current).abs() < 0.01);
+    }
+
+    // -- edge cases --
+    // without a generator the grid is dead
+    #[test]
+    fn test_no_generator() {
+        let mut app = App::new();
+        app.add_plugins((GridGraphPlugin, PowerFlowPlugin));
+        app.insert_resource(PowerFlowSettings { mode: PowerFlowMode::Dc, ..default() });
+        let world = app.world_mut();
+        let (tower, load) = (world.spawn(CableConnection::default()).id(), world.spawn(Load { power: 1000.0, ..default() }).id());
+        let cable = spawn_straight_cable(world, tower, load, 100.0, ConductorType::Aluminium);
+        world.flush();
+
+        world.run_system_once(solve_power_flow).unwrap();
+        assert_eq!(world.get::<BusVoltage>(load).unwrap().magnitude, 0.0);
+        assert_eq!(world.get::<LineFlow>(cable).unwrap().current, 0.0);
+    }
+
+    // results are left alone until the grid or what is on it changes
+    #[test]
+    fn test_solves_only_on_change() {
+        let mut app = App::new();
+        app.add_plugins((GridGraphPlugin, PowerFlowPlugin));
+        let world = app.world_mut();
+        let generator = world.spawn(Generator { voltage: 1000.0 }).id();
+        let load = world.spawn(Load { power: 1000.0, ..default() }).id();
+        spawn_straight_cable(world, generator, load, 100.0, ConductorType::Copper);
+        world.flush();
+        world.run_schedule(FixedUpdate);
+        assert!(world.get::<BusVoltage>(load).unwrap().magnitude > 0.0);
+
+        world.get_mut::<BusVoltage>(load).unwrap().magnitude = -1.0;
+        world.run_schedule(FixedUpdate);
+        assert_eq!(world.get::<BusVoltage>(load).unwrap().magnitude, -1.0);
+
+        world.get_mut::<Load>(load).unwrap().power = 2000.0;
+        world.run_schedule(FixedUpdate);
+        assert!(world.get::<BusVoltage>(load).unwrap().magnitude > 0.0);
+    }
+}
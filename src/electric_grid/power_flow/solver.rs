@@ -0,0 +1,305 @@
+use std::collections::VecDeque;
+use super::complex::*;
+
+// lines shorter than this still get some impedance, so cables that have not been generated yet do not short the network
+pub const MIN_IMPEDANCE: f64 = 1e-6;
+
+// a node of the network. sources hold their voltage at the given magnitude and an angle of 0, every other bus draws its load.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Bus {
+    pub source: Option<f64>,
+    // active power in watts plus reactive power in vars, drawn from the network. ignored on sources.
+    pub load: Complex,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Line {
+    pub from: usize,
+    pub to: usize,
+    // series impedance in ohms
+    pub impedance: Complex,
+}
+
+impl Line {
+    fn admittance(&self) -> Complex {
+        match self.impedance.norm() < MIN_IMPEDANCE {
+            true => Complex::real(MIN_IMPEDANCE).recip(),
+            false => self.impedance.recip(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Network {
+    pub buses: Vec<Bus>,
+    pub lines: Vec<Line>,
+}
+
+// bus voltages and line currents. currents flow from the line's from bus to its to bus.
+// buses without a path to a source are left at 0 volts.
+#[derive(Clone, Debug)]
+pub struct Solution {
+    pub voltages: Vec<Complex>,
+    pub currents: Vec<Complex>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl Solution {
+    // complex power entering the line at its from bus
+    pub fn line_power(&self, network: &Network, line: usize) -> Complex {
+        self.voltages[network.lines[line].from] * self.currents[line].conj()
+    }
+}
+
+/*
+dc nodal analysis. cables are taken as their resistance only and loads as the resistance
+that would draw their active power at the voltage of the source feeding them, which makes the system linear.
+*/
+pub fn solve_dc(network: &Network) -> Solution {
+    let nominal = nominal_voltages(network);
+    let unknowns = unknown_buses(network, &nominal);
+    let position = positions(network.buses.len(), &unknowns);
+
+    let mut matrix = vec![vec![0.0; unknowns.len()]; unknowns.len()];
+    let mut rhs = vec![0.0; unknowns.len()];
+    for line in &network.lines {
+        let conductance = 1.0 / line.impedance.re.max(MIN_IMPEDANCE);
+        for (bus, other) in [(line.from, line.to), (line.to, line.from)] {
+            let Some(row) = position[bus] else { continue };
+            matrix[row][row] += conductance;
+            match (position[other], network.buses[other].source) {
+                (Some(column), _) => matrix[row][column] -= conductance,
+                (None, Some(voltage)) => rhs[row] += conductance * voltage,
+                (None, None) => {}
+            }
+        }
+    }
+    for (row, bus) in unknowns.iter().enumerate() {
+        if let Some(nominal) = nominal[*bus] {
+            matrix[row][row] += network.buses[*bus].load.re / (nominal * nominal);
+        }
+    }
+
+    let solved = solve_linear(matrix, rhs);
+    let mut voltages: Vec<Complex> = network.buses.iter()
+        .map(|bus| bus.source.map_or(Complex::ZERO, Complex::real))
+        .collect();
+    if let Some(solved) = &solved {
+        for (row, bus) in unknowns.iter().enumerate() {
+            voltages[*bus] = Complex::real(solved[row]);
+        }
+    }
+    let currents = network.lines.iter()
+        .map(|line| (voltages[line.from] - voltages[line.to]) * (1.0 / line.impedance.re.max(MIN_IMPEDANCE)))
+        .collect();
+    Solution { voltages, currents, iterations: 1, converged: solved.is_some() }
+}
+
+/*
+ac phasor load flow by gauss-seidel iteration. loads draw constant complex power.
+starts from every bus at the voltage of its source and stops once no voltage moves by more than the tolerance, in volts.
+a network asked for more power than it can carry diverges, which is reported as not converged.
+*/
+pub fn solve_ac(network: &Network, max_iterations: usize, tolerance: f64) -> Solution {
+    let nominal = nominal_voltages(network);
+    let unknowns = unknown_buses(network, &nominal);
+
+    // the bus admittance matrix, split into its diagonal and the off-diagonal entries of each row
+    let mut diagonal = vec![Complex::ZERO; network.buses.len()];
+    let mut off_diagonal: Vec<Vec<(usize, Complex)>> = vec![Vec::new(); network.buses.len()];
+    for line in network.lines.iter().filter(|line| line.from != line.to) {
+        let admittance = line.admittance();
+        diagonal[line.from] += admittance;
+        diagonal[line.to] += admittance;
+        off_diagonal[line.from].push((line.to, -admittance));
+        off_diagonal[line.to].push((line.from, -admittance));
+    }
+
+    let mut voltages: Vec<Complex> = network.buses.iter().zip(&nominal)
+        .map(|(bus, nominal)| Complex::real(bus.source.or(*nominal).unwrap_or(0.0)))
+        .collect();
+    let (mut iterations, mut converged) = (0, unknowns.is_empty());
+    while !converged && iterations < max_iterations {
+        iterations += 1;
+        let mut largest_change: f64 = 0.0;
+        for bus in &unknowns {
+            let others = off_diagonal[*bus].iter()
+                .fold(Complex::ZERO, |sum, (other, admittance)| sum + *admittance * voltages[*other]);
+            let injected = -network.buses[*bus].load;
+            let voltage = (injected.conj() / voltages[*bus].conj() - others) / diagonal[*bus];
+            largest_change = largest_change.max((voltage - voltages[*bus]).norm());
+            voltages[*bus] = voltage;
+        }
+        if !largest_change.is_finite() { break }
+        converged = largest_change < tolerance;
+    }
+
+    let currents = network.lines.iter()
+        .map(|line| (voltages[line.from] - voltages[line.to]) * line.admittance())
+        .collect();
+    Solution { voltages, currents, iterations, converged }
+}
+
+// voltage of the source feeding each bus, None for buses cut off from every source.
+// a bus reachable from several sources takes the highest of their voltages.
+fn nominal_voltages(network: &Network) -> Vec<Option<f64>> {
+    let mut neighbours = vec![Vec::new(); network.buses.len()];
+    for line in &network.lines {
+        neighbours[line.from].push(line.to);
+        neighbours[line.to].push(line.from);
+    }
+    let mut sources: Vec<(usize, f64)> = network.buses.iter().enumerate()
+        .filter_map(|(index, bus)| bus.source.map(|voltage| (index, voltage)))
+        .collect();
+    sources.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut nominal = vec![None; network.buses.len()];
+    for (source, voltage) in sources {
+        if nominal[source].is_some() { continue }
+        nominal[source] = Some(voltage);
+        let mut queue = VecDeque::from([source]);
+        while let Some(bus) = queue.pop_front() {
+            for next in &neighbours[bus] {
+                if nominal[*next].is_none() {
+                    nominal[*next] = Some(voltage);
+                    queue.push_back(*next);
+                }
+            }
+        }
+    }
+    nominal
+}
+
+// energized buses whose voltage has to be solved for
+fn unknown_buses(network: &Network, nominal: &[Option<f64>]) -> Vec<usize> {
+    (0..network.buses.len())
+        .filter(|bus| network.buses[*bus].source.is_none() && nominal[*bus].is_some())
+        .collect()
+}
+
+// row of each bus in the system of unknowns
+fn positions(bus_count: usize, unknowns: &[usize]) -> Vec<Option<usize>> {
+    let mut position = vec![None; bus_count];
+    for (row, bus) in unknowns.iter().enumerate() {
+        position[*bus] = Some(row);
+    }
+    position
+}
+
+// gaussian elimination with partial pivoting. None if the matrix is singular.
+fn solve_linear(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let size = rhs.len();
+    for column in 0..size {
+        let pivot = (column..size).max_by(|a, b| matrix[*a][column].abs().total_cmp(&matrix[*b][column].abs()))?;
+        if matrix[pivot][column].abs() < f64::EPSILON { return None }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+        for row in column + 1..size {
+            let factor = matrix[row][column] / matrix[column][column];
+            if factor == 0.0 { continue }
+            let (pivot_rows, rows) = matrix.split_at_mut(row);
+            for (value, pivot_value) in rows[0][column..].iter_mut().zip(&pivot_rows[column][column..]) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[column];
+        }
+    }
+    let mut solution = vec![0.0; size];
+    for row in (0..size).rev() {
+        let known: f64 = (row + 1..size).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - known) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(voltage: f64) -> Bus {
+        Bus { source: Some(voltage), load: Complex::ZERO }
+    }
+
+    fn load(power: Complex) -> Bus {
+        Bus { source: None, load: power }
+    }
+
+    // -- basic --
+    // a load behind a resistive line sees the line and itself as a voltage divider
+    #[test]
+    fn test_dc_voltage_divider() {
+        let network = Network {
+            buses: vec![source(1000.0), load(Complex::real(1000.0))],
+            lines: vec![Line { from: 0, to: 1, impedance: Complex::real(10.0) }],
+        };
+        let solution = solve_dc(&network);
+        assert!(solution.converged);
+        // the load is 1000 ohms at 1000 volts
+        assert!((solution.voltages[1].re - 1000.0 * 1000.0 / 1010.0).abs() < 1e-6);
+        assert!((solution.currents[0].re - 1000.0 / 1010.0).abs() < 1e-6);
+    }
+
+    // two equal lines in parallel share the current evenly, one of them attached the other way around
+    #[test]
+    fn test_dc_parallel_lines() {
+        let network = Network {
+            buses: vec![source(400.0), load(Complex::real(4000.0))],
+            lines: vec![
+                Line { from: 0, to: 1, impedance: Complex::real(2.0) },
+                Line { from: 1, to: 0, impedance: Complex::real(2.0) },
+            ],
+        };
+        let solution = solve_dc(&network);
+        assert!((solution.currents[0].re + solution.currents[1].re).abs() < 1e-9);
+    }
+
+    // the load receives the power it asks for, the source also covers the losses of the line
+    #[test]
+    fn test_ac_power_balance() {
+        let impedance = Complex::new(1.0, 2.0);
+        let demand = Complex::new(100_000.0, 20_000.0);
+        let network = Network {
+            buses: vec![source(10_000.0), load(demand)],
+            lines: vec![Line { from: 0, to: 1, impedance }],
+        };
+        let solution = solve_ac(&network, 100, 1e-6);
+        assert!(solution.converged);
+
+        let received = solution.voltages[1] * solution.currents[0].conj();
+        assert!((received - demand).norm() < 1.0);
+        let losses = impedance * solution.currents[0].norm_squared();
+        assert!((solution.line_power(&network, 0) - demand - losses).norm() < 1.0);
+        assert!(solution.voltages[1].norm() < 10_000.0);
+        assert!(solution.voltages[1].arg() < 0.0);
+    }
+
+    // -- edge cases --
+    // buses cut off from every source stay dead instead of making the system singular
+    #[test]
+    fn test_unenergized_island() {
+        let network = Network {
+            buses: vec![source(1000.0), load(Complex::real(10.0)), load(Complex::real(10.0)), Bus::default()],
+            lines: vec![
+                Line { from: 0, to: 1, impedance: Complex::real(1.0) },
+                Line { from: 2, to: 3, impedance: Complex::ZERO },
+            ],
+        };
+        for solution in [solve_dc(&network), solve_ac(&network, 100, 1e-6)] {
+            assert!(solution.converged);
+            assert_eq!(solution.voltages[2], Complex::ZERO);
+            assert_eq!(solution.currents[1], Complex::ZERO);
+            assert!(solution.voltages[1].re > 990.0);
+        }
+    }
+
+    // asking for more power than the line can deliver does not converge
+    #[test]
+    fn test_ac_overload_diverges() {
+        let network = Network {
+            buses: vec![source(100.0), load(Complex::real(1_000_000.0))],
+            lines: vec![Line { from: 0, to: 1, impedance: Complex::real(1.0) }],
+        };
+        assert!(!solve_ac(&network, 200, 1e-6).converged);
+    }
+}
@@ -0,0 +1,190 @@
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
+use super::cables::*;
+use super::spark_movement::*;
+
+pub struct SparkContactsPlugin;
+impl Plugin for SparkContactsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<SparkContacts>()
+        .add_systems(Update, resolve_spark_contacts.after(move_spark));
+    }
+}
+
+// what happens when two sparks on the same cable meet
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ContactRule {
+    // they block each other and cannot pass
+    #[default]
+    Collide,
+    // the spark with the higher voltage absorbs the other one
+    Merge,
+    // they push each other apart
+    Repel,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SparkContacts {
+    // how close two sparks have to get along their cable to touch, in metres
+    pub distance: f32,
+    // how far apart repelled sparks are pushed, in metres
+    pub repel_distance: f32,
+    pub rule: ContactRule,
+}
+
+impl Default for SparkContacts {
+    fn default() -> Self {
+        SparkContacts { distance: 1.0, repel_distance: 5.0, rule: default() }
+    }
+}
+
+/*
+triggered on a spark that touched another one on its cable, every frame they are pushed into each other.
+for merges, the event is triggered on the spark that absorbed the other one, which is despawned right after.
+*/
+#[derive(EntityEvent, Debug)]
+pub struct SparkContact {
+    pub entity: Entity,
+    pub other: Entity,
+    pub rule: ContactRule,
+}
+
+// move two distances along a cable apart to the given gap around their midpoint, keeping both on the cable
+pub fn separate(a: f32, b: f32, gap: f32, length: f32) -> (f32, f32) {
+    let gap = gap.min(length);
+    let middle = ((a + b) / 2.0).clamp(gap / 2.0, length - gap / 2.0);
+    match a <= b {
+        true => (middle - gap / 2.0, middle + gap / 2.0),
+        false => (middle + gap / 2.0, middle - gap / 2.0),
+    }
+}
+
+// whichever of two sparks survives a merge: the one with the higher voltage, or the older one
+fn merge_survivor(a: (Entity, &Spark), b: (Entity, &Spark)) -> (Entity, Entity) {
+    match a.1.voltage.total_cmp(&b.1.voltage).then_with(|| b.0.cmp(&a.0)).is_ge() {
+        true => (a.0, b.0),
+        false => (b.0, a.0),
+    }
+}
+
+fn resolve_spark_contacts(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut Transform)>,
+    cables: Query<&Cable>,
+    contacts: Res<SparkContacts>,
+) {
+    // sparks of each cable, in order along it
+    let mut riders: HashMap<Entity, Vec<(Entity, f32)>> = HashMap::new();
+    for (spark_entity, spark, _) in &sparks {
+        riders.entry(spark.connected_to_cable_entity).or_default().push((spark_entity, spark.dist_along));
+    }
+
+    let mut merged = HashSet::new();
+    for (cable_entity, mut cable_riders) in riders {
+        if cable_riders.len() < 2 { continue; }
+        let Ok(cable) = cables.get(cable_entity) else { continue };
+        cable_riders.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        for pair in cable_riders.windows(2) {
+            let ((first, _), (second, _)) = (pair[0], pair[1]);
+            if merged.contains(&first) || merged.contains(&second) { continue; }
+            let Ok([(_, mut first_spark, mut first_transform), (_, mut second_spark, mut second_transform)]) = sparks.get_many_mut([first, second]) else { continue };
+            if (second_spark.dist_along - first_spark.dist_along).abs() >= contacts.distance { continue; }
+
+            let gap = match contacts.rule {
+                ContactRule::Collide => contacts.distance,
+                ContactRule::Repel => contacts.repel_distance,
+                ContactRule::Merge => {
+                    let (survivor, absorbed) = merge_survivor((first, &first_spark), (second, &second_spark));
+                    debug!("spark {} absorbed spark {}", survivor, absorbed);
+                    merged.insert(absorbed);
+                    commands.trigger(SparkContact { entity: survivor, other: absorbed, rule: ContactRule::Merge });
+                    commands.entity(absorbed).try_despawn();
+                    continue;
+                },
+            };
+            (first_spark.dist_along, second_spark.dist_along) = separate(first_spark.dist_along, second_spark.dist_along, gap, cable.length());
+            first_transform.translation = cable.pos_at_distance(first_spark.dist_along);
+            second_transform.translation = cable.pos_at_distance(second_spark.dist_along);
+            commands.trigger(SparkContact { entity: first, other: second, rule: contacts.rule });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+
+    fn spawn_sparks(world: &mut World, rule: ContactRule, positions: &[(f32, f32)]) -> (Entity, Vec<Entity>) {
+        world.insert_resource(SparkContacts { rule, ..default() });
+        let mut cable = Cable::default();
+        cable.set_segments(vec![Vec3::ZERO, Vec3::X * 20.0]);
+        let cable = world.spawn(cable).id();
+        let sparks = positions.iter()
+            .map(|(along, voltage)| {
+                let mut spark = Spark::new(cable, 1.0);
+                spark.dist_along = *along;
+                spark.voltage = *voltage;
+                world.spawn(spark).id()
+            })
+            .collect();
+        (cable, sparks)
+    }
+
+    fn dist_along(world: &World, spark: Entity) -> f32 {
+        world.get::<Spark>(spark).unwrap().dist_along
+    }
+
+    // -- basic --
+    // colliding sparks are kept apart at the contact distance
+    #[test]
+    fn test_collide() {
+        let mut world = World::new();
+        let (_, sparks) = spawn_sparks(&mut world, ContactRule::Collide, &[(10.0, 1.0), (10.2, 1.0)]);
+        world.run_system_once(resolve_spark_contacts).unwrap();
+        assert!((dist_along(&world, sparks[1]) - dist_along(&world, sparks[0]) - 1.0).abs() < 0.001);
+        assert!((world.get::<Transform>(sparks[0]).unwrap().translation.x - 9.6).abs() < 0.001);
+    }
+
+    // the spark with the higher voltage absorbs the other one
+    #[test]
+    fn test_merge() {
+        #[derive(Resource, Default)]
+        struct Contacts(Vec<(Entity, Entity)>);
+
+        let mut app = App::new();
+        app
+            .init_resource::<Contacts>()
+            .add_observer(|trigger: On<SparkContact>, mut contacts: ResMut<Contacts>| contacts.0.push((trigger.entity, trigger.other)));
+        let world = app.world_mut();
+        let (_, sparks) = spawn_sparks(world, ContactRule::Merge, &[(5.0, 100.0), (5.5, 400.0), (15.0, 1.0)]);
+        world.run_system_once(resolve_spark_contacts).unwrap();
+
+        assert!(world.get_entity(sparks[0]).is_err());
+        assert!(world.get_entity(sparks[1]).is_ok() && world.get_entity(sparks[2]).is_ok());
+        assert_eq!(world.resource::<Contacts>().0, vec![(sparks[1], sparks[0])]);
+    }
+
+    // repelled sparks are pushed apart, but not off their cable
+    #[test]
+    fn test_repel() {
+        let mut world = World::new();
+        let (_, sparks) = spawn_sparks(&mut world, ContactRule::Repel, &[(19.5, 1.0), (19.8, 1.0)]);
+        world.run_system_once(resolve_spark_contacts).unwrap();
+        assert_eq!(dist_along(&world, sparks[1]), 20.0);
+        assert_eq!(dist_along(&world, sparks[0]), 15.0);
+    }
+
+    // -- edge cases --
+    // sparks on different cables never touch, however close they are
+    #[test]
+    fn test_different_cables() {
+        let mut world = World::new();
+        let (_, sparks) = spawn_sparks(&mut world, ContactRule::Merge, &[(5.0, 1.0)]);
+        let (_, others) = spawn_sparks(&mut world, ContactRule::Merge, &[(5.0, 1.0)]);
+        world.run_system_once(resolve_spark_contacts).unwrap();
+        assert!(world.get_entity(sparks[0]).is_ok() && world.get_entity(others[0]).is_ok());
+        assert_eq!(separate(3.0, 1.0, 4.0, 10.0), (4.0, 0.0));
+    }
+}
@@ -1,21 +1,33 @@
 use bevy::{ color::palettes::css::YELLOW, prelude::*};
 use super::cables::*;
+use super::controllers::*;
+use super::junctions::*;
+use super::phases::*;
+use super::spark_physics::*;
+use super::substation::*;
+
+// voltage sparks start out with, in volts
+pub static SPARK_VOLTAGE: f32 = 10_000.0;
 
 pub struct SparkMovementPlugin;
 impl Plugin for SparkMovementPlugin {
     fn build(&self, app: &mut App) {
         app
         .init_gizmo_group::<SparkGizmos>()
+        .add_observer(follow_regenerated_cable)
+        .add_observer(evict_sparks_from_removed_cable)
         .add_systems(Update, (move_spark, spark_gizmos));
     }
 }
 
 #[derive(Component)]
-#[require(Transform)]
+#[require(Transform, JunctionPolicy, PhaseRule, SparkController)]
 pub struct Spark { 
     pub(crate) connected_to_cable_entity: Entity,
-    pub speed: f32, // per second
-    pub(crate) dist_along: f32,
+    pub speed: f32, // metres per second
+    pub(crate) dist_along: f32, // metres along the connected cable, measured from its start
+    pub voltage: f32, // volts, changed by passing through substations
+    pub phase: Phase, // taken on from the cables the spark enters
 }
 
 impl Spark {
@@ -24,93 +36,177 @@ impl Spark {
             connected_to_cable_entity: start_cable_entity,
             speed,
             dist_along: 0.,
+            voltage: SPARK_VOLTAGE,
+            phase: Phase::L1,
         }
     }
 }
 
-fn move_spark(
-    mut sparks: Query<(&mut Spark, &mut Transform)>,
+// advance every spark by its throttle. sparks waiting at a junction stay put until it is resolved,
+// sparks with physics are moved by their own integration instead.
+#[allow(clippy::type_complexity)]
+pub(crate) fn move_spark(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut Transform, &PhaseRule, &SparkThrottle), (Without<AtJunction>, Without<SparkPhysics>)>,
     time: Res<Time>,
-    keyboard: Res<ButtonInput<KeyCode>>,
-    cables: Query<(&Cable, &StartsFrom, &EndsAt)>,
-    cable_start_connections: Query<&CablesStartingHere>,
-    cable_end_connections: Query<&CablesEndingHere>,
+    network: CableNetwork,
 ) {
-    if let Ok((mut spark, mut spark_transform)) = sparks.single_mut() {
-        if keyboard.pressed(KeyCode::KeyW) {
-            spark.dist_along += spark.speed * time.delta_secs();
-            set_spark_transform_and_dist_along(&mut spark, &mut spark_transform, &cables, &cable_start_connections, &cable_end_connections);
-        } else if keyboard.pressed(KeyCode::KeyS) {
-            spark.dist_along -= spark.speed * time.delta_secs();
-            set_spark_transform_and_dist_along(&mut spark, &mut spark_transform, &cables, &cable_start_connections, &cable_end_connections);
-        }
+    for (spark_entity, mut spark, mut spark_transform, phase_rule, throttle) in &mut sparks {
+        if throttle.0 == 0.0 { continue; }
+        let distance = throttle.0 * spark.speed * time.delta_secs();
+        advance_spark(&mut commands, spark_entity, &mut spark, &mut spark_transform, *phase_rule, &network, distance);
     }
 }
 
-fn set_spark_transform_and_dist_along(
+// move the spark the given distance along its cable, announcing what changed about it on the way.
+// returns whether it stopped at a junction, which is inserted on it.
+pub(crate) fn advance_spark(
+    commands: &mut Commands,
+    spark_entity: Entity,
+    spark: &mut Spark,
+    spark_transform: &mut Transform,
+    phase_rule: PhaseRule,
+    network: &CableNetwork,
+    distance: f32,
+) -> bool {
+    let (voltage, phase) = (spark.voltage, spark.phase);
+    spark.dist_along += distance;
+    let junction = set_spark_transform_and_dist_along(spark, spark_transform, phase_rule, network);
+    if spark.voltage != voltage {
+        commands.trigger(VoltageChanged { entity: spark_entity, from: voltage, to: spark.voltage });
+    }
+    if spark.phase != phase {
+        commands.trigger(PhaseShifted { entity: spark_entity, from: phase, to: spark.phase });
+    }
+    match junction {
+        Some(junction) => {
+            debug!("spark reached a junction with {} choices", junction.choices.len());
+            commands.entity(spark_entity).insert(junction);
+            true
+        },
+        None => false,
+    }
+}
+
+// place the spark at its distance along its cable, moving it across connections when it overshoots.
+// if a connection offers more than one way onwards, the spark stops there and the junction is returned for the caller to insert.
+// passing through a substation or onto another phase changes the spark, it is up to the caller to announce it.
+pub(crate) fn set_spark_transform_and_dist_along(
     spark: &mut Spark, 
     spark_transform: &mut Transform, 
-    cables: &Query<(&Cable, &StartsFrom, &EndsAt)>,
-    cable_start_connections: &Query<&CablesStartingHere>,
-    cable_end_connections: &Query<&CablesEndingHere>,
-) {
-    let (connected_cable, prev_cable_connection, next_cable_connection) = cables.get(spark.connected_to_cable_entity).unwrap();
+    phase_rule: PhaseRule,
+    network: &CableNetwork,
+) -> Option<AtJunction> {
+    // the cable may have been despawned this frame, eviction will take care of the spark
+    let Ok((connected_cable, prev_cable_connection, next_cable_connection, _, _)) = network.cables.get(spark.connected_to_cable_entity) else { return None };
+
+    // if the distance is within the cable
+    let cable_length = connected_cable.length();
+    if (0.0..=cable_length).contains(&spark.dist_along) {
+        spark_transform.translation = connected_cable.pos_at_distance(spark.dist_along);
+        return None;
+    }
 
-    // if t is within bounds
-    if Interval::UNIT.contains(spark.dist_along) {
-        spark_transform.translation = connected_cable.get_pos_along(spark.dist_along);
-        return; 
-    } 
     // if not, we will have to get to the next cable in the relationship, OR stop if there is none
-    else if spark.dist_along > 1.0 {
-        // overshoot, get next
-        match cable_start_connections.get(next_cable_connection.0) {
-            Ok(cables_starting_at_next_connector) => {
-                // for now, just get the first starter
-                match cables_starting_at_next_connector.collection().iter().next() {
-                    // next cable exists, move to it
-                    Some(next_cable_entity) => {
-                        spark.dist_along = spark.dist_along - 1.0;
-                        spark.connected_to_cable_entity = *next_cable_entity;
-                        // try again on new cable
-                        set_spark_transform_and_dist_along(spark, spark_transform, cables, cable_start_connections, cable_end_connections);
-                        return;
-                    },
-                    // end of the line, just clamp t at 1 and stay on the same cable
-                    None => {
-                        spark.dist_along = 1.0;
-                        spark_transform.translation = connected_cable.get_pos_along(spark.dist_along);
-                        return; 
-                    }
-                }
-            },
-            Err(_) => {
-                unreachable!();
+    let (travel, connection, leftover, clamped, incoming) = if spark.dist_along > cable_length {
+        // overshoot, look at cables starting at the next connector
+        (Travel::Forward, next_cable_connection.0, spark.dist_along - cable_length, cable_length, connected_cable.end_direction())
+    } else {
+        // undershoot, look at cables ending at the prev connector
+        (Travel::Backward, prev_cable_connection.0, -spark.dist_along, 0.0, connected_cable.start_direction().map(|dir| -dir))
+    };
+    let mut choices = network.gather_choices(connection, travel, spark, phase_rule);
+
+    match choices.len() {
+        // end of the line, just clamp the distance and stay on the same cable
+        0 => {
+            spark.dist_along = clamped;
+            spark_transform.translation = connected_cable.pos_at_distance(spark.dist_along);
+            None
+        },
+        // only one way onwards, move to it and try again on the new cable with the distance left over
+        1 => {
+            let next = choices.remove(0);
+            let (next_cable, _, _, _, _) = network.cables.get(next.cable).unwrap();
+            spark.connected_to_cable_entity = next.cable;
+            spark.voltage = next.voltage;
+            spark.phase = next.phase.unwrap_or(spark.phase);
+            spark.dist_along = match next.travel {
+                Travel::Forward => leftover,
+                Travel::Backward => next_cable.length() - leftover,
+            };
+            set_spark_transform_and_dist_along(spark, spark_transform, phase_rule, network)
+        },
+        // junction, wait at the connection until a choice has been made
+        _ => {
+            spark.dist_along = clamped;
+            spark_transform.translation = connected_cable.pos_at_distance(spark.dist_along);
+            let incoming = incoming.unwrap_or(choices[0].direction);
+            Some(AtJunction::new(connection, travel, incoming, choices, leftover))
+        }
+    }
+}
+
+// keep sparks on the geometry of their cable when it gets regenerated, e.g. because a tower moved
+fn follow_regenerated_cable(
+    trigger: On<CableGenerated>,
+    mut sparks: Query<(&mut Spark, &mut Transform)>,
+    cables: Query<&Cable>,
+) {
+    let Ok(cable) = cables.get(trigger.entity) else { return };
+    for (mut spark, mut spark_transform) in &mut sparks {
+        if spark.connected_to_cable_entity != trigger.entity { continue; }
+        spark.dist_along = spark.dist_along.clamp(0.0, cable.length());
+        spark_transform.translation = cable.pos_at_distance(spark.dist_along);
+    }
+}
+
+// triggered on a spark that was riding a cable which got removed, when there was no other cable left to move it to.
+// the spark is despawned right after.
+#[derive(EntityEvent)]
+pub struct SparkLost {
+    pub entity: Entity,
+}
+
+// move sparks off a cable that is being removed, onto whichever remaining cable passes closest to them.
+fn evict_sparks_from_removed_cable(
+    trigger: On<Remove, Cable>,
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut Transform, &PhaseRule, Option<&mut AtJunction>)>,
+    network: CableNetwork,
+    cable_index: Res<CableSpatialIndex>,
+) {
+    let removed_cable = trigger.entity;
+    for (spark_entity, mut spark, mut spark_transform, phase_rule, junction) in &mut sparks {
+        // a waiting spark cannot take the removed cable anymore
+        if let Some(mut junction) = junction {
+            junction.choices.retain(|choice| choice.cable != removed_cable);
+            junction.selected = junction.selected.min(junction.choices.len().saturating_sub(1));
+            if junction.choices.is_empty() || spark.connected_to_cable_entity == removed_cable {
+                commands.entity(spark_entity).remove::<AtJunction>();
             }
         }
-    } else if spark.dist_along < 0.0 {
-        // undershoot, get prev
-        match cable_end_connections.get(prev_cable_connection.0) {
-            Ok(cables_ending_at_prev_connector) => {
-                match cables_ending_at_prev_connector.collection().iter().next() {
-                    // prev cable exists, move to it
-                    Some(prev_cable_entity) => {
-                        spark.dist_along = spark.dist_along + 1.0;
-                        spark.connected_to_cable_entity = *prev_cable_entity;
-                        // try again on new cable
-                        set_spark_transform_and_dist_along(spark, spark_transform, cables, cable_start_connections, cable_end_connections);
-                        return;
-                    },
-                    // end of the line, just clamp t at 0 and stay on the same cable
-                    None => {
-                        spark.dist_along = 0.0;
-                        spark_transform.translation = connected_cable.get_pos_along(spark.dist_along);
-                        return; 
-                    }
-                }
+
+        if spark.connected_to_cable_entity != removed_cable { continue; }
+
+        // the removed cable may still be in the index, depending on which observer runs first.
+        // the spark may only go where it could have gone at a junction.
+        let can_ride = |cable_entity| cable_entity != removed_cable && network.can_ride(cable_entity, spark.voltage, spark.phase, *phase_rule);
+        let nearest = cable_index.nearest_filtered(spark_transform.translation, f32::INFINITY, can_ride)
+            .and_then(|hit| network.cables.get(hit.cable).ok().map(|(cable, _, _, _, cable_phase)| (hit.cable, cable, cable_phase, hit.along)));
+
+        match nearest {
+            Some((cable_entity, cable, cable_phase, along)) => {
+                debug!("spark relocated from removed cable {} to {}", removed_cable, cable_entity);
+                spark.connected_to_cable_entity = cable_entity;
+                spark.dist_along = along;
+                spark.phase = cable_phase.map_or(spark.phase, |cable_phase| cable_phase.phase);
+                spark_transform.translation = cable.pos_at_distance(along);
             },
-            Err(_) => {
-                unreachable!();
+            None => {
+                warn!("spark lost, its cable {} was removed and there is no other cable it can go to", removed_cable);
+                commands.trigger(SparkLost { entity: spark_entity });
+                commands.entity(spark_entity).try_despawn();
             }
         }
     }
@@ -126,4 +222,91 @@ fn spark_gizmos(
     for spark_transform in spark {
         gizmos.sphere(spark_transform.to_isometry(), 1.0, YELLOW);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+    use crate::electric_grid::breakers::*;
+
+    #[derive(Resource, Default)]
+    struct Lost(Vec<Entity>);
+
+    fn spawn_indexed_cable(world: &mut World, points: Vec<Vec3>) -> Entity {
+        let from = world.spawn(CableConnection::default()).id();
+        let to = world.spawn(CableConnection::default()).id();
+        let entity = spawn_generated_cable(world, from, to, points.clone());
+        world.resource_mut::<CableSpatialIndex>().insert(entity, &points);
+        entity
+    }
+
+    // a spark on a removed cable moves to the closest remaining cable
+    #[test]
+    fn test_spark_relocated_when_cable_removed() {
+        let mut app = App::new();
+        app
+        .init_resource::<CableSpatialIndex>()
+        .add_observer(evict_sparks_from_removed_cable);
+        let world = app.world_mut();
+        let removed = spawn_indexed_cable(world, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let near = spawn_indexed_cable(world, vec![Vec3::Z, Vec3::new(10.0, 0.0, 1.0)]);
+        spawn_indexed_cable(world, vec![Vec3::Z * 5.0, Vec3::new(10.0, 0.0, 5.0)]);
+        let spark = world.spawn((Spark::new(removed, 1.0), Transform::from_translation(Vec3::X * 3.0))).id();
+
+        world.despawn(removed);
+        world.flush();
+
+        let spark = world.get::<Spark>(spark).unwrap();
+        assert_eq!(spark.connected_to_cable_entity, near);
+        assert!((spark.dist_along - 3.0).abs() < 0.001);
+    }
+
+    // with nowhere to go, the spark is lost instead of panicking later on
+    #[test]
+    fn test_spark_lost_when_last_cable_removed() {
+        let mut app = App::new();
+        app
+        .init_resource::<CableSpatialIndex>()
+        .add_observer(evict_sparks_from_removed_cable);
+        let world = app.world_mut();
+        let removed = spawn_indexed_cable(world, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let spark = world.spawn(Spark::new(removed, 1.0)).id();
+
+        world.despawn(removed);
+        world.flush();
+
+        assert!(world.get_entity(spark).is_err());
+    }
+
+    // -- edge cases --
+    // the spark skips closer cables it could not have taken at a junction, and is lost if there are only such cables
+    #[test]
+    fn test_relocation_follows_junction_rules() {
+        let mut app = App::new();
+        app
+        .init_resource::<CableSpatialIndex>()
+        .init_resource::<Lost>()
+        .add_observer(evict_sparks_from_removed_cable)
+        .add_observer(|trigger: On<SparkLost>, mut lost: ResMut<Lost>| lost.0.push(trigger.entity));
+        let world = app.world_mut();
+        let removed = spawn_indexed_cable(world, vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let open = spawn_indexed_cable(world, vec![Vec3::Z, Vec3::new(10.0, 0.0, 1.0)]);
+        world.entity_mut(open).insert(Breaker { state: BreakerState::Open, ..default() });
+        let other_phase = spawn_indexed_cable(world, vec![Vec3::Z * 2.0, Vec3::new(10.0, 0.0, 2.0)]);
+        world.entity_mut(other_phase).insert(CablePhase { circuit: 0, phase: Phase::L2 });
+        let low_voltage = spawn_indexed_cable(world, vec![Vec3::Z * 3.0, Vec3::new(10.0, 0.0, 3.0)]);
+        world.entity_mut(low_voltage).insert(VoltageRating { min: 0.0, max: 1_000.0 });
+        let allowed = spawn_indexed_cable(world, vec![Vec3::Z * 4.0, Vec3::new(10.0, 0.0, 4.0)]);
+        let spark = world.spawn((Spark::new(removed, 1.0), Transform::from_translation(Vec3::X * 3.0))).id();
+
+        world.despawn(removed);
+        world.flush();
+        assert_eq!(world.get::<Spark>(spark).unwrap().connected_to_cable_entity, allowed);
+
+        world.despawn(allowed);
+        world.flush();
+        assert!(world.get_entity(spark).is_err());
+        assert_eq!(world.resource::<Lost>().0, vec![spark]);
+    }
+}
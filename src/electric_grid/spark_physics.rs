@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+use super::controllers::*;
+use super::junctions::*;
+use super::phases::*;
+use super::spark_movement::*;
+
+use crate::actions::*;
+
+pub struct SparkPhysicsPlugin;
+impl Plugin for SparkPhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_systems(Update, device_boost.before(move_spark))
+        .add_systems(FixedUpdate, integrate_spark_physics);
+    }
+}
+
+/*
+makes a spark move with momentum instead of at a fixed speed. its throttle accelerates it, gravity pulls it
+along the slope of the cable so it speeds up going down into the sag and slows down climbing out of it, and drag slows it down.
+it is integrated on the fixed timestep so the same input always gives the same ride.
+the spark keeps its speed through junctions, and turning back at one sends it back the way it came at the same speed.
+*/
+#[derive(Component, Clone, Copy, Debug)]
+#[require(SparkBoost)]
+pub struct SparkPhysics {
+    // metres per second along the cable, positive towards its end
+    pub velocity: f32,
+    // metres per second squared at full throttle
+    pub acceleration: f32,
+    pub gravity: f32,
+    // quadratic drag, per metre
+    pub drag: f32,
+    // extra acceleration while boosting
+    pub boost: f32,
+    pub max_speed: f32,
+    // direction the spark was last moving in, to keep it going the same way when it moves onto a cable facing the other way
+    pub(crate) heading: Vec3,
+    // cable the spark stopped at a junction on and how fast it was going
+    pub(crate) stopped: Option<(Entity, f32)>,
+}
+
+impl Default for SparkPhysics {
+    fn default() -> Self {
+        SparkPhysics { velocity: 0.0, acceleration: 8.0, gravity: 9.81, drag: 0.01, boost: 12.0, max_speed: 40.0, heading: Vec3::ZERO, stopped: None }
+    }
+}
+
+impl SparkPhysics {
+    // velocity after a step of dt seconds on a cable rising by `slope` metres per metre travelled towards its end
+    pub fn step_velocity(&self, throttle: f32, boosting: bool, slope: f32, dt: f32) -> f32 {
+        let mut acceleration = throttle * self.acceleration - self.gravity * slope - self.drag * self.velocity * self.velocity.abs();
+        if boosting && throttle != 0.0 {
+            acceleration += throttle.signum() * self.boost;
+        }
+        (self.velocity + acceleration * dt).clamp(-self.max_speed, self.max_speed)
+    }
+}
+
+// whether the spark is boosting, set by its controller like the throttle
+#[derive(Component, Clone, Copy, Default, PartialEq, Debug)]
+pub struct SparkBoost(pub bool);
+
+fn device_boost(
+    mut sparks: Query<(&SparkController, &mut SparkBoost)>,
+    input: ControllerActions,
+) {
+    for (controller, mut boost) in &mut sparks {
+        boost.0 = input.pressed(controller, Action::Boost);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn integrate_spark_physics(
+    mut commands: Commands,
+    mut sparks: Query<(Entity, &mut Spark, &mut Transform, &PhaseRule, &SparkThrottle, &SparkBoost, &mut SparkPhysics), Without<AtJunction>>,
+    time: Res<Time>,
+    network: CableNetwork,
+) {
+    let dt = time.delta_secs();
+    for (spark_entity, mut spark, mut spark_transform, phase_rule, throttle, boost, mut physics) in &mut sparks {
+        // leaving a junction, either onto the chosen cable or back the way the spark came
+        if let Some((stopped_on, speed)) = physics.stopped.take() {
+            if spark.connected_to_cable_entity == stopped_on {
+                physics.heading = -physics.heading;
+            }
+            physics.velocity = speed;
+        }
+
+        let Ok((cable, _, _, _, _)) = network.cables.get(spark.connected_to_cable_entity) else { continue };
+        let Some(tangent) = cable.tangent_at_distance(spark.dist_along) else { continue };
+        if physics.heading != Vec3::ZERO && (*tangent * physics.velocity.signum()).dot(physics.heading) < 0.0 {
+            physics.velocity = -physics.velocity;
+        }
+        physics.velocity = physics.step_velocity(throttle.0, boost.0, tangent.y, dt);
+
+        let cable_before = spark.connected_to_cable_entity;
+        if advance_spark(&mut commands, spark_entity, &mut spark, &mut spark_transform, *phase_rule, &network, physics.velocity * dt) {
+            physics.stopped = Some((spark.connected_to_cable_entity, physics.velocity.abs()));
+            physics.velocity = 0.0;
+            continue;
+        }
+
+        let Ok((cable, _, _, _, _)) = network.cables.get(spark.connected_to_cable_entity) else { continue };
+        // ran into the end of the line
+        let at_end = match physics.velocity > 0.0 {
+            true => spark.dist_along >= cable.length(),
+            false => spark.dist_along <= 0.0,
+        };
+        if at_end && spark.connected_to_cable_entity == cable_before {
+            physics.velocity = 0.0;
+        }
+        if physics.velocity != 0.0
+            && let Some(tangent) = cable.tangent_at_distance(spark.dist_along) {
+            physics.heading = *tangent * physics.velocity.signum();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+    use crate::electric_grid::cables::*;
+
+    fn physics_test_world(points: Vec<Vec3>) -> (World, Entity) {
+        let mut world = World::new();
+        let mut time = Time::<()>::default();
+        time.advance_by(std::time::Duration::from_secs_f32(1.0 / 64.0));
+        world.insert_resource(time);
+        let (from, to) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+        let cable = spawn_generated_cable(&mut world, from, to, points);
+        (world, cable)
+    }
+
+    fn ride(world: &mut World, spark: Entity, steps: usize) -> (f32, f32) {
+        for _ in 0..steps {
+            world.run_system_once(integrate_spark_physics).unwrap();
+        }
+        (world.get::<Spark>(spark).unwrap().dist_along, world.get::<SparkPhysics>(spark).unwrap().velocity)
+    }
+
+    // -- basic --
+    // going downhill speeds the spark up, climbing slows it down
+    #[test]
+    fn test_slope() {
+        let physics = SparkPhysics { velocity: 5.0, ..default() };
+        assert!(physics.step_velocity(0.0, false, -0.5, 0.1) > 5.0);
+        assert!(physics.step_velocity(0.0, false, 0.5, 0.1) < 5.0);
+        assert!(physics.step_velocity(1.0, true, 0.0, 0.1) > physics.step_velocity(1.0, false, 0.0, 0.1));
+    }
+
+    // drag keeps the speed of a spark at full throttle on flat cable from growing forever
+    #[test]
+    fn test_drag_limits_speed() {
+        let mut physics = SparkPhysics { max_speed: f32::INFINITY, ..default() };
+        for _ in 0..10_000 {
+            physics.velocity = physics.step_velocity(1.0, false, 0.0, 1.0 / 64.0);
+        }
+        let terminal = (physics.acceleration / physics.drag).sqrt();
+        assert!((physics.velocity - terminal).abs() < 0.01);
+    }
+
+    // a spark let go at the top of a sagging cable slides down into the dip and up the other side, the same way every time
+    #[test]
+    fn test_slides_into_sag() {
+        let sag = vec![Vec3::new(0.0, 10.0, 0.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(20.0, 10.0, 0.0)];
+        let (mut world, cable) = physics_test_world(sag.clone());
+        let spark = world.spawn((Spark::new(cable, 1.0), SparkPhysics::default())).id();
+        let (along, velocity) = ride(&mut world, spark, 192);
+        assert!(along > 15.0 && velocity > 0.0);
+
+        let (mut other_world, other_cable) = physics_test_world(sag);
+        let other_spark = other_world.spawn((Spark::new(other_cable, 1.0), SparkPhysics::default())).id();
+        assert_eq!(ride(&mut other_world, other_spark, 192), (along, velocity));
+    }
+
+    // -- edge cases --
+    // hitting the end of the line stops the spark dead
+    #[test]
+    fn test_stops_at_end_of_line() {
+        let (mut world, cable) = physics_test_world(vec![Vec3::ZERO, Vec3::X * 10.0]);
+        let mut spark = Spark::new(cable, 1.0);
+        spark.dist_along = 9.9;
+        let spark = world.spawn((spark, SparkPhysics { velocity: 20.0, ..default() })).id();
+        assert_eq!(ride(&mut world, spark, 1), (10.0, 0.0));
+    }
+}
@@ -0,0 +1,247 @@
+use bevy::{color::palettes::css::{AQUA, PURPLE}, prelude::*};
+use thiserror::Error;
+use super::cables::*;
+
+static SUBSTATION_GIZMO_SIZE: f32 = 6.0;
+
+pub struct SubstationPlugin;
+impl Plugin for SubstationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_gizmo_group::<SubstationGizmos>()
+        .add_systems(Update, substation_gizmos);
+    }
+}
+
+/*
+a node where several lines end and others begin, with a transformer in between.
+lines are attached to the substation's ports, which are cable connections spawned as its children.
+a spark arriving at an incoming port may leave through any outgoing port with its voltage multiplied by the ratio,
+and a spark arriving at an outgoing port may leave through any incoming port with its voltage divided by it.
+*/
+#[derive(Component, Clone, Copy, Debug)]
+#[require(Transform, Visibility)]
+pub struct Substation {
+    // outgoing voltage over incoming voltage, e.g. 10.0 for a step up transformer
+    pub ratio: f32,
+}
+
+impl Substation {
+    // voltage of a spark leaving through the given side after arriving at the other one
+    pub fn transform_voltage(&self, voltage: f32, leaving_through: SubstationSide) -> f32 {
+        match leaving_through {
+            SubstationSide::Outgoing => voltage * self.ratio,
+            SubstationSide::Incoming => voltage / self.ratio,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SubstationSide {
+    Incoming,
+    Outgoing,
+}
+
+impl SubstationSide {
+    pub fn opposite(self) -> Self {
+        match self {
+            SubstationSide::Incoming => SubstationSide::Outgoing,
+            SubstationSide::Outgoing => SubstationSide::Incoming,
+        }
+    }
+}
+
+// a cable connection belonging to the substation it is a child of
+#[derive(Component, Clone, Copy, Debug)]
+#[require(CableConnection)]
+pub struct SubstationPort {
+    pub side: SubstationSide,
+}
+
+/*
+the range of spark voltages a cable may carry. sparks are only offered cables rated for their voltage,
+counting the change they go through when passing a substation. cables without a rating accept any voltage.
+*/
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct VoltageRating {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl VoltageRating {
+    pub fn accepts(&self, voltage: f32) -> bool {
+        (self.min..=self.max).contains(&voltage)
+    }
+}
+
+// triggered on a spark whose voltage was changed by a substation it passed through
+#[derive(EntityEvent, Debug)]
+pub struct VoltageChanged {
+    pub entity: Entity,
+    pub from: f32,
+    pub to: f32,
+}
+
+pub struct SpawnedSubstation {
+    pub entity: Entity,
+    pub incoming: Vec<Entity>,
+    pub outgoing: Vec<Entity>,
+}
+
+#[derive(Debug, Error)]
+pub enum SubstationError {
+    #[error("substation ratio must be finite and above 0, got {0}")]
+    InvalidRatio(f32),
+}
+
+// spawn a substation with a port at each of the given offsets, in the substation's local space.
+// cables can then be attached to the returned ports like to any other cable connection.
+// a ratio that would turn voltages into 0, infinity or NaN is rejected, and nothing is spawned.
+pub fn spawn_substation(commands: &mut Commands, transform: Transform, substation: Substation, incoming: &[Vec3], outgoing: &[Vec3]) -> Result<SpawnedSubstation, SubstationError> {
+    if !substation.ratio.is_finite() || substation.ratio <= 0.0 {
+        return Err(SubstationError::InvalidRatio(substation.ratio));
+    }
+    let entity = commands.spawn((
+        Name::new("Substation"),
+        substation,
+        transform,
+    )).id();
+    let mut spawn_ports = |offsets: &[Vec3], side: SubstationSide| -> Vec<Entity> {
+        offsets.iter().enumerate()
+            .map(|(index, offset)| commands.spawn((
+                Name::new(format!("Substation Port {side:?} {index}")),
+                SubstationPort { side },
+                CableConnection { index: index as u32, ..default() },
+                Transform::from_translation(*offset),
+                ChildOf(entity),
+            )).id())
+            .collect()
+    };
+    let incoming = spawn_ports(incoming, SubstationSide::Incoming);
+    let outgoing = spawn_ports(outgoing, SubstationSide::Outgoing);
+    Ok(SpawnedSubstation { entity, incoming, outgoing })
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct SubstationGizmos;
+
+fn substation_gizmos(
+    mut gizmos: Gizmos<SubstationGizmos>,
+    substations: Query<&GlobalTransform, With<Substation>>,
+    ports: Query<(&GlobalTransform, &SubstationPort)>,
+) {
+    for substation_transform in substations {
+        gizmos.cuboid(substation_transform.compute_transform().with_scale(Vec3::splat(SUBSTATION_GIZMO_SIZE)), PURPLE);
+    }
+    for (port_transform, port) in ports {
+        let radius = match port.side {
+            SubstationSide::Incoming => 0.5,
+            SubstationSide::Outgoing => 1.0,
+        };
+        gizmos.sphere(port_transform.to_isometry(), radius, AQUA);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use super::*;
+    use crate::electric_grid::{junctions::*, phases::*, spark_movement::*};
+
+    struct TestGrid {
+        incoming_cable: Entity,
+        high_voltage_cable: Entity,
+        low_voltage_cable: Entity,
+    }
+
+    // one line into a step up substation, two lines out of it rated for different voltages
+    fn spawn_test_grid(world: &mut World) -> TestGrid {
+        let substation = world.spawn(Substation { ratio: 10.0 }).id();
+        let port = |world: &mut World, side| world.spawn((SubstationPort { side }, ChildOf(substation))).id();
+        let (port_in, port_high, port_low) = (port(world, SubstationSide::Incoming), port(world, SubstationSide::Outgoing), port(world, SubstationSide::Outgoing));
+        let tower = world.spawn(CableConnection::default()).id();
+        let (high_end, low_end) = (world.spawn(CableConnection::default()).id(), world.spawn(CableConnection::default()).id());
+
+        let incoming_cable = spawn_generated_cable(world, tower, port_in, vec![Vec3::X * -10.0, Vec3::ZERO]);
+        let high_voltage_cable = spawn_generated_cable(world, port_high, high_end, vec![Vec3::ZERO, Vec3::new(10.0, 0.0, 1.0)]);
+        world.entity_mut(high_voltage_cable).insert(VoltageRating { min: 50_000.0, max: 150_000.0 });
+        // attached the other way around, the spark rides it backwards
+        let low_voltage_cable = spawn_generated_cable(world, low_end, port_low, vec![Vec3::new(10.0, 0.0, -1.0), Vec3::ZERO]);
+        world.entity_mut(low_voltage_cable).insert(VoltageRating { min: 0.0, max: 20_000.0 });
+        TestGrid { incoming_cable, high_voltage_cable, low_voltage_cable }
+    }
+
+    fn choices_at_end_of(world: &mut World, cable: Entity, voltage: f32) -> Vec<JunctionChoice> {
+        let mut spark = Spark::new(cable, 1.0);
+        spark.voltage = voltage;
+        world.run_system_once(move |network: CableNetwork, ends: Query<&EndsAt>| {
+            network.gather_choices(ends.get(cable).unwrap().0, Travel::Forward, &spark, PhaseRule::SamePhase)
+        }).unwrap()
+    }
+
+    // -- basic --
+    // passing through the substation steps the voltage up, and only lines rated for the new voltage are offered
+    #[test]
+    fn test_choices_through_substation() {
+        let mut world = World::new();
+        let grid = spawn_test_grid(&mut world);
+
+        let choices = choices_at_end_of(&mut world, grid.incoming_cable, 10_000.0);
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].cable, grid.high_voltage_cable);
+        assert_eq!(choices[0].travel, Travel::Forward);
+        assert_eq!(choices[0].voltage, 100_000.0);
+
+        let choices = choices_at_end_of(&mut world, grid.incoming_cable, 1_000.0);
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].cable, grid.low_voltage_cable);
+        assert_eq!(choices[0].travel, Travel::Backward);
+        assert_eq!(choices[0].voltage, 10_000.0);
+    }
+
+    // a spark running through a substation ends up on the outgoing line with its new voltage
+    #[test]
+    fn test_spark_passes_substation() {
+        let mut world = World::new();
+        let grid = spawn_test_grid(&mut world);
+        let mut spark = Spark::new(grid.incoming_cable, 1.0);
+        spark.voltage = 10_000.0;
+        spark.dist_along = 12.0;
+        let spark = world.spawn(spark).id();
+
+        world.run_system_once(move |mut sparks: Query<(&mut Spark, &mut Transform)>, network: CableNetwork| {
+            let (mut spark, mut transform) = sparks.get_mut(spark).unwrap();
+            assert!(set_spark_transform_and_dist_along(&mut spark, &mut transform, PhaseRule::SamePhase, &network).is_none());
+        }).unwrap();
+
+        let spark = world.get::<Spark>(spark).unwrap();
+        assert_eq!(spark.connected_to_cable_entity, grid.high_voltage_cable);
+        assert_eq!(spark.voltage, 100_000.0);
+        assert!((spark.dist_along - 2.0).abs() < 0.001);
+    }
+
+    // -- edge cases --
+    // coming back out of the substation the way the spark went in steps the voltage back down
+    #[test]
+    fn test_substation_reversible() {
+        let substation = Substation { ratio: 10.0 };
+        let up = substation.transform_voltage(400.0, SubstationSide::Outgoing);
+        assert_eq!(substation.transform_voltage(up, SubstationSide::Incoming), 400.0);
+    }
+
+    // a ratio that is not a finite positive number spawns nothing
+    #[test]
+    fn test_invalid_ratio() {
+        let mut world = World::new();
+        for ratio in [0.0, -10.0, f32::NAN, f32::INFINITY] {
+            let spawned = spawn_substation(&mut world.commands(), Transform::default(), Substation { ratio }, &[Vec3::X], &[Vec3::NEG_X]);
+            assert!(matches!(spawned, Err(SubstationError::InvalidRatio(_))));
+        }
+        world.flush();
+        assert_eq!(world.query::<&SubstationPort>().iter(&world).len(), 0);
+
+        let spawned = spawn_substation(&mut world.commands(), Transform::default(), Substation { ratio: 10.0 }, &[Vec3::X], &[Vec3::NEG_X]).unwrap();
+        world.flush();
+        assert_eq!(world.get::<Children>(spawned.entity).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,142 @@
+use bevy::{math::cubic_splines::{CubicBezierError, InsufficientDataError}, prelude::*};
+use super::*;
+
+// samples taken per spline segment when measuring its length
+static SAMPLES_PER_SEGMENT: usize = 64;
+
+pub struct TowerRoutePlugin;
+impl Plugin for TowerRoutePlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_observer(use_tower_routes);
+    }
+}
+
+/*
+lays out a line of towers along a spline instead of at hand typed positions.
+towers are spaced evenly along the spline, as close to the target span as possible without any span exceeding the max span.
+once placed, the route turns into a regular TowerSpawner on the same entity, so wiring and parenting work the same way.
+*/
+#[derive(Component)]
+pub struct TowerRoute {
+    pub spline: CubicCurve<Vec3>,
+    // preferred distance between consecutive towers, in metres along the spline
+    pub span: f32,
+    // no span may be longer than this
+    pub max_span: f32,
+    pub scene: Option<Handle<Gltf>>,
+    pub wiring: Wiring,
+    // placement of the towers along the route. with OnGround, the height of the spline above the ground is kept
+    pub placement: Placement,
+}
+
+impl TowerRoute {
+    // a route passing through every control point
+    pub fn catmull_rom(control_points: impl IntoIterator<Item = Vec3>, span: f32, max_span: f32) -> Result<Self, InsufficientDataError> {
+        let spline = CubicCardinalSpline::new_catmull_rom(control_points).to_curve()?;
+        Ok(Self::new(spline, span, max_span))
+    }
+
+    // a route made of bezier segments, each given by its start, two handles and end
+    pub fn bezier(control_points: impl IntoIterator<Item = [Vec3; 4]>, span: f32, max_span: f32) -> Result<Self, CubicBezierError> {
+        let spline = CubicBezier::new(control_points).to_curve()?;
+        Ok(Self::new(spline, span, max_span))
+    }
+
+    pub fn new(spline: CubicCurve<Vec3>, span: f32, max_span: f32) -> Self {
+        TowerRoute { spline, span, max_span, scene: None, wiring: default(), placement: default() }
+    }
+
+    pub fn tower_positions(&self) -> Vec<Vec3> {
+        let samples: Vec<Vec3> = self.spline.iter_positions(self.spline.segments().len() * SAMPLES_PER_SEGMENT).collect();
+        place_along(&samples, self.span, self.max_span)
+    }
+}
+
+// evenly spaced points along a polyline, including both of its ends.
+// the spacing is as close to span as possible while never exceeding max_span.
+fn place_along(samples: &[Vec3], span: f32, max_span: f32) -> Vec<Vec3> {
+    let Some(first) = samples.first() else { return Vec::new() };
+    let arc_lengths: Vec<f32> = std::iter::once(0.0)
+        .chain(samples.windows(2).scan(0.0, |length, pair| {
+            *length += pair[0].distance(pair[1]);
+            Some(*length)
+        }))
+        .collect();
+    let total = *arc_lengths.last().unwrap();
+    if total <= 0.0 || span <= 0.0 { return vec![*first] }
+
+    let min_span_count = match max_span > 0.0 {
+        true => (total / max_span).ceil() as usize,
+        false => 1,
+    };
+    let span_count = ((total / span).round() as usize).max(min_span_count).max(1);
+    let spacing = total / span_count as f32;
+
+    (0..=span_count)
+        .map(|i| {
+            let distance = (i as f32 * spacing).min(total);
+            let index = arc_lengths.partition_point(|&length| length <= distance).clamp(1, samples.len() - 1);
+            let segment_length = arc_lengths[index] - arc_lengths[index - 1];
+            let fraction = match segment_length > 0.0 {
+                true => (distance - arc_lengths[index - 1]) / segment_length,
+                false => 0.0,
+            };
+            samples[index - 1].lerp(samples[index], fraction)
+        })
+        .collect()
+}
+
+fn use_tower_routes(
+    trigger: On<Add, TowerRoute>,
+    mut tower_routes: Query<&mut TowerRoute>,
+    mut commands: Commands,
+) {
+    let Ok(mut route) = tower_routes.get_mut(trigger.entity) else { return };
+    let positions = route.tower_positions();
+    debug!("placing {} towers along route", positions.len());
+    commands.entity(trigger.entity)
+        .insert(TowerSpawner {
+            positions,
+            scene: route.scene.take(),
+            wiring: std::mem::take(&mut route.wiring),
+            placement: route.placement,
+        })
+        .remove::<TowerRoute>();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    fn spacings(positions: &[Vec3]) -> Vec<f32> {
+        positions.windows(2).map(|pair| pair[0].distance(pair[1])).collect()
+    }
+
+    #[test]
+    fn test_place_along_straight() {
+        let positions = place_along(&[Vec3::ZERO, Vec3::X * 100.0], 20.0, 30.0);
+        assert_eq!(positions.len(), 6);
+        assert!((positions[0] - Vec3::ZERO).length() < 0.001);
+        assert!((positions[5] - Vec3::X * 100.0).length() < 0.001);
+        assert!(spacings(&positions).iter().all(|spacing| (spacing - 20.0).abs() < 0.001));
+    }
+
+    // rounding to the nearest span count must never produce spans over the max
+    #[test]
+    fn test_place_along_respects_max_span() {
+        let positions = place_along(&[Vec3::ZERO, Vec3::X * 50.0, Vec3::new(50.0, 0.0, 20.0)], 60.0, 40.0);
+        assert_eq!(positions.len(), 3);
+        assert!(spacings(&positions).iter().all(|spacing| *spacing <= 40.0));
+    }
+
+    #[test]
+    fn test_route_through_control_points() {
+        let route = TowerRoute::catmull_rom([Vec3::ZERO, Vec3::new(100.0, 0.0, 50.0), Vec3::new(200.0, 0.0, 0.0)], 25.0, 35.0).unwrap();
+        let positions = route.tower_positions();
+        assert!((positions[0] - Vec3::ZERO).length() < 0.001);
+        assert!((positions.last().unwrap() - Vec3::new(200.0, 0.0, 0.0)).length() < 0.01);
+        assert!(positions.len() > 8);
+    }
+}
@@ -0,0 +1,670 @@
+use bevy::{asset::{io::Reader, AssetLoader, LoadContext}, image::ImageLoaderSettings, prelude::*};
+use bevy_pretty_text::prelude::*;
+use ron::error::Position;
+use serde::{de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor}, Deserialize};
+use thiserror::Error;
+
+use crate::camera::CameraShot;
+use crate::dialogue::{DialogueAsset, DialogueTrigger, GridChange, TriggerAction, TriggerPolicy, TriggerShape};
+use crate::electric_grid::{spark_movement::SPARK_VOLTAGE, substation::VoltageRating, tower_route::TowerRoute, Placement, Wiring};
+
+static DEFAULT_TOWER_MODEL: &str = "transmission_tower/TRANSMISSION_TOWER.glb";
+
+// -- file format --
+// what a level file looks like on disk. positions and colors are plain tuples so the files stay readable.
+
+#[derive(Deserialize, Debug)]
+pub(super) struct LevelDef {
+    lines: Vec<TowerLineDef>,
+    #[serde(default)]
+    spark: Option<SparkDef>,
+    // texts pushed onto the text queue when the level spawns
+    #[serde(default)]
+    dialogue: Vec<String>,
+    // dialogue script run from its start node when the level spawns
+    #[serde(default)]
+    dialogue_script: Option<String>,
+    #[serde(default)]
+    terrain: Option<TerrainDef>,
+    #[serde(default)]
+    camera_shots: Vec<CameraShotDef>,
+    #[serde(default)]
+    triggers: Vec<TriggerDef>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TowerLineDef {
+    #[serde(default)]
+    name: String,
+    // either explicit tower positions, or a route to place them along
+    #[serde(default)]
+    positions: Vec<(f32, f32, f32)>,
+    #[serde(default)]
+    route: Option<RouteDef>,
+    #[serde(default = "default_tower_model")]
+    model: String,
+    // hang of each span between consecutive towers. empty for the default hang, or a single value for every span
+    #[serde(default)]
+    hang: Vec<f32>,
+    // srgb cable color
+    #[serde(default)]
+    cable_color: Option<(f32, f32, f32)>,
+    // connection indices to wire between towers, all of them if not given
+    #[serde(default)]
+    connections: Option<Vec<u32>>,
+    // stand the towers on the terrain, taking the y of their positions as a height above the ground
+    #[serde(default)]
+    on_ground: bool,
+    // lowest and highest spark voltage the cables accept, any voltage if not given
+    #[serde(default)]
+    voltage: Option<(f32, f32)>,
+}
+
+// towers spaced evenly along a catmull-rom spline through the control points
+#[derive(Deserialize, Debug)]
+struct RouteDef {
+    points: Vec<(f32, f32, f32)>,
+    span: f32,
+    max_span: f32,
+}
+
+impl TowerLineDef {
+    fn tower_positions(&self) -> Vec<Vec3> {
+        match &self.route {
+            Some(route) => TowerRoute::catmull_rom(route.points.iter().copied().map(Vec3::from), route.span, route.max_span)
+                .map(|route| route.tower_positions())
+                .unwrap_or_default(),
+            None => self.positions.iter().copied().map(Vec3::from).collect(),
+        }
+    }
+}
+
+// ground under the level, from a grayscale heightmap image
+#[derive(Deserialize, Debug)]
+struct TerrainDef {
+    heightmap: String,
+    // position of the heightmap's top left corner at black
+    #[serde(default)]
+    origin: (f32, f32, f32),
+    // extent of the terrain on the xz plane, in metres
+    size: (f32, f32),
+    // height of white above the origin, in metres
+    max_height: f32,
+    // how close cables may come to the ground before being reported, the default clearance if not given
+    #[serde(default)]
+    clearance: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct SparkDef {
+    // the spark starts on the cable passing closest to this point
+    start_near: (f32, f32, f32),
+    // metres per second
+    #[serde(default = "default_spark_speed")]
+    speed: f32,
+    // volts
+    #[serde(default = "default_spark_voltage")]
+    voltage: f32,
+    // whether the spark moves with momentum instead of at its speed
+    #[serde(default)]
+    momentum: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct CameraShotDef {
+    // dialogue cues shots by name
+    name: String,
+    position: (f32, f32, f32),
+    look_at: (f32, f32, f32),
+    // orthographic scale, the player's zoom if not given
+    #[serde(default)]
+    scale: Option<f32>,
+    // the shot plays while the spark is inside this box, given by two opposite corners
+    #[serde(default)]
+    region: Option<[(f32, f32, f32); 2]>,
+}
+
+// story told when the player's spark reaches a place along the grid
+#[derive(Deserialize, Debug)]
+struct TriggerDef {
+    // remembers whether a trigger that fires once already did, so unique across all levels
+    id: String,
+    position: (f32, f32, f32),
+    shape: TriggerShapeDef,
+    // texts pushed onto the text queue, or the node of the level's dialogue script to run
+    #[serde(default)]
+    text: Vec<String>,
+    #[serde(default)]
+    node: Option<String>,
+    // show the text in speech bubbles at the position instead of at the top of the screen
+    #[serde(default)]
+    speech: bool,
+    #[serde(default)]
+    policy: TriggerPolicyDef,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+enum TriggerShapeDef {
+    // full size of a box centred on the position
+    Box((f32, f32, f32)),
+    Sphere(f32),
+    // fraction along the cables of the span closest to the position
+    CablePoint(f32),
+    // when cables anywhere lose power or get it back, the position is only where speech is shown
+    Blackout,
+    Restored,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+enum TriggerPolicyDef {
+    #[default]
+    Once,
+    EveryTime,
+    // seconds
+    Cooldown(f32),
+}
+
+fn default_tower_model() -> String { DEFAULT_TOWER_MODEL.to_string() }
+fn default_spark_speed() -> f32 { 10.0 }
+fn default_spark_voltage() -> f32 { SPARK_VOLTAGE }
+
+// -- loaded asset --
+
+#[derive(Asset, TypePath, Debug)]
+pub struct LevelAsset {
+    pub lines: Vec<TowerLine>,
+    pub spark: Option<SparkStart>,
+    pub dialogue: Vec<String>,
+    pub dialogue_script: Option<Handle<DialogueAsset>>,
+    pub terrain: Option<LevelTerrain>,
+    pub camera_shots: Vec<LevelCameraShot>,
+    pub triggers: Vec<LevelTrigger>,
+}
+
+#[derive(Debug)]
+pub struct TowerLine {
+    pub name: String,
+    pub positions: Vec<Vec3>,
+    pub scene: Handle<Gltf>,
+    pub wiring: Wiring,
+    pub placement: Placement,
+}
+
+#[derive(Debug, Clone)]
+pub struct LevelTerrain {
+    pub heightmap: Handle<Image>,
+    pub origin: Vec3,
+    pub size: Vec2,
+    pub max_height: f32,
+    pub clearance: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LevelCameraShot {
+    pub name: String,
+    pub shot: CameraShot,
+    // opposite corners of the shot's region
+    pub region: Option<(Vec3, Vec3)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LevelTrigger {
+    pub position: Vec3,
+    pub trigger: DialogueTrigger,
+}
+
+#[derive(Debug, Clone)]
+pub struct SparkStart {
+    pub start_near: Vec3,
+    pub speed: f32,
+    pub voltage: f32,
+    pub momentum: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum LevelLoadError {
+    #[error("could not read level file: {0}")]
+    Io(#[from] std::io::Error),
+    // the position of syntax errors is part of the ron error message
+    #[error("could not parse level file at {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("level has no tower lines")]
+    NoLines,
+    #[error("tower line {index} ({name:?}) at {position}: {problem}")]
+    InvalidLine { index: usize, name: String, position: Position, problem: String },
+    #[error("spark: {0}")]
+    InvalidSpark(String),
+    #[error("terrain: {0}")]
+    InvalidTerrain(String),
+    #[error("camera shot {name:?}: {problem}")]
+    InvalidCameraShot { name: String, problem: String },
+    #[error("trigger {id:?}: {problem}")]
+    InvalidTrigger { id: String, problem: String },
+}
+
+impl LevelDef {
+    pub(super) fn parse(bytes: &[u8]) -> Result<Self, LevelLoadError> {
+        let level: LevelDef = ron::de::from_bytes(bytes)?;
+        level.validate(bytes)?;
+        Ok(level)
+    }
+
+    // check everything the spawners would otherwise panic on, naming the tower line at fault and where it is in the source
+    fn validate(&self, source: &[u8]) -> Result<(), LevelLoadError> {
+        if self.lines.is_empty() {
+            return Err(LevelLoadError::NoLines);
+        }
+        for (index, line) in self.lines.iter().enumerate() {
+            let invalid = |problem: String| LevelLoadError::InvalidLine { index, name: line.name.clone(), position: line_position(source, index), problem };
+            if let Some(route) = &line.route {
+                if !line.positions.is_empty() {
+                    return Err(invalid("has both positions and a route, give only one".into()));
+                }
+                if route.points.len() < 2 {
+                    return Err(invalid("route needs at least two points".into()));
+                }
+                if let Some(point) = route.points.iter().find(|point| !Vec3::from(**point).is_finite()) {
+                    return Err(invalid(format!("route point {point:?} must be finite")));
+                }
+                if route.span.is_nan() || route.span <= 0.0 || route.max_span.is_nan() || route.max_span < route.span {
+                    return Err(invalid(format!("route span {} must be positive and no longer than max span {}", route.span, route.max_span)));
+                }
+            }
+            let positions = line.tower_positions();
+            if positions.is_empty() {
+                return Err(invalid("has no tower positions".into()));
+            }
+            if let Some(position) = positions.iter().find(|position| !position.is_finite()) {
+                return Err(invalid(format!("tower position {position} must be finite")));
+            }
+            let spans = positions.len() - 1;
+            if line.hang.len() > 1 && line.hang.len() != spans {
+                return Err(invalid(format!("has {} hang values for {} spans, give one per span or a single one for all", line.hang.len(), spans)));
+            }
+            if let Some(hang) = line.hang.iter().find(|hang| !hang.is_finite() || **hang < 0.0) {
+                return Err(invalid(format!("hang {hang} must be finite and not negative")));
+            }
+            for (span, pair) in positions.windows(2).enumerate() {
+                if pair[0].xz() == pair[1].xz() {
+                    return Err(invalid(format!("towers {} and {} stand on the same spot", span, span + 1)));
+                }
+            }
+            if line.model.is_empty() {
+                return Err(invalid("has an empty model path".into()));
+            }
+            if let Some((min, max)) = line.voltage
+                && (min.is_nan() || max.is_nan() || min < 0.0 || max < min) {
+                return Err(invalid(format!("voltage range {min}..{max} must not be negative or empty")));
+            }
+            if line.on_ground && self.terrain.is_none() {
+                return Err(invalid("is placed on the ground, but the level has no terrain".into()));
+            }
+        }
+        if let Some(terrain) = &self.terrain {
+            let invalid = |problem: String| Err(LevelLoadError::InvalidTerrain(problem));
+            if terrain.heightmap.is_empty() {
+                return invalid("has an empty heightmap path".into());
+            }
+            if terrain.size.0.is_nan() || terrain.size.0 <= 0.0 || terrain.size.1.is_nan() || terrain.size.1 <= 0.0 {
+                return invalid(format!("size {:?} must be positive", terrain.size));
+            }
+            if !terrain.max_height.is_finite() {
+                return invalid(format!("max height {} must be finite", terrain.max_height));
+            }
+            if let Some(clearance) = terrain.clearance
+                && (clearance.is_nan() || clearance < 0.0) {
+                return invalid(format!("clearance {clearance} must not be negative"));
+            }
+        }
+        if let Some(spark) = &self.spark
+            && !Vec3::from(spark.start_near).is_finite() {
+            return Err(LevelLoadError::InvalidSpark(format!("start {:?} must be finite", spark.start_near)));
+        }
+        if let Some(spark) = &self.spark
+            && (spark.speed.is_nan() || spark.speed <= 0.0) {
+            return Err(LevelLoadError::InvalidSpark(format!("speed {} must be positive", spark.speed)));
+        }
+        if let Some(spark) = &self.spark
+            && (spark.voltage.is_nan() || spark.voltage <= 0.0) {
+            return Err(LevelLoadError::InvalidSpark(format!("voltage {} must be positive", spark.voltage)));
+        }
+        for shot in &self.camera_shots {
+            let invalid = |problem: &str| LevelLoadError::InvalidCameraShot { name: shot.name.clone(), problem: problem.into() };
+            if shot.position == shot.look_at {
+                return Err(invalid("looks at its own position"));
+            }
+            if shot.scale.is_some_and(|scale| scale.is_nan() || scale <= 0.0) {
+                return Err(invalid("scale must be positive"));
+            }
+            if self.camera_shots.iter().filter(|other| other.name == shot.name).count() > 1 {
+                return Err(invalid("name is used by more than one shot"));
+            }
+        }
+        for trigger in &self.triggers {
+            let invalid = |problem: &str| Err(LevelLoadError::InvalidTrigger { id: trigger.id.clone(), problem: problem.into() });
+            if trigger.id.is_empty() {
+                return invalid("id must not be empty");
+            }
+            if self.triggers.iter().filter(|other| other.id == trigger.id).count() > 1 {
+                return invalid("id is used by more than one trigger");
+            }
+            match (trigger.text.is_empty(), &trigger.node) {
+                (true, None) => return invalid("needs either text or a dialogue node"),
+                (false, Some(_)) => return invalid("cannot have both text and a dialogue node"),
+                (_, Some(_)) if trigger.speech => return invalid("only text can be shown as speech"),
+                (_, Some(_)) if self.dialogue_script.is_none() => return invalid("runs a dialogue node, but the level has no dialogue script"),
+                _ => {},
+            }
+            // texts are shown as pretty text
+            if let Some(error) = trigger.text.iter().find_map(|text| PrettyParser::spans(text).err()) {
+                return Err(LevelLoadError::InvalidTrigger { id: trigger.id.clone(), problem: format!("bad text markup, {error}") });
+            }
+            match trigger.shape {
+                TriggerShapeDef::Box(size) if !Vec3::from(size).cmpgt(Vec3::ZERO).all() => return invalid("box size must be positive"),
+                TriggerShapeDef::Sphere(radius) if radius.is_nan() || radius <= 0.0 => return invalid("radius must be positive"),
+                TriggerShapeDef::CablePoint(t) if !(0.0..=1.0).contains(&t) => return invalid("cable point must be between 0 and 1"),
+                _ => {},
+            }
+            if let TriggerPolicyDef::Cooldown(seconds) = trigger.policy
+                && (seconds.is_nan() || seconds <= 0.0) {
+                return invalid("cooldown must be positive");
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+where the tower line with the given index starts in a level file that parses.
+ron keeps no positions once a value is deserialized, so the file is parsed again, skipping every value but the lines,
+and stopped right in front of the wanted one, where the parser's error position is.
+*/
+fn line_position(source: &[u8], index: usize) -> Position {
+    let start = Position { line: 1, col: 1 };
+    let Ok(mut deserializer) = ron::de::Deserializer::from_bytes(source) else { return start };
+    match (&mut deserializer).deserialize_struct("LevelDef", &[], LinesOf(index)) {
+        Err(stopped) => deserializer.span_error(stopped).position,
+        Ok(()) => start,
+    }
+}
+
+// the level, of which only the lines are looked into
+struct LinesOf(usize);
+
+impl<'de> Visitor<'de> for LinesOf {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a level")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key == "lines" {
+                true => map.next_value_seed(LineAt(self.0))?,
+                false => { map.next_value::<IgnoredAny>()?; },
+            }
+        }
+        Ok(())
+    }
+}
+
+// the lines, skipped up to the one at the index
+struct LineAt(usize);
+
+impl<'de> DeserializeSeed<'de> for LineAt {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LineAt {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a list of tower lines")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        for _ in 0..self.0 {
+            seq.next_element::<IgnoredAny>()?;
+        }
+        seq.next_element_seed(StopHere)?;
+        Ok(())
+    }
+}
+
+// fails as soon as a value is about to be read
+struct StopHere;
+
+impl<'de> DeserializeSeed<'de> for StopHere {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, _deserializer: D) -> Result<(), D::Error> {
+        Err(de::Error::custom("stopped at the tower line"))
+    }
+}
+
+#[derive(Default, TypePath)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = LevelAsset;
+    type Settings = ();
+    type Error = LevelLoadError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let level = LevelDef::parse(&bytes)?;
+
+        let lines = level.lines.into_iter()
+            .map(|line| TowerLine {
+                positions: line.tower_positions(),
+                // tower models are dependencies of the level, so it only counts as loaded once they are
+                scene: load_context.load(line.model),
+                wiring: Wiring {
+                    hangs: line.hang,
+                    cable_color: line.cable_color.map(|(r, g, b)| Color::srgb(r, g, b)),
+                    indices: line.connections,
+                    voltage_rating: line.voltage.map(|(min, max)| VoltageRating { min, max }),
+                },
+                placement: match line.on_ground {
+                    true => Placement::OnGround,
+                    false => Placement::Exact,
+                },
+                name: line.name,
+            })
+            .collect();
+
+        let dialogue_script = level.dialogue_script.map(|path| load_context.load(path));
+        let triggers = level.triggers.into_iter()
+            .map(|trigger| LevelTrigger {
+                position: Vec3::from(trigger.position),
+                trigger: DialogueTrigger {
+                    shape: match trigger.shape {
+                        TriggerShapeDef::Box(size) => TriggerShape::Box { half_extents: Vec3::from(size) / 2.0 },
+                        TriggerShapeDef::Sphere(radius) => TriggerShape::Sphere { radius },
+                        TriggerShapeDef::CablePoint(t) => TriggerShape::CablePoint { t },
+                        TriggerShapeDef::Blackout => TriggerShape::Grid { on: GridChange::Blackout },
+                        TriggerShapeDef::Restored => TriggerShape::Grid { on: GridChange::Restored },
+                    },
+                    // validation made sure there is a script for nodes to be in
+                    action: match (trigger.node, &dialogue_script) {
+                        (Some(node), Some(script)) => TriggerAction::Dialogue { script: script.clone(), node },
+                        _ if trigger.speech => TriggerAction::Speech(trigger.text),
+                        _ => TriggerAction::Text(trigger.text),
+                    },
+                    policy: match trigger.policy {
+                        TriggerPolicyDef::Once => TriggerPolicy::Once,
+                        TriggerPolicyDef::EveryTime => TriggerPolicy::EveryTime,
+                        TriggerPolicyDef::Cooldown(seconds) => TriggerPolicy::Cooldown(seconds),
+                    },
+                    id: trigger.id,
+                },
+            })
+            .collect();
+
+        Ok(LevelAsset {
+            lines,
+            spark: level.spark.map(|spark| SparkStart { start_near: Vec3::from(spark.start_near), speed: spark.speed, voltage: spark.voltage, momentum: spark.momentum }),
+            dialogue: level.dialogue,
+            dialogue_script,
+            terrain: level.terrain.map(|terrain| LevelTerrain {
+                // heights must be read as stored, not converted from srgb
+                heightmap: load_context.loader()
+                    .with_settings(|settings: &mut ImageLoaderSettings| settings.is_srgb = false)
+                    .load(terrain.heightmap),
+                origin: Vec3::from(terrain.origin),
+                size: Vec2::from(terrain.size),
+                max_height: terrain.max_height,
+                clearance: terrain.clearance,
+            }),
+            camera_shots: level.camera_shots.into_iter()
+                .map(|shot| LevelCameraShot {
+                    shot: CameraShot { position: Vec3::from(shot.position), look_at: Vec3::from(shot.look_at), scale: shot.scale },
+                    region: shot.region.map(|[a, b]| (Vec3::from(a), Vec3::from(b))),
+                    name: shot.name,
+                })
+                .collect(),
+            triggers,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the level shipped with the game is always valid
+    #[test]
+    fn test_first_level_valid() {
+        let level = LevelDef::parse(include_bytes!("../../assets/levels/first.level.ron"));
+        assert!(level.is_ok(), "{:?}", level.err());
+    }
+
+    #[test]
+    fn test_syntax_error_has_position() {
+        let error = LevelDef::parse(b"(\n  lines: [\n    (positions: [(0.0, 0.0)]),\n  ],\n)").unwrap_err();
+        assert!(matches!(error, LevelLoadError::Parse(ref spanned) if spanned.position.line == 3), "{error}");
+    }
+
+    // validation errors point at the tower line at fault
+    #[test]
+    fn test_validation_names_line() {
+        let source = r#"(
+            lines: [
+                (name: "ok", positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)]),
+                (name: "bad hang", positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0), (20.0, 0.0, 0.0)], hang: [1.0, 2.0, 3.0]),
+            ],
+        )"#;
+        let error = LevelDef::parse(source.as_bytes()).unwrap_err();
+        assert!(matches!(error, LevelLoadError::InvalidLine { index: 1, ref name, .. } if name == "bad hang"), "{error}");
+        assert!(matches!(error, LevelLoadError::InvalidLine { position: Position { line: 4, col: 17 }, .. }), "{error}");
+
+        let source = "(lines: [(positions: [(0.0, 0.0, 0.0)], hang: [1.0, 2.0])])";
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidLine { position: Position { line: 1, col: 10 }, .. })));
+    }
+
+    #[test]
+    fn test_route_line() {
+        let source = "(lines: [(route: Some((points: [(0.0, 0.0, 0.0), (100.0, 0.0, 40.0), (200.0, 0.0, 0.0)], span: 40.0, max_span: 50.0)))])";
+        let level = LevelDef::parse(source.as_bytes()).unwrap();
+        assert!(level.lines[0].tower_positions().len() > 5);
+
+        let source = "(lines: [(route: Some((points: [(0.0, 0.0, 0.0), (100.0, 0.0, 0.0)], span: 40.0, max_span: 20.0)))])";
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidLine { index: 0, .. })));
+    }
+
+    #[test]
+    fn test_validation_stacked_towers() {
+        let source = "(lines: [(positions: [(0.0, 0.0, 0.0), (0.0, 5.0, 0.0)])])";
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidLine { index: 0, .. })));
+        assert!(matches!(LevelDef::parse(b"(lines: [])"), Err(LevelLoadError::NoLines)));
+    }
+
+    // numbers that are not finite would place towers, cables and sparks nowhere
+    #[test]
+    fn test_validation_not_finite() {
+        let invalid_line = |line: &str| {
+            let source = format!("(lines: [{line}])");
+            matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidLine { index: 0, .. }))
+        };
+        assert!(invalid_line("(positions: [(0.0, 0.0, 0.0), (inf, 0.0, 0.0)])"));
+        assert!(invalid_line("(positions: [(0.0, NaN, 0.0), (10.0, 0.0, 0.0)])"));
+        assert!(invalid_line("(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)], hang: [inf])"));
+        assert!(invalid_line("(route: Some((points: [(0.0, 0.0, 0.0), (-inf, 0.0, 0.0)], span: 40.0, max_span: 50.0)))"));
+
+        let source = "(lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)])], spark: Some((start_near: (0.0, inf, 0.0))))";
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidSpark(_))));
+    }
+
+    #[test]
+    fn test_terrain() {
+        let source = r#"(
+            lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)], on_ground: true)],
+            terrain: Some((heightmap: "terrain/heightmap.png", size: (100.0, 100.0), max_height: 20.0)),
+        )"#;
+        assert!(LevelDef::parse(source.as_bytes()).is_ok());
+
+        let source = "(lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)], on_ground: true)])";
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidLine { index: 0, .. })));
+
+        let source = r#"(
+            lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)])],
+            terrain: Some((heightmap: "terrain/heightmap.png", size: (100.0, 0.0), max_height: 20.0)),
+        )"#;
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidTerrain(_))));
+    }
+
+    #[test]
+    fn test_camera_shots() {
+        let source = r#"(
+            lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)])],
+            camera_shots: [(name: "overview", position: (0.0, 100.0, 50.0), look_at: (0.0, 0.0, 0.0), region: Some(((-10.0, -10.0, -10.0), (10.0, 10.0, 10.0))))],
+        )"#;
+        assert!(LevelDef::parse(source.as_bytes()).is_ok());
+
+        let source = r#"(
+            lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)])],
+            camera_shots: [(name: "stuck", position: (0.0, 10.0, 0.0), look_at: (0.0, 10.0, 0.0))],
+        )"#;
+        assert!(matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidCameraShot { ref name, .. }) if name == "stuck"));
+    }
+
+    #[test]
+    fn test_triggers() {
+        let source = r#"(
+            lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)])],
+            dialogue_script: Some("dialogue/first.dialogue"),
+            triggers: [
+                (id: "tower", position: (0.0, 0.0, 0.0), shape: Sphere(10.0), text: ["hello"], speech: true),
+                (id: "span", position: (5.0, 0.0, 0.0), shape: CablePoint(0.5), node: Some("span"), policy: Cooldown(5.0)),
+                (id: "dark", position: (0.0, 0.0, 0.0), shape: Blackout, text: ["the lights went out"], policy: EveryTime),
+            ],
+        )"#;
+        assert!(LevelDef::parse(source.as_bytes()).is_ok());
+
+        let invalid = |trigger: &str| {
+            let source = format!(r#"(lines: [(positions: [(0.0, 0.0, 0.0), (10.0, 0.0, 0.0)])], triggers: [{trigger}])"#);
+            matches!(LevelDef::parse(source.as_bytes()), Err(LevelLoadError::InvalidTrigger { .. }))
+        };
+        assert!(invalid(r#"(id: "a", position: (0.0, 0.0, 0.0), shape: CablePoint(1.5), text: ["x"])"#));
+        assert!(invalid(r#"(id: "a", position: (0.0, 0.0, 0.0), shape: Sphere(1.0))"#));
+        assert!(invalid(r#"(id: "a", position: (0.0, 0.0, 0.0), shape: Sphere(1.0), node: Some("start"), speech: true)"#));
+        assert!(invalid(r#"(id: "a", position: (0.0, 0.0, 0.0), shape: Sphere(1.0), node: Some("start"))"#));
+        assert!(invalid(r#"(id: "a", position: (0.0, 0.0, 0.0), shape: Sphere(1.0), text: ["x"]), (id: "a", position: (0.0, 0.0, 0.0), shape: Sphere(1.0), text: ["y"])"#));
+        assert!(invalid(r#"(id: "a", position: (0.0, 0.0, 0.0), shape: Sphere(1.0), text: ["fine", "[oops](spark"], speech: true)"#));
+    }
+}
@@ -0,0 +1,191 @@
+use bevy::prelude::*;
+use loader::*;
+
+use crate::{
+    camera::{CameraRegion, CameraRig, CameraShots},
+    dialogue::{DialogueRunner, StartDialogue},
+    electric_grid::{arcing::Arcing, cables::CableSpatialIndex, phases::CablePhase, spark_movement::Spark, spark_physics::SparkPhysics, TowerSpawner},
+    terrain::{ClearanceSettings, Heightfield, TerrainSource},
+    ui::TextQueue,
+};
+
+pub mod loader;
+
+static FIRST_LEVEL: &str = "levels/first.level.ron";
+
+pub struct LevelPlugin;
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_asset::<LevelAsset>()
+        .init_asset_loader::<LevelLoader>()
+        .add_systems(Startup, load_level)
+        .add_systems(Update, (reload_level, spawn_level, spawn_level_spark).chain());
+    }
+}
+
+/*
+the level currently being played. everything spawned from the level file lives under the root entity,
+so the whole level can be despawned at once when the file changes on disk.
+*/
+#[derive(Resource)]
+pub struct CurrentLevel {
+    pub handle: Handle<LevelAsset>,
+    root: Option<Entity>,
+    // the spark waits for its cables to be generated before it can be placed
+    pending_spark: Option<SparkStart>,
+}
+
+// marker for the entity everything in a level is spawned under
+#[derive(Component)]
+pub struct LevelRoot;
+
+fn load_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    commands.insert_resource(CurrentLevel {
+        handle: asset_server.load(FIRST_LEVEL),
+        root: None,
+        pending_spark: None,
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_level(
+    mut commands: Commands,
+    mut level: ResMut<CurrentLevel>,
+    levels: Res<Assets<LevelAsset>>,
+    asset_server: Res<AssetServer>,
+    mut text_queue: ResMut<TextQueue>,
+    terrain_source: Option<Res<TerrainSource>>,
+    heightfield: Option<Res<Heightfield>>,
+    mut camera_shots: ResMut<CameraShots>,
+) {
+    if level.root.is_some() || !asset_server.is_loaded_with_dependencies(&level.handle) { return; }
+    let Some(level_asset) = levels.get(&level.handle) else { return };
+
+    // towers can only be placed on the ground once the terrain is built
+    if let Some(terrain) = &level_asset.terrain {
+        if terrain_source.is_none() {
+            commands.insert_resource(TerrainSource {
+                heightmap: terrain.heightmap.clone(),
+                origin: terrain.origin,
+                size: terrain.size,
+                max_height: terrain.max_height,
+            });
+            commands.insert_resource(terrain.clearance.map_or_else(ClearanceSettings::default, |clearance| ClearanceSettings { clearance }));
+        }
+        if heightfield.is_none() { return; }
+    }
+    debug!("spawning level with {} tower lines", level_asset.lines.len());
+
+    let root = commands.spawn((
+        Name::new("Level"),
+        LevelRoot,
+        Transform::default(),
+        Visibility::default(),
+    )).id();
+
+    for line in &level_asset.lines {
+        commands.spawn((
+            Name::new(format!("Tower Line {}", line.name)),
+            TowerSpawner {
+                positions: line.positions.clone(),
+                scene: Some(line.scene.clone()),
+                wiring: line.wiring.clone(),
+                placement: line.placement,
+            },
+            ChildOf(root),
+        ));
+    }
+
+    camera_shots.0.clear();
+    for level_shot in &level_asset.camera_shots {
+        camera_shots.0.insert(level_shot.name.clone(), level_shot.shot);
+        if let Some((a, b)) = level_shot.region {
+            commands.spawn((
+                Name::new(format!("Camera Region {}", level_shot.name)),
+                CameraRegion { min: a.min(b), max: a.max(b), shot: level_shot.shot },
+                ChildOf(root),
+            ));
+        }
+    }
+
+    for level_trigger in &level_asset.triggers {
+        commands.spawn((
+            Name::new(format!("Trigger {}", level_trigger.trigger.id)),
+            level_trigger.trigger.clone(),
+            Transform::from_translation(level_trigger.position),
+            ChildOf(root),
+        ));
+    }
+
+    for text in &level_asset.dialogue {
+        text_queue.push_text(text);
+    }
+    if let Some(script) = &level_asset.dialogue_script {
+        commands.trigger(StartDialogue { script: script.clone(), node: "start".into() });
+    }
+
+    level.root = Some(root);
+    level.pending_spark = level_asset.spark.clone();
+}
+
+// place the level's spark on the cable closest to its start point, as soon as there are generated cables to choose from
+fn spawn_level_spark(
+    mut commands: Commands,
+    mut level: ResMut<CurrentLevel>,
+    cable_index: Res<CableSpatialIndex>,
+    cable_phases: Query<&CablePhase>,
+) {
+    let (Some(root), Some(spark_start)) = (level.root, level.pending_spark.as_ref()) else { return };
+    let Some(nearest) = cable_index.nearest(spark_start.start_near, f32::INFINITY) else { return };
+
+    let mut spark = Spark::new(nearest.cable, spark_start.speed);
+    spark.dist_along = nearest.along;
+    spark.voltage = spark_start.voltage;
+    if let Ok(cable_phase) = cable_phases.get(nearest.cable) {
+        spark.phase = cable_phase.phase;
+    }
+    let spark_entity = commands.spawn((
+        spark,
+        Transform::from_translation(nearest.position),
+        Arcing::default(),
+        ChildOf(root),
+    )).id();
+    if spark_start.momentum {
+        commands.entity(spark_entity).insert(SparkPhysics::default());
+    }
+    let rig = CameraRig::following(spark_entity);
+    commands.spawn((
+        Name::new("Camera"),
+        rig.desired_transform(nearest.position, |_| None),
+        rig,
+        ChildOf(root),
+    ));
+    level.pending_spark = None;
+}
+
+// respawn the level from scratch whenever its file changes on disk
+fn reload_level(
+    mut commands: Commands,
+    mut asset_events: MessageReader<AssetEvent<LevelAsset>>,
+    mut level: ResMut<CurrentLevel>,
+    mut text_queue: ResMut<TextQueue>,
+    mut dialogue_runner: ResMut<DialogueRunner>,
+) {
+    for event in asset_events.read() {
+        if !event.is_modified(&level.handle) { continue; }
+        info!("level file changed, respawning level");
+        if let Some(root) = level.root.take() {
+            commands.entity(root).despawn();
+        }
+        level.pending_spark = None;
+        text_queue.clear();
+        dialogue_runner.stop();
+        // the new level may have a different terrain, or none
+        commands.remove_resource::<TerrainSource>();
+        commands.remove_resource::<Heightfield>();
+    }
+}
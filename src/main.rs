@@ -1,8 +1,6 @@
-use std::{f32::consts::PI, str::FromStr};
+use std::f32::consts::PI;
 
 use bevy::{
-    camera::ScalingMode,
-    asset::LoadState, 
     color::palettes::css::GREEN, 
     dev_tools::fps_overlay::{FpsOverlayConfig, FpsOverlayPlugin, FrameTimeGraphConfig}, 
     log::LogPlugin, 
@@ -10,12 +8,20 @@ use bevy::{
 };
 use bevy_skein::SkeinPlugin;
 // use bevy_inspector_egui::{bevy_egui::EguiPlugin, quick::WorldInspectorPlugin};
+use actions::*;
+use camera::*;
+use dialogue::*;
 use electric_grid::*;
+use level::*;
+use terrain::*;
 use ui::*;
 
-use crate::electric_grid::{cables::Cable, spark_movement::Spark};
-
+mod actions;
+mod camera;
+mod dialogue;
 mod electric_grid;
+mod level;
+mod terrain;
 mod ui;
 
 
@@ -29,6 +35,7 @@ fn main() {
                 ..Default::default()
             }),
             SkeinPlugin::default(),
+            ActionsPlugin,
             UIPlugin,
             FpsOverlayPlugin {
                 config: FpsOverlayConfig {
@@ -56,11 +63,10 @@ fn main() {
             },
 
         ))
-        .add_plugins(ElectricGridPlugin)
+        .add_plugins((CameraRigPlugin, DialoguePlugin, ElectricGridPlugin, LevelPlugin, TerrainPlugin))
         //.add_plugins(EguiPlugin::default())
         //.add_plugins(WorldInspectorPlugin::new())
         .add_systems(Startup, setup)
-        .add_systems(Update, (spawn_spark, spawn_towers))
         .run();
 }
 
@@ -82,57 +88,6 @@ fn setup(mut commands: Commands) {
     ));
 }
 
-fn spawn_spark(
-    mut commands: Commands, 
-    cables: Query<Entity, With<Cable>>, 
-    mut text_queue: ResMut<TextQueue>,
-    mut used: Local<bool>
-) {
-    if *used { return }
-    // spawn at random first cable
-    if let Some(cable_entity) = cables.iter().next() {
-        commands.spawn((
-            Spark::new(cable_entity, 1.0),
-        )).with_child((
-            Transform::from_translation(Vec3::new(100.0, 30.0, -100.0)).looking_at(Vec3::Y * 20.0, Vec3::Y),
-            Camera3d::default(),
-            Projection::Orthographic(OrthographicProjection {
-                scaling_mode: ScalingMode::WindowSize,
-                scale: 0.15,
-                ..OrthographicProjection::default_3d()
-            }),
-        ));
-        text_queue.push_text("[little spark....|0.2| coming from a place of such violence...|0.2| what does that make you?|1| the conditions of your existence are part of the great fabric humans have woven onto the web of the world.|1| yet, unlike the humans of this world...|0.2| your movement has only a single axis of freedom.\n|2| soar through the power lines, through ceramic containers of transmission towers, through substations that will change your nature.|1| sing your little song of spark and three-phased vibration.\n|2|i hope you are the catalyst of change.|0.2|i love you.|1|](spark)");
-        text_queue.push_text("test");
-        *used = true;
-    }
-}
-
-fn spawn_towers(
-    mut commands: Commands, 
-    tower_scene: If<Res<TowerScene>>,
-    mut loaded: Local<bool>, 
-    server: Res<AssetServer>,
-) {
-    if *loaded { return; }
-    match server.get_load_state(tower_scene.0.0.id()) {
-        Some(LoadState::Loaded) => {
-            commands.spawn(TowerSpawner(
-                vec![
-                    Vec3::new(0.0, 0.0, 0.0),
-                    Vec3::new(50.0, 10.0, 0.0),
-                    Vec3::new(100.0, 20.0, 0.0),
-                    Vec3::new(150.0, 15.0, 0.0),
-                ]
-            ));
-            *loaded = true;
-        },
-        _ => {}
-    }
-    
-}
-
-
 /*
 fn move_camera(accumulated_mouse_motion: Res<AccumulatedMouseMotion>,
     player: Single<&mut Transform, With<Camera>>,
@@ -0,0 +1,397 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    color::palettes::css::{ORANGE_RED, YELLOW},
+    mesh::{Indices, PrimitiveTopology},
+    platform::collections::HashMap,
+    prelude::*
+};
+
+use crate::electric_grid::cables::{Cable, CableGenerated};
+
+static TERRAIN_COLOR: Color = Color::srgb(0.32, 0.42, 0.25);
+// minimum distance in metres cables should keep from the ground, unless set otherwise
+static DEFAULT_CLEARANCE: f32 = 5.0;
+
+pub struct TerrainPlugin;
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<ClearanceSettings>()
+        .init_resource::<ClearanceReport>()
+        .init_gizmo_group::<ClearanceGizmos>()
+        .add_observer(check_generated_cable_clearance)
+        .add_observer(forget_removed_cable_clearance)
+        .add_systems(Update, (build_terrain, recheck_clearance_on_terrain_change, clearance_gizmos).chain());
+    }
+}
+
+/*
+where the terrain comes from. inserting this resource (re)builds the Heightfield resource and the terrain mesh
+once the heightmap image is loaded, and again whenever the image changes on disk.
+the heightmap is a grayscale image seen from above: its left edge lies at origin.x, its top edge at origin.z,
+black pixels are at origin.y and white pixels are max_height metres above that.
+the image should be loaded without srgb conversion, so gray values map linearly to height.
+*/
+#[derive(Resource, Clone, Debug)]
+pub struct TerrainSource {
+    pub heightmap: Handle<Image>,
+    pub origin: Vec3,
+    // extent of the terrain on the xz plane, in metres
+    pub size: Vec2,
+    pub max_height: f32,
+}
+
+// marker for the entity the terrain mesh is rendered on
+#[derive(Component)]
+pub struct TerrainMesh;
+
+/*
+ground height sampled on a regular grid over the xz plane, with bilinear interpolation between samples.
+heights are stored row by row, rows running along +z and samples within a row along +x.
+*/
+#[derive(Resource, Clone, Debug)]
+pub struct Heightfield {
+    origin: Vec3,
+    size: Vec2,
+    resolution: UVec2,
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    // None if there are less than two samples along either axis, or the heights do not fill the grid
+    pub fn new(origin: Vec3, size: Vec2, resolution: UVec2, heights: Vec<f32>) -> Option<Self> {
+        if resolution.x < 2 || resolution.y < 2 || heights.len() != (resolution.x * resolution.y) as usize { return None }
+        if !(size.x > 0.0 && size.y > 0.0) { return None }
+        Some(Heightfield { origin, size, resolution, heights })
+    }
+
+    // one sample per pixel, using the red channel of the image as the height fraction
+    pub fn from_image(image: &Image, origin: Vec3, size: Vec2, max_height: f32) -> Option<Self> {
+        let resolution = image.size();
+        let heights = (0..resolution.y)
+            .flat_map(|y| (0..resolution.x).map(move |x| (x, y)))
+            .map(|(x, y)| image.get_color_at(x, y).map(|color| origin.y + color.to_linear().red * max_height))
+            .collect::<Result<Vec<f32>, _>>()
+            .inspect_err(|error| error!("could not read heightmap: {error}"))
+            .ok()?;
+        Self::new(origin, size, resolution, heights)
+    }
+
+    // ground height at the given position on the xz plane. None outside of the terrain.
+    pub fn height_at(&self, xz: Vec2) -> Option<f32> {
+        let cell = (xz - self.origin.xz()) / self.cell_size();
+        let max_cell = (self.resolution - 1).as_vec2();
+        if cell.is_nan() || cell.cmplt(Vec2::ZERO).any() || cell.cmpgt(max_cell).any() { return None }
+
+        // the last row and column of cells are sampled from their far side so every lookup has four samples
+        let base = cell.floor().min(max_cell - 1.0);
+        let fraction = cell - base;
+        let (x, y) = (base.x as u32, base.y as u32);
+        let top = self.sample(x, y).lerp(self.sample(x + 1, y), fraction.x);
+        let bottom = self.sample(x, y + 1).lerp(self.sample(x + 1, y + 1), fraction.x);
+        Some(top.lerp(bottom, fraction.y))
+    }
+
+    // the given position with its y taken as a height above the ground. positions outside of the terrain are left as they are.
+    pub fn snap_to_ground(&self, pos: Vec3) -> Vec3 {
+        match self.height_at(pos.xz()) {
+            Some(ground) => Vec3::new(pos.x, ground + pos.y, pos.z),
+            None => {
+                warn!("position {pos} is outside of the terrain, it cannot be placed on the ground");
+                pos
+            },
+        }
+    }
+
+    // distance in metres between neighbouring samples along x and z
+    pub fn cell_size(&self) -> Vec2 {
+        self.size / (self.resolution - 1).as_vec2()
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        let cell_size = self.cell_size();
+        let (width, height) = (self.resolution.x, self.resolution.y);
+        let grid = || (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)));
+
+        let positions: Vec<Vec3> = grid()
+            .map(|(x, y)| Vec3::new(self.origin.x + x as f32 * cell_size.x, self.sample(x, y), self.origin.z + y as f32 * cell_size.y))
+            .collect();
+        // central differences, one sided at the edges
+        let normals: Vec<Vec3> = grid()
+            .map(|(x, y)| {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+                let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+                let slope_x = (self.sample(right, y) - self.sample(left, y)) / ((right - left) as f32 * cell_size.x);
+                let slope_z = (self.sample(x, down) - self.sample(x, up)) / ((down - up) as f32 * cell_size.y);
+                Vec3::new(-slope_x, 1.0, -slope_z).normalize()
+            })
+            .collect();
+        let uvs: Vec<Vec2> = grid()
+            .map(|(x, y)| Vec2::new(x as f32 / (width - 1) as f32, y as f32 / (height - 1) as f32))
+            .collect();
+        let indices: Vec<u32> = (0..height - 1)
+            .flat_map(|y| (0..width - 1).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let corner = y * width + x;
+                let (right, below) = (corner + 1, corner + width);
+                // counter clockwise seen from above
+                [corner, below, right, right, below, below + 1]
+            })
+            .collect();
+
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+            .with_inserted_indices(Indices::U32(indices))
+    }
+
+    fn sample(&self, x: u32, y: u32) -> f32 {
+        self.heights[(y * self.resolution.x + x) as usize]
+    }
+}
+
+// (re)build the heightfield and its mesh when the source is set or its image changes
+#[allow(clippy::too_many_arguments)]
+fn build_terrain(
+    mut commands: Commands,
+    source: Option<Res<TerrainSource>>,
+    heightfield: Option<Res<Heightfield>>,
+    mut image_events: MessageReader<AssetEvent<Image>>,
+    images: Res<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain_meshes: Query<Entity, With<TerrainMesh>>,
+) {
+    let image_modified = image_events.read()
+        .any(|event| source.as_ref().is_some_and(|source| event.is_modified(&source.heightmap)));
+
+    let Some(source) = source else {
+        // the terrain was removed
+        if heightfield.is_some() {
+            commands.remove_resource::<Heightfield>();
+        }
+        for entity in terrain_meshes {
+            commands.entity(entity).despawn();
+        }
+        return;
+    };
+    if heightfield.is_some() && !source.is_changed() && !image_modified { return; }
+    let Some(image) = images.get(&source.heightmap) else { return };
+    let Some(new_heightfield) = Heightfield::from_image(image, source.origin, source.size, source.max_height) else {
+        error!("heightmap must be at least 2x2 pixels and the terrain must have a positive size");
+        return;
+    };
+    debug!("building {}x{} terrain", new_heightfield.resolution.x, new_heightfield.resolution.y);
+
+    for entity in terrain_meshes {
+        commands.entity(entity).despawn();
+    }
+    commands.spawn((
+        Name::new("Terrain"),
+        TerrainMesh,
+        Mesh3d(meshes.add(new_heightfield.mesh())),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: TERRAIN_COLOR,
+            perceptual_roughness: 0.9,
+            ..default()
+        })),
+    ));
+    commands.insert_resource(new_heightfield);
+}
+
+// -- cable clearance --
+
+#[derive(Resource, Clone, Debug)]
+pub struct ClearanceSettings {
+    // cables closer than this to the ground, in metres, are reported
+    pub clearance: f32,
+}
+
+impl Default for ClearanceSettings {
+    fn default() -> Self { ClearanceSettings { clearance: DEFAULT_CLEARANCE } }
+}
+
+// a cable that sags too close to the ground, at its lowest point relative to the terrain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClearanceViolation {
+    // where the cable is closest to the ground
+    pub position: Vec3,
+    // height of the cable above the ground there, negative if it goes underground
+    pub height_above_ground: f32,
+}
+
+// every cable that currently sags below the clearance, kept up to date as cables are generated and removed
+#[derive(Resource, Default, Debug)]
+pub struct ClearanceReport {
+    pub violations: HashMap<Entity, ClearanceViolation>,
+}
+
+// lowest point of the cable relative to the ground. segments are walked in steps no longer than a terrain cell,
+// so the terrain cannot poke through between two cable points unnoticed. None if no part of the cable is over the terrain.
+pub fn lowest_clearance(points: &[Vec3], heightfield: &Heightfield) -> Option<ClearanceViolation> {
+    let step = heightfield.cell_size().min_element();
+    let check = |position: Vec3| heightfield.height_at(position.xz())
+        .map(|ground| ClearanceViolation { position, height_above_ground: position.y - ground });
+
+    points.windows(2)
+        .flat_map(|pair| {
+            let steps = (pair[0].distance(pair[1]) / step).ceil().max(1.0) as u32;
+            (0..steps).map(move |i| pair[0].lerp(pair[1], i as f32 / steps as f32))
+        })
+        .chain(points.last().copied())
+        .filter_map(check)
+        .min_by(|a, b| a.height_above_ground.total_cmp(&b.height_above_ground))
+}
+
+fn update_clearance(
+    cable_entity: Entity,
+    cable: &Cable,
+    heightfield: &Heightfield,
+    settings: &ClearanceSettings,
+    report: &mut ClearanceReport,
+) {
+    match lowest_clearance(cable.points(), heightfield).filter(|lowest| lowest.height_above_ground < settings.clearance) {
+        Some(violation) => {
+            if report.violations.insert(cable_entity, violation).is_none() {
+                warn!(
+                    "cable {cable_entity} is {:.2}m above the ground at {}, below the clearance of {}m",
+                    violation.height_above_ground, violation.position, settings.clearance,
+                );
+            }
+        },
+        None => { report.violations.remove(&cable_entity); },
+    }
+}
+
+fn check_generated_cable_clearance(
+    trigger: On<CableGenerated>,
+    cables: Query<&Cable>,
+    heightfield: Option<Res<Heightfield>>,
+    settings: Res<ClearanceSettings>,
+    mut report: ResMut<ClearanceReport>,
+) {
+    let (Some(heightfield), Ok(cable)) = (heightfield, cables.get(trigger.entity)) else { return };
+    update_clearance(trigger.entity, cable, &heightfield, &settings, &mut report);
+}
+
+fn forget_removed_cable_clearance(
+    trigger: On<Remove, Cable>,
+    mut report: ResMut<ClearanceReport>,
+) {
+    report.violations.remove(&trigger.entity);
+}
+
+// cables generated before the terrain was built, or under a terrain that changed, are checked again
+fn recheck_clearance_on_terrain_change(
+    cables: Query<(Entity, &Cable)>,
+    heightfield: Option<Res<Heightfield>>,
+    settings: Res<ClearanceSettings>,
+    mut report: ResMut<ClearanceReport>,
+) {
+    let Some(heightfield) = heightfield else {
+        if !report.violations.is_empty() {
+            report.violations.clear();
+        }
+        return;
+    };
+    if !heightfield.is_changed() && !settings.is_changed() { return; }
+    for (cable_entity, cable) in cables {
+        update_clearance(cable_entity, cable, &heightfield, &settings, &mut report);
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct ClearanceGizmos;
+
+// mark where each offending cable comes too close to the ground
+fn clearance_gizmos(
+    mut gizmos: Gizmos<ClearanceGizmos>,
+    report: Res<ClearanceReport>,
+    heightfield: Option<Res<Heightfield>>,
+) {
+    let Some(heightfield) = heightfield else { return };
+    for violation in report.violations.values() {
+        let ground = heightfield.height_at(violation.position.xz()).unwrap_or(violation.position.y);
+        gizmos.line(violation.position, Vec3::new(violation.position.x, ground, violation.position.z), ORANGE_RED);
+        gizmos.sphere(Isometry3d::from_translation(violation.position), 0.5, YELLOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+
+    // a 3x3 terrain over 20x20 metres, rising along +x
+    fn ramp() -> Heightfield {
+        Heightfield::new(Vec3::new(-10.0, 1.0, -10.0), Vec2::splat(20.0), UVec2::splat(3), vec![
+            1.0, 3.0, 5.0,
+            1.0, 3.0, 5.0,
+            1.0, 3.0, 5.0,
+        ]).unwrap()
+    }
+
+    // -- basic --
+    #[test]
+    fn test_height_at_interpolates() {
+        let terrain = ramp();
+        assert_eq!(terrain.height_at(Vec2::new(-10.0, -10.0)), Some(1.0));
+        assert_eq!(terrain.height_at(Vec2::new(0.0, 3.0)), Some(3.0));
+        assert!((terrain.height_at(Vec2::new(5.0, -2.0)).unwrap() - 4.0).abs() < 0.001);
+        assert_eq!(terrain.height_at(Vec2::new(10.0, 10.0)), Some(5.0));
+        assert_eq!(terrain.height_at(Vec2::new(10.1, 0.0)), None);
+    }
+
+    #[test]
+    fn test_snap_to_ground() {
+        let terrain = ramp();
+        assert_eq!(terrain.snap_to_ground(Vec3::new(0.0, 2.0, 0.0)), Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(terrain.snap_to_ground(Vec3::new(50.0, 2.0, 0.0)), Vec3::new(50.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn test_from_image() {
+        use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+        let image = Image::new(
+            Extent3d { width: 2, height: 2, depth_or_array_layers: 1 },
+            TextureDimension::D2,
+            vec![0, 255, 51, 102],
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::default(),
+        );
+        let terrain = Heightfield::from_image(&image, Vec3::new(0.0, -5.0, 0.0), Vec2::splat(10.0), 50.0).unwrap();
+        assert!((terrain.height_at(Vec2::new(10.0, 0.0)).unwrap() - 45.0).abs() < 0.01);
+        assert!((terrain.height_at(Vec2::new(0.0, 10.0)).unwrap() - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_mesh_covers_terrain() {
+        let mesh = ramp().mesh();
+        assert_eq!(mesh.count_vertices(), 9);
+        assert_eq!(mesh.indices().unwrap().len(), 4 * 6);
+    }
+
+    // a cable sagging into a hill between two of its points is caught
+    #[test]
+    fn test_lowest_clearance() {
+        let hill = Heightfield::new(Vec3::ZERO, Vec2::splat(40.0), UVec2::new(5, 2), vec![
+            0.0, 0.0, 12.0, 0.0, 0.0,
+            0.0, 0.0, 12.0, 0.0, 0.0,
+        ]).unwrap();
+        let cable = [Vec3::new(0.0, 15.0, 5.0), Vec3::new(40.0, 15.0, 5.0)];
+        let lowest = lowest_clearance(&cable, &hill).unwrap();
+        assert!((lowest.height_above_ground - 3.0).abs() < 0.001);
+        assert!((lowest.position.x - 20.0).abs() < 0.001);
+
+        assert!(lowest_clearance(&[Vec3::new(100.0, 0.0, 0.0), Vec3::new(200.0, 0.0, 0.0)], &hill).is_none());
+    }
+
+    // -- edge cases --
+    #[test]
+    fn test_invalid_heightfield() {
+        assert!(Heightfield::new(Vec3::ZERO, Vec2::ONE, UVec2::new(1, 5), vec![0.0; 5]).is_none());
+        assert!(Heightfield::new(Vec3::ZERO, Vec2::ONE, UVec2::splat(2), vec![0.0; 3]).is_none());
+        assert!(Heightfield::new(Vec3::ZERO, Vec2::ZERO, UVec2::splat(2), vec![0.0; 4]).is_none());
+    }
+}
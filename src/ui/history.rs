@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_pretty_text::prelude::*;
+
+use crate::actions::*;
+
+// the oldest texts are forgotten past this many
+static HISTORY_LIMIT: usize = 500;
+// logical pixels per second the backlog scrolls at with the scroll action fully pushed
+static SCROLL_SPEED: f32 = 600.0;
+
+// a text that was shown, as it was written to the text queue
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextHistoryEntry {
+    // with its pretty text markup, so it looks the same when shown again
+    pub text: String,
+    // the style the whole text is shown in, e.g. "spark" for [...](spark)
+    pub style: Option<String>,
+    // seconds since the game started when the text was shown
+    pub time: f32,
+}
+
+// every text shown so far, oldest first
+#[derive(Resource, Default, Debug)]
+pub struct TextHistory {
+    entries: VecDeque<TextHistoryEntry>,
+}
+
+impl TextHistory {
+    pub fn record(&mut self, text: &str, time: f32) {
+        if self.entries.len() >= HISTORY_LIMIT {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TextHistoryEntry { text: text.to_string(), style: markup_style(text), time });
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &TextHistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn last(&self) -> Option<&TextHistoryEntry> {
+        self.entries.back()
+    }
+
+    pub fn with_style<'a>(&'a self, style: &'a str) -> impl Iterator<Item = &'a TextHistoryEntry> {
+        self.entries.iter().filter(move |entry| entry.style.as_deref() == Some(style))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// the style of markup styling the whole text, like [text](style)
+fn markup_style(text: &str) -> Option<String> {
+    let text = text.trim();
+    if !text.starts_with('[') { return None; }
+    let (inner, style) = text.strip_suffix(')')?.rsplit_once("](")?;
+    // more than one piece of styled text
+    if inner.contains("](") { return None; }
+    Some(style.trim().to_string())
+}
+
+// the panel listing the history, while it is open
+#[derive(Component)]
+pub(crate) struct Backlog;
+
+// the backlog action opens and closes the panel, which is rebuilt whenever a text is added while it is open
+pub(crate) fn toggle_backlog(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    history: Res<TextHistory>,
+    backlog: Query<Entity, With<Backlog>>,
+) {
+    match (backlog.single().ok(), actions.just_pressed_any(Action::Backlog)) {
+        (None, true) => spawn_backlog(&mut commands, &history),
+        (Some(entity), true) => commands.entity(entity).despawn(),
+        (Some(entity), false) if history.is_changed() => {
+            commands.entity(entity).despawn();
+            spawn_backlog(&mut commands, &history);
+        },
+        _ => {},
+    }
+}
+
+fn spawn_backlog(commands: &mut Commands, history: &TextHistory) {
+    commands.spawn((
+        Backlog,
+        Node {
+            position_type: PositionType::Absolute,
+            top: percent(0.),
+            bottom: percent(0.),
+            left: percent(0.),
+            right: percent(0.),
+            margin: px(50).all(),
+            padding: px(20).all(),
+            flex_direction: FlexDirection::Column,
+            row_gap: px(12),
+            overflow: Overflow::scroll_y(),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.85)),
+        // start at the newest text, the layout clamps this to the bottom
+        ScrollPosition(Vec2::new(0.0, f32::MAX)),
+        // above the text box
+        GlobalZIndex(1),
+    )).with_children(|parent| {
+        for entry in history.entries() {
+            parent.spawn(Node { column_gap: px(16), ..default() }).with_children(|row| {
+                let seconds = entry.time as u32;
+                row.spawn((
+                    Text::new(format!("{:02}:{:02}", seconds / 60, seconds % 60)),
+                    TextColor(Color::srgb(0.5, 0.5, 0.5)),
+                ));
+                // no typewriter, the text is shown whole right away
+                match PrettyParser::spans(&entry.text) {
+                    Ok(parsed_text) => { row.spawn((parsed_text, TextLayout::new_with_justify(Justify::Left))); },
+                    Err(_) => { row.spawn(Text::new(entry.text.clone())); },
+                }
+            });
+        }
+    });
+}
+
+pub(crate) fn scroll_backlog(
+    actions: Res<Actions>,
+    mut backlog: Query<&mut ScrollPosition, With<Backlog>>,
+    time: Res<Time>,
+) {
+    let scroll = actions.value_any(Action::Scroll);
+    if scroll == 0.0 { return; }
+    for mut position in &mut backlog {
+        position.y = (position.y + scroll * SCROLL_SPEED * time.delta_secs()).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use super::*;
+    use crate::ui::{text_display, TextQueue, TextSettings};
+
+    // -- basic --
+    // texts are recorded once they are shown, with the style they are shown in
+    #[test]
+    fn test_records_shown_text() {
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .init_resource::<Actions>()
+            .init_resource::<TextQueue>()
+            .init_resource::<TextHistory>()
+            .init_resource::<TextSettings>()
+            .add_systems(Update, text_display);
+        let mut text_queue = app.world_mut().resource_mut::<TextQueue>();
+        text_queue.push_text("[little spark....](spark)");
+        text_queue.push_text("plain");
+
+        app.update();
+        let history = app.world().resource::<TextHistory>();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.last().unwrap().text, "[little spark....](spark)");
+        assert_eq!(history.with_style("spark").count(), 1);
+    }
+
+    // -- edge cases --
+    // only markup around the whole text counts as its style, and the oldest texts are dropped past the limit
+    #[test]
+    fn test_style_and_limit() {
+        assert_eq!(markup_style("[a|0.5| b](spark)"), Some("spark".into()));
+        assert_eq!(markup_style("a [b](spark) c"), None);
+        assert_eq!(markup_style("[a](red) [b](spark)"), None);
+        assert_eq!(markup_style("(plain)"), None);
+
+        let mut history = TextHistory::default();
+        for index in 0..HISTORY_LIMIT + 1 {
+            history.record(&index.to_string(), index as f32);
+        }
+        assert_eq!(history.len(), HISTORY_LIMIT);
+        assert_eq!(history.entries().next().unwrap().text, "1");
+    }
+}
@@ -3,39 +3,70 @@ use std::{collections::VecDeque, str::FromStr};
 use bevy::prelude::*;
 use bevy_pretty_text::prelude::*;
 
+use crate::actions::*;
+
+pub use history::TextHistory;
+pub use settings::TextSettings;
+
+mod history;
+mod settings;
+mod speech;
+
 pub struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app
         .init_resource::<TextQueue>()
-        .add_plugins(PrettyTextPlugin)
+        .init_resource::<TextHistory>()
+        .init_resource::<TextSettings>()
+        .add_plugins((PrettyTextPlugin, speech::SpeechPlugin))
         .add_systems(Startup, load_ui)
-        .add_systems(Update, text_display)
+        .add_systems(Update, (text_display, history::toggle_backlog, history::scroll_backlog))
         .add_observer(on_spawn_text)
-        .add_observer(on_typewriter_finished);
+        .add_observer(on_typewriter_finished)
+        .add_observer(skip_all_text);
     }
 }
 
 struct TextQueueItem {
     string: String, // text to display when this item gets popped
+    speaker: Option<Entity>, // shown in a bubble following this entity, at the top of the screen if None
 }
 
 #[derive(Resource, Default)]
 pub struct TextQueue{
     queue: VecDeque<TextQueueItem>,
     is_writing: bool,
+    // the text being shown, and when it was done being written
+    shown: String,
+    finished_at: Option<f32>,
 }
 
 impl TextQueue {
     pub fn push_text(&mut self, text: &str) {
         let Ok(string) = String::from_str(text);
-        self.queue.push_back(TextQueueItem { string });
+        self.queue.push_back(TextQueueItem { string, speaker: None });
+    }
+
+    // text said by an entity, e.g. a spark or a tower, shown in a speech bubble next to it
+    pub fn push_speech(&mut self, text: &str, speaker: Entity) {
+        self.queue.push_back(TextQueueItem { string: text.to_string(), speaker: Some(speaker) });
     }
     
+    // drop every text that has not been displayed yet
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
     fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
 
+    // nothing left to show and nothing being written
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty() && !self.is_writing
+    }
+
     fn pop_text(&mut self) -> Option<TextQueueItem> {
         self.queue.pop_front()
     }
@@ -79,16 +110,27 @@ fn load_ui(
     ));
 }
 
-fn text_display(
+pub(crate) fn text_display(
     mut commands: Commands, 
     mut text_queue: ResMut<TextQueue>, 
-    mut keyboard: ResMut<ButtonInput<KeyCode>>,
+    mut actions: ResMut<Actions>,
+    mut history: ResMut<TextHistory>,
+    settings: Res<TextSettings>,
     text_box: Query<Entity, With<TextBox>>,
+    time: Res<Time>,
 ) {
-    let pressed_advance = keyboard.clear_just_pressed(KeyCode::KeyZ);
+    let fast_forward = actions.pressed_any(Action::FastForward);
+    let auto_advance = !text_queue.is_writing && text_queue.finished_at.is_some_and(|finished_at| {
+        settings.advance_delay(&text_queue.shown).is_some_and(|delay| time.elapsed_secs() - finished_at >= delay)
+    });
+    let pressed_advance = actions.consume_any(Action::Advance) || fast_forward || auto_advance;
 
+    // drop the text being shown and everything after it
+    if actions.consume_any(Action::Skip) {
+        commands.trigger(SkipAllText);
+    }
     // skip text load
-    if pressed_advance {
+    else if pressed_advance {
         match text_box.single() {
             Ok(entity) => {
                 if text_queue.is_writing {
@@ -97,6 +139,7 @@ fn text_display(
                 } else {
                     debug!("clearing text");
                     commands.entity(entity).despawn();
+                    text_queue.finished_at = None;
                 }
             },
             _ => {}
@@ -120,40 +163,85 @@ fn text_display(
         if do_write {
             // advance queue
             let popped = text_queue.pop_text().unwrap();
+            history.record(&popped.string, time.elapsed_secs());
+            text_queue.shown.clone_from(&popped.string);
+            text_queue.finished_at = None;
 
             // spawn text
-            commands.trigger(SpawnText(popped.string));
+            commands.trigger(SpawnText(popped.string, popped.speaker));
             text_queue.is_writing = true;
         }
     }
 }
 
+// drop the text being shown and every text queued after it. they still make it into the history.
+#[derive(Event, Debug)]
+pub struct SkipAllText;
+
+fn skip_all_text(
+    _trigger: On<SkipAllText>,
+    mut commands: Commands,
+    mut text_queue: ResMut<TextQueue>,
+    mut history: ResMut<TextHistory>,
+    text_box: Query<Entity, With<TextBox>>,
+    time: Res<Time>,
+) {
+    debug!("skipping all text");
+    while let Some(skipped) = text_queue.pop_text() {
+        history.record(&skipped.string, time.elapsed_secs());
+    }
+    text_queue.is_writing = false;
+    text_queue.finished_at = None;
+    for entity in &text_box {
+        commands.entity(entity).despawn();
+    }
+}
+
+// the text to show, and who says it if anyone
 #[derive(Event)]
-pub struct SpawnText(pub String);
+pub struct SpawnText(pub String, pub Option<Entity>);
 
 fn on_typewriter_finished(
     _trigger: On<TypewriterFinished>,
     mut text_queue: ResMut<TextQueue>,
+    time: Res<Time>,
 ) {
     debug!("typewriter finished");
     text_queue.is_writing = false;
+    text_queue.finished_at = Some(time.elapsed_secs());
 }
 
 #[derive(Component)]
-struct TextBox;
+pub(crate) struct TextBox;
 
 fn on_spawn_text(
     trigger: On<SpawnText>,
     mut commands: Commands,
+    settings: Res<TextSettings>,
+    mut text_queue: ResMut<TextQueue>,
+    time: Res<Time>,
 ) {
-    let parsed_text = PrettyParser::spans(&trigger.0).unwrap();
+    let layout = TextLayout::new_with_justify(Justify::Left);
+    match PrettyParser::spans(&trigger.0) {
+        Ok(parsed_text) => spawn_text_box(&mut commands, (TextBox, Typewriter::new(settings.chars_per_second), TypewriterIndex::glyph(), layout, parsed_text), trigger.1),
+        // shown whole without its markup, there is nothing for the typewriter to write
+        Err(error) => {
+            warn!("could not parse text {:?}, showing it as it is, {}", trigger.0, error);
+            spawn_text_box(&mut commands, (TextBox, layout, Text::new(trigger.0.clone())), trigger.1);
+            text_queue.is_writing = false;
+            text_queue.finished_at = Some(time.elapsed_secs());
+        },
+    }
+}
+
+fn spawn_text_box(commands: &mut Commands, text_box: impl Bundle, speaker: Option<Entity>) {
+    if let Some(speaker) = speaker {
+        speech::spawn_speech_bubble(commands, text_box, speaker);
+        return;
+    }
     // Text with one section
     commands.spawn((
-        TextBox,
-        Typewriter::new(30.),
-        TypewriterIndex::glyph(),
-        TextLayout::new_with_justify(Justify::Left),
-        parsed_text,
+        text_box,
         // Set the style of the Node itself.
         Node {
             position_type: PositionType::Absolute,
@@ -165,7 +253,6 @@ fn on_spawn_text(
             ..default()
         },
     ));
-    
 }
 
 /* not sure how to get this to work??
@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+
+// when a text that is done being written makes way for the next one by itself
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum AutoAdvance {
+    // only when the player advances
+    #[default]
+    Off,
+    // this many seconds after every text
+    After(f32),
+    // long enough to read the text: the base seconds, plus a second for every so many characters
+    ReadingTime { base: f32, chars_per_second: f32 },
+}
+
+// how texts are written and advanced, for players to set to their liking
+#[derive(Resource, Clone, PartialEq, Debug)]
+pub struct TextSettings {
+    // how fast the typewriter writes
+    pub chars_per_second: f32,
+    pub auto_advance: AutoAdvance,
+}
+
+impl Default for TextSettings {
+    fn default() -> Self {
+        TextSettings { chars_per_second: 30.0, auto_advance: AutoAdvance::Off }
+    }
+}
+
+impl TextSettings {
+    // seconds a written text stays before the next one, None if it stays until the player advances
+    pub fn advance_delay(&self, text: &str) -> Option<f32> {
+        match self.auto_advance {
+            AutoAdvance::Off => None,
+            AutoAdvance::After(seconds) => Some(seconds),
+            AutoAdvance::ReadingTime { base, chars_per_second } => Some(base + readable_chars(text) as f32 / chars_per_second.max(f32::EPSILON)),
+        }
+    }
+}
+
+// characters of the text that are read, leaving out the pretty text markup around them, e.g. [little spark|0.2| hi](spark)
+fn readable_chars(text: &str) -> usize {
+    let mut count = 0;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            // a pause
+            '|' => rest = rest[1..].find('|').map_or("", |end| &rest[end + 2..]),
+            // the style of the text before it
+            ']' if rest[1..].starts_with('(') => rest = rest.find(')').map_or("", |end| &rest[end + 1..]),
+            '[' | ']' => rest = &rest[1..],
+            c => {
+                count += c.is_alphanumeric() as usize;
+                rest = &rest[c.len_utf8()..];
+            },
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+    use super::*;
+    use crate::{actions::Actions, ui::*};
+
+    fn text_app(settings: TextSettings) -> App {
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .init_resource::<Actions>()
+            .init_resource::<TextQueue>()
+            .init_resource::<TextHistory>()
+            .insert_resource(settings)
+            .add_observer(skip_all_text)
+            .add_systems(Update, text_display);
+        app
+    }
+
+    // what the typewriter does once the text box is done writing
+    fn finish_writing(app: &mut App) {
+        app.world_mut().spawn(TextBox);
+        let now = app.world().resource::<Time>().elapsed_secs();
+        let mut text_queue = app.world_mut().resource_mut::<TextQueue>();
+        text_queue.is_writing = false;
+        text_queue.finished_at = Some(now);
+    }
+
+    fn wait(app: &mut App, seconds: f32) {
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(seconds));
+        app.update();
+    }
+
+    // -- basic --
+    // reading time only counts the characters being read
+    #[test]
+    fn test_advance_delay() {
+        let settings = TextSettings { auto_advance: AutoAdvance::ReadingTime { base: 1.0, chars_per_second: 10.0 }, ..default() };
+        assert_eq!(readable_chars("[little spark|0.2| hi](spark)"), 13);
+        assert!(settings.advance_delay("[little spark|0.2| hi](spark)").is_some_and(|delay| (delay - 2.3).abs() < 1e-5));
+        assert_eq!(TextSettings::default().advance_delay("hi"), None);
+    }
+
+    // a written text is cleared after the delay, and the next one shown after it
+    #[test]
+    fn test_auto_advance() {
+        let mut app = text_app(TextSettings { auto_advance: AutoAdvance::After(1.0), ..default() });
+        let mut text_queue = app.world_mut().resource_mut::<TextQueue>();
+        text_queue.push_text("first");
+        text_queue.push_text("second");
+        app.update();
+        finish_writing(&mut app);
+
+        wait(&mut app, 0.5);
+        assert_eq!(app.world_mut().query::<&TextBox>().iter(app.world()).len(), 1);
+        wait(&mut app, 0.6);
+        assert_eq!(app.world_mut().query::<&TextBox>().iter(app.world()).len(), 0);
+        app.update();
+        assert_eq!(app.world().resource::<TextHistory>().last().unwrap().text, "second");
+    }
+
+    // -- edge cases --
+    // skipping drops the text being written and everything queued, which still ends up in the history
+    #[test]
+    fn test_skip_all() {
+        let mut app = text_app(TextSettings::default());
+        let mut text_queue = app.world_mut().resource_mut::<TextQueue>();
+        text_queue.push_text("first");
+        text_queue.push_text("second");
+        text_queue.push_text("third");
+        app.update();
+        app.world_mut().spawn(TextBox);
+
+        app.world_mut().trigger(SkipAllText);
+        app.world_mut().flush();
+        assert!(app.world().resource::<TextQueue>().is_idle());
+        assert_eq!(app.world_mut().query::<&TextBox>().iter(app.world()).len(), 0);
+        assert_eq!(app.world().resource::<TextHistory>().len(), 3);
+    }
+
+    // a text whose markup does not parse is shown as it is, already written
+    #[test]
+    fn test_bad_markup() {
+        let mut app = text_app(TextSettings::default());
+        app.add_observer(on_spawn_text);
+        app.world_mut().resource_mut::<TextQueue>().push_text("[unclosed|");
+        app.update();
+        assert_eq!(app.world_mut().query::<&TextBox>().iter(app.world()).len(), 1);
+        assert!(app.world().resource::<TextQueue>().is_idle());
+    }
+}
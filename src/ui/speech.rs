@@ -0,0 +1,151 @@
+use bevy::{prelude::*, ui::UiSystems};
+
+// how close to the edges of the screen bubbles may come, in logical pixels
+static SCREEN_MARGIN: f32 = 16.0;
+// how far above its speaker a bubble floats
+static BUBBLE_LIFT: f32 = 24.0;
+// size of the square turned into the bubble's pointer
+static TAIL_SIZE: f32 = 12.0;
+static BUBBLE_COLOR: Color = Color::srgba(0.05, 0.05, 0.08, 0.85);
+
+pub(crate) struct SpeechPlugin;
+impl Plugin for SpeechPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_systems(PostUpdate, place_speech_bubbles.after(TransformSystems::Propagate).before(UiSystems::Layout));
+    }
+}
+
+// a text box spoken by an entity, following it around the screen
+#[derive(Component, Clone, Copy, Debug)]
+#[require(Visibility::Hidden)]
+pub struct SpeechBubble {
+    pub speaker: Entity,
+}
+
+// the pointer from a bubble to its speaker, despawned with the bubble
+#[derive(Component)]
+#[relationship(relationship_target = BubbleTail)]
+struct TailOf(Entity);
+
+#[derive(Component)]
+#[relationship_target(relationship = TailOf, linked_spawn)]
+struct BubbleTail(Entity);
+
+// where a bubble of the given size goes to point at a spot on the screen, all in logical pixels from the top left
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct BubblePlacement {
+    top_left: Vec2,
+    // from the bubble's left edge
+    tail_x: f32,
+    // the speaker is too close to the top of the screen for the bubble to float above it
+    below: bool,
+}
+
+fn bubble_placement(anchor: Vec2, size: Vec2, viewport: Vec2) -> BubblePlacement {
+    let max = (viewport - size - SCREEN_MARGIN).max(Vec2::splat(SCREEN_MARGIN));
+    let above = anchor.y - BUBBLE_LIFT - size.y;
+    let below = above < SCREEN_MARGIN;
+    let top = match below {
+        true => anchor.y + BUBBLE_LIFT,
+        false => above,
+    };
+    let top_left = Vec2::new(anchor.x - size.x / 2.0, top).clamp(Vec2::splat(SCREEN_MARGIN), max);
+    // the tail stays on the bubble, pointing as close to the speaker as it can
+    let inset = TAIL_SIZE.min(size.x / 2.0);
+    BubblePlacement { top_left, tail_x: (anchor.x - top_left.x).clamp(inset, size.x - inset), below }
+}
+
+// the bubble of a text that has a speaker, with its tail
+pub(crate) fn spawn_speech_bubble(commands: &mut Commands, text_box: impl Bundle, speaker: Entity) {
+    let bubble = commands.spawn((
+        text_box,
+        SpeechBubble { speaker },
+        Node {
+            position_type: PositionType::Absolute,
+            max_width: px(320),
+            padding: px(10).all(),
+            ..default()
+        },
+        BackgroundColor(BUBBLE_COLOR),
+        BorderRadius::all(px(6)),
+    )).id();
+    commands.spawn((
+        TailOf(bubble),
+        Node {
+            position_type: PositionType::Absolute,
+            width: px(TAIL_SIZE),
+            height: px(TAIL_SIZE),
+            ..default()
+        },
+        UiTransform::from_rotation(Rot2::degrees(45.0)),
+        BackgroundColor(BUBBLE_COLOR),
+        Visibility::Hidden,
+    ));
+}
+
+// put every bubble above where its speaker is seen by the active camera. bubbles whose speaker is gone or behind the camera stay where they were.
+#[allow(clippy::type_complexity)]
+fn place_speech_bubbles(
+    mut bubbles: Query<(&SpeechBubble, &mut Node, &ComputedNode, &mut Visibility, &BubbleTail)>,
+    mut tails: Query<(&mut Node, &mut Visibility), (With<TailOf>, Without<SpeechBubble>)>,
+    speakers: Query<&GlobalTransform>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+) {
+    let Some((camera, camera_transform)) = cameras.iter()
+        .filter(|(camera, _)| camera.is_active)
+        .max_by_key(|(camera, _)| camera.order) else { return };
+    let Some(viewport) = camera.logical_viewport_size() else { return };
+
+    for (bubble, mut node, computed, mut visibility, tail) in &mut bubbles {
+        let Ok(speaker) = speakers.get(bubble.speaker) else { continue };
+        let Ok(anchor) = camera.world_to_viewport(camera_transform, speaker.translation()) else { continue };
+        let size = computed.size() * computed.inverse_scale_factor();
+        let placement = bubble_placement(anchor, size, viewport);
+        node.left = px(placement.top_left.x);
+        node.top = px(placement.top_left.y);
+        *visibility = Visibility::Inherited;
+
+        let Ok((mut tail_node, mut tail_visibility)) = tails.get_mut(tail.0) else { continue };
+        let tail_y = match placement.below {
+            true => placement.top_left.y,
+            false => placement.top_left.y + size.y,
+        };
+        tail_node.left = px(placement.top_left.x + placement.tail_x - TAIL_SIZE / 2.0);
+        tail_node.top = px(tail_y - TAIL_SIZE / 2.0);
+        *tail_visibility = Visibility::Inherited;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static VIEWPORT: Vec2 = Vec2::new(800.0, 600.0);
+    static SIZE: Vec2 = Vec2::new(200.0, 50.0);
+
+    // -- basic --
+    // the bubble floats centred above its speaker, with the tail in its middle
+    #[test]
+    fn test_above_speaker() {
+        let placement = bubble_placement(Vec2::new(400.0, 300.0), SIZE, VIEWPORT);
+        assert_eq!(placement.top_left, Vec2::new(300.0, 300.0 - BUBBLE_LIFT - 50.0));
+        assert_eq!(placement.tail_x, 100.0);
+        assert!(!placement.below);
+    }
+
+    // -- edge cases --
+    // near the edges the bubble stays on screen, its tail pointing towards the speaker, and goes below speakers at the top
+    #[test]
+    fn test_clamped_to_screen() {
+        let placement = bubble_placement(Vec2::new(790.0, 10.0), SIZE, VIEWPORT);
+        assert_eq!(placement.top_left.x, VIEWPORT.x - SIZE.x - SCREEN_MARGIN);
+        assert_eq!(placement.tail_x, SIZE.x - TAIL_SIZE);
+        assert!(placement.below);
+
+        // off screen to the left and below
+        let placement = bubble_placement(Vec2::new(-100.0, 900.0), SIZE, VIEWPORT);
+        assert_eq!(placement.top_left, Vec2::new(SCREEN_MARGIN, VIEWPORT.y - SIZE.y - SCREEN_MARGIN));
+        assert_eq!(placement.tail_x, TAIL_SIZE);
+    }
+}